@@ -0,0 +1,56 @@
+// Compares the streaming parser with the line-by-line, regex-based one it replaced, on the control files
+// in data/ and any listed, separated by colons, in RPKG_BENCH_FILES (say a real Packages index from a
+// mirror, which data/ doesn't have). Run with `cargo bench --features regex-baseline --bench parse`.
+use std::io::BufRead;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rpkg::Packages;
+
+// data/packages.csv goes through load-csv, not these parsers
+const FILES : &[&str] = &["installed-packages"];
+
+fn files() -> Vec<String> {
+    let mut files : Vec<String> = FILES.iter().map(|f| format!("{}/data/{}", env!("CARGO_MANIFEST_DIR"), f)).collect();
+    if let Ok(extra) = std::env::var("RPKG_BENCH_FILES") {
+        files.extend(extra.split(':').filter(|f| !f.is_empty()).map(String::from));
+    }
+    files
+}
+
+fn parse(c: &mut Criterion) {
+    for path in files() {
+        let data = std::fs::read(&path).unwrap_or_else(|e| panic!("can't read {}: {}", path, e));
+        let file = path.rsplit('/').next().unwrap();
+
+        let mut group = c.benchmark_group(format!("{} as a status file", file));
+        group.sample_size(20);
+        group.bench_function("regex", |b| b.iter(|| {
+            let mut packages = Packages::new();
+            packages.parse_installed_lines(&path, data[..].lines());
+            packages
+        }));
+        group.bench_function("streaming", |b| b.iter(|| {
+            let mut packages = Packages::new();
+            packages.parse_installed_reader(&path, &data[..]);
+            packages
+        }));
+        group.finish();
+
+        let mut group = c.benchmark_group(format!("{} as a Packages index", file));
+        group.sample_size(20);
+        group.bench_function("regex", |b| b.iter(|| {
+            let mut packages = Packages::new();
+            packages.parse_packages_lines(&path, data[..].lines());
+            packages
+        }));
+        group.bench_function("streaming", |b| b.iter(|| {
+            let mut packages = Packages::new();
+            packages.parse_packages_reader(&path, &data[..]);
+            packages
+        }));
+        group.finish();
+    }
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
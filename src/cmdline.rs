@@ -0,0 +1,331 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+
+use regex::{Regex, RegexBuilder};
+
+/// A built-in filter that a command's output can be piped into.
+pub enum Filter {
+    /// grep [-v] [-i] <regex>: keeps the lines matching regex, or with -v the others
+    Grep { regex : Regex, invert : bool },
+    /// count: replaces the output with its number of lines
+    Count,
+}
+
+impl Filter {
+    fn parse(args: &[String]) -> Result<Filter, String> {
+        let args : Vec<&str> = args.iter().map(String::as_str).collect();
+        match args[..] {
+            ["count"] => Ok(Filter::Count),
+            ["grep", ref rest @ ..] => {
+                let (mut invert, mut ignore_case) = (false, false);
+                let mut pattern = None;
+                for arg in rest {
+                    match *arg {
+                        "-v" if pattern.is_none() => invert = true,
+                        "-i" if pattern.is_none() => ignore_case = true,
+                        _ if pattern.is_none() => pattern = Some(*arg),
+                        _ => return Err(String::from("syntax: grep [-v] [-i] <regex>")),
+                    }
+                }
+                let pattern = pattern.ok_or_else(|| String::from("syntax: grep [-v] [-i] <regex>"))?;
+                let regex = RegexBuilder::new(pattern).case_insensitive(ignore_case).build()
+                    .map_err(|e| format!("bad regex {}: {}", pattern, e))?;
+                Ok(Filter::Grep { regex, invert })
+            }
+            [] => Err(String::from("empty command in pipe")),
+            [other, ..] => Err(format!("unknown filter {:?}; expected grep or count", other)),
+        }
+    }
+
+    fn apply(&self, output: String) -> String {
+        match self {
+            Filter::Grep { regex, invert } =>
+                output.lines().filter(|l| regex.is_match(l) != *invert).map(|l| format!("{}\n", l)).collect(),
+            Filter::Count => format!("{}\n", output.lines().count()),
+        }
+    }
+}
+
+/// Where `> file` or `>> file` sends a command's output.
+pub struct Redirect {
+    pub file : String,
+    pub append : bool,
+}
+
+/// One command of a line: its words, then the filters it is piped into and where the result goes.
+pub struct CommandLine {
+    pub args : Vec<String>,
+    pub filters : Vec<Filter>,
+    pub redirect : Option<Redirect>,
+}
+
+impl CommandLine {
+    /// True when the command's output has to be captured rather than go straight to stdout.
+    pub fn is_plain(&self) -> bool {
+        self.filters.is_empty() && self.redirect.is_none()
+    }
+}
+
+enum Token {
+    Word(String),
+    Pipe,
+    Redirect { append : bool },
+    Semicolon,
+}
+
+/// Splits line into words like a small shell: whitespace separates words, '...' quotes literally, "..."
+/// quotes with backslash escapes, a backslash outside quotes escapes the next character, and an unquoted
+/// # at the start of a word comments out the rest of the line. Unquoted ;, | and > or >> are operators,
+/// except that inside unquoted parentheses |, > and # are ordinary characters, as in ?depends(a (>= 1)).
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut word = String::new();
+    // a word has started, even if it is empty so far, as in ""
+    let mut in_word = false;
+    // how many unquoted ( are still open
+    let mut depth = 0;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        None => return Err(String::from("unterminated ' quote")),
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        None => return Err(String::from("unterminated \" quote")),
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            None => return Err(String::from("unterminated \" quote")),
+                            Some(c) => word.push(c),
+                        },
+                        Some(c) => word.push(c),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                None => return Err(String::from("trailing \\")),
+                Some(c) => { in_word = true; word.push(c) }
+            },
+            '(' => {
+                depth += 1;
+                in_word = true;
+                word.push('(')
+            }
+            ')' => {
+                // a stray ) doesn't count against a later (
+                if depth > 0 { depth -= 1; }
+                in_word = true;
+                word.push(')')
+            }
+            '#' if !in_word && depth == 0 => break,
+            c if c.is_whitespace() || c == ';' || (depth == 0 && (c == '|' || c == '>')) => {
+                if in_word {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                    in_word = false;
+                }
+                match c {
+                    ';' => tokens.push(Token::Semicolon),
+                    '|' => tokens.push(Token::Pipe),
+                    '>' => {
+                        let append = chars.peek() == Some(&'>');
+                        if append { chars.next(); }
+                        tokens.push(Token::Redirect { append });
+                    }
+                    _ => (),
+                }
+            }
+            c => { in_word = true; word.push(c) }
+        }
+    }
+    if in_word {
+        tokens.push(Token::Word(word));
+    }
+    Ok(tokens)
+}
+
+/// Quotes word so that tokenize gives it back unchanged.
+pub fn quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', "'\\''"))
+}
+
+/// Parses a line into the commands separated by ;, skipping empty ones.
+pub fn parse(line: &str) -> Result<Vec<CommandLine>, String> {
+    let mut commands = vec![];
+    let mut tokens = tokenize(line)?.into_iter().peekable();
+    while tokens.peek().is_some() {
+        // the command, then each filter, as lists of words
+        let mut segments : Vec<Vec<String>> = vec![vec![]];
+        let mut redirect = None;
+        while let Some(token) = tokens.next() {
+            match token {
+                Token::Semicolon => break,
+                Token::Word(_) | Token::Pipe if redirect.is_some() =>
+                    return Err(String::from("output redirection must come last")),
+                Token::Word(w) => segments.last_mut().unwrap().push(w),
+                Token::Pipe => segments.push(vec![]),
+                Token::Redirect { append } => match tokens.next() {
+                    Some(Token::Word(file)) if redirect.is_none() => redirect = Some(Redirect { file, append }),
+                    _ => return Err(String::from("syntax: <cmd> > <file>")),
+                },
+            }
+        }
+        let args = segments.remove(0);
+        if args.is_empty() {
+            if segments.is_empty() && redirect.is_none() { continue; }
+            return Err(String::from("missing command before | or >"));
+        }
+        let filters = segments.iter().map(|s| Filter::parse(s)).collect::<Result<Vec<Filter>, String>>()?;
+        commands.push(CommandLine { args, filters, redirect });
+    }
+    Ok(commands)
+}
+
+/// Points stdout at another file until dropped, which points it back where it was.
+struct StdoutRedirect {
+    // a duplicate of the original stdout
+    saved : libc::c_int,
+}
+
+impl StdoutRedirect {
+    fn to(file: &File) -> Result<StdoutRedirect, String> {
+        std::io::stdout().flush().map_err(|e| format!("can't flush stdout: {}", e))?;
+        let saved = unsafe { libc::dup(libc::STDOUT_FILENO) };
+        if saved < 0 {
+            return Err(format!("can't save stdout: {}", io::Error::last_os_error()));
+        }
+        if unsafe { libc::dup2(file.as_raw_fd(), libc::STDOUT_FILENO) } < 0 {
+            let e = io::Error::last_os_error();
+            unsafe { libc::close(saved) };
+            return Err(format!("can't redirect stdout: {}", e));
+        }
+        Ok(StdoutRedirect { saved })
+    }
+}
+
+impl Drop for StdoutRedirect {
+    fn drop(&mut self) {
+        let _ = std::io::stdout().flush();
+        if unsafe { libc::dup2(self.saved, libc::STDOUT_FILENO) } < 0 {
+            // everything printed from now on would silently go to the capture file
+            eprintln!("can't restore stdout: {}", io::Error::last_os_error());
+            std::process::exit(1);
+        }
+        if unsafe { libc::close(self.saved) } < 0 {
+            eprintln!("can't close saved stdout: {}", io::Error::last_os_error());
+        }
+    }
+}
+
+/// Runs f with stdout going to an anonymous temporary file, and returns f's result with what it printed.
+fn capture_stdout<R, F: FnOnce() -> R>(f: F) -> Result<(R, String), String> {
+    // unnamed, so nothing else can open, replace or symlink it
+    let mut file = tempfile::tempfile().map_err(|e| format!("can't create a temporary file: {}", e))?;
+    let result = {
+        let _redirect = StdoutRedirect::to(&file)?;
+        f()
+    };
+    let mut output = String::new();
+    file.seek(SeekFrom::Start(0)).and_then(|_| file.read_to_string(&mut output))
+        .map_err(|e| format!("can't read back command output: {}", e))?;
+    Ok((result, output))
+}
+
+/// Runs f, which runs command's words, then passes what it printed through command's filters and
+/// prints it or writes it to the redirection target. Returns f's result, or an error about the output.
+pub fn run_captured<R, F: FnOnce() -> R>(command: &CommandLine, f: F) -> Result<R, String> {
+    let (result, output) = capture_stdout(f)?;
+    let output = command.filters.iter().fold(output, |output, filter| filter.apply(output));
+    match &command.redirect {
+        None => print!("{}", output),
+        Some(Redirect { file, append }) => {
+            let mut out = OpenOptions::new().write(true).create(true).append(*append).truncate(!*append).open(file)
+                .map_err(|e| format!("can't write {}: {}", file, e))?;
+            out.write_all(output.as_bytes()).map_err(|e| format!("can't write {}: {}", file, e))?;
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// tokenize's result as strings: words in brackets, operators as themselves.
+    fn tokens(line: &str) -> Vec<String> {
+        tokenize(line).unwrap().into_iter().map(|t| match t {
+            Token::Word(w) => format!("[{}]", w),
+            Token::Pipe => String::from("|"),
+            Token::Redirect { append: false } => String::from(">"),
+            Token::Redirect { append: true } => String::from(">>"),
+            Token::Semicolon => String::from(";"),
+        }).collect()
+    }
+
+    #[test]
+    fn splits_words_and_operators() {
+        assert_eq!(tokens("  info\t0ad  "), ["[info]", "[0ad]"]);
+        assert_eq!(tokens("info 0ad>a;deps 0ad >>a"), ["[info]", "[0ad]", ">", "[a]", ";", "[deps]", "[0ad]", ">>", "[a]"]);
+        assert_eq!(tokens("search x|count"), ["[search]", "[x]", "|", "[count]"]);
+    }
+
+    #[test]
+    fn quotes_and_escapes() {
+        assert_eq!(tokens("a 'b c' \"d \\\"e\\\\\""), ["[a]", "[b c]", "[d \"e\\]"]);
+        assert_eq!(tokens("'it'\\''s' \"\" x\\ y \\;"), ["[it's]", "[]", "[x y]", "[;]"]);
+        assert_eq!(tokens("'a \\b'"), ["[a \\b]"]);
+        assert_eq!(tokens(&quote("it's | > x")), ["[it's | > x]"]);
+    }
+
+    #[test]
+    fn unterminated_quotes_and_escapes_are_errors() {
+        assert_eq!(tokenize("info 'x").err().unwrap(), "unterminated ' quote");
+        assert_eq!(tokenize("info \"x").err().unwrap(), "unterminated \" quote");
+        assert_eq!(tokenize("info \"x\\").err().unwrap(), "unterminated \" quote");
+        assert_eq!(tokenize("info x\\").err().unwrap(), "trailing \\");
+    }
+
+    #[test]
+    fn comments_start_a_word() {
+        assert_eq!(tokens("info 0ad # the game"), ["[info]", "[0ad]"]);
+        assert_eq!(tokens("info a#b"), ["[info]", "[a#b]"]);
+        assert_eq!(tokens("info '#x'"), ["[info]", "[#x]"]);
+    }
+
+    #[test]
+    fn operators_are_literal_inside_parentheses() {
+        assert_eq!(tokens("query ?depends(libc6 (>= 2.34))"), ["[query]", "[?depends(libc6]", "[(>=]", "[2.34))]"]);
+        assert_eq!(tokens("query ?name(a|b) | count"), ["[query]", "[?name(a|b)]", "|", "[count]"]);
+        assert_eq!(tokens("query ?name(#x) > f"), ["[query]", "[?name(#x)]", ">", "[f]"]);
+        // a stray ) doesn't leave operators literal
+        assert_eq!(tokens("info a) > f"), ["[info]", "[a)]", ">", "[f]"]);
+    }
+
+    #[test]
+    fn parses_filters_and_redirects() {
+        let commands = parse("how-to-install 0ad | grep -v ^lib | count >> out; ; info x").unwrap();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].args, ["how-to-install", "0ad"]);
+        assert_eq!(commands[0].filters.len(), 2);
+        let redirect = commands[0].redirect.as_ref().unwrap();
+        assert_eq!((redirect.file.as_str(), redirect.append), ("out", true));
+        assert!(commands[1].is_plain());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse("info x > f | count").err().unwrap(), "output redirection must come last");
+        assert_eq!(parse("info x >").err().unwrap(), "syntax: <cmd> > <file>");
+        assert_eq!(parse("| count").err().unwrap(), "missing command before | or >");
+        assert_eq!(parse("info x | sort").err().unwrap(), "unknown filter \"sort\"; expected grep or count");
+    }
+}
@@ -0,0 +1,188 @@
+use std::borrow::Cow;
+
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+/// What an argument is, for completion.
+#[derive(Clone, Copy)]
+pub enum Arg {
+    Package,
+    File,
+    Command,
+    Choice(&'static [&'static str]),
+    Other,
+}
+
+/// A shell command: process_command dispatches on name, and help, syntax errors and completion
+/// all come from here.
+pub struct Command {
+    pub name : &'static str,
+    pub aliases : &'static [&'static str],
+    pub syntax : &'static str,
+    pub min_args : usize,
+    /// None for any number
+    pub max_args : Option<usize>,
+    /// kinds of the positional arguments; the last one repeats when max_args is None
+    pub args : &'static [Arg],
+    pub help : &'static str,
+}
+
+impl Command {
+    pub fn accepts(&self, n_args: usize) -> bool {
+        n_args >= self.min_args && self.max_args.is_none_or(|max| n_args <= max)
+    }
+
+    fn arg_kind(&self, i: usize) -> Arg {
+        match self.args.get(i) {
+            Some(a) => *a,
+            None if self.max_args.is_none() => self.args.last().copied().unwrap_or(Arg::Other),
+            None => Arg::Other,
+        }
+    }
+}
+
+const fn cmd(name: &'static str, aliases: &'static [&'static str], syntax: &'static str, min_args: usize,
+             max_args: Option<usize>, args: &'static [Arg], help: &'static str) -> Command {
+    Command { name, aliases, syntax, min_args, max_args, args, help }
+}
+
+const PKG : &[Arg] = &[Arg::Package];
+const FILE : &[Arg] = &[Arg::File];
+
+pub const COMMANDS : &[Command] = &[
+    cmd("load-defaults", &["ld"], "", 0, Some(0), &[], "load data/packages.csv and data/installed-packages"),
+    cmd("load-packages", &["lp"], "<pkgfile-name>", 1, Some(1), FILE, "load a Packages file of available packages"),
+    cmd("load-installed", &["li"], "<pkgfile-name>", 1, Some(1), FILE, "load a dpkg status file of installed packages"),
+    cmd("load-csv", &["lc"], "<csvfile-name>", 1, Some(1), FILE, "load name,version,md5sum rows of available packages"),
+    cmd("load-release", &["lr"], "<Release|InRelease>", 1, Some(1), FILE, "check later load-packages against this Release file"),
+    cmd("unload-release", &[], "", 0, Some(0), &[], "stop checking load-packages against a Release file"),
+    cmd("verify-index", &[], "<pkgfile-name>", 1, Some(1), FILE, "check a Packages file against the loaded Release file"),
+    cmd("verify-release", &[], "<InRelease|Release> <keyring> [<Release.gpg>]", 2, Some(3), &[Arg::File, Arg::File, Arg::File],
+        "check the OpenPGP signatures of a Release file"),
+    cmd("load-repo", &[], "<root> <suite> [<component>...]", 2, None, &[Arg::File, Arg::Other, Arg::Other],
+        "load a suite from a Debian mirror tree"),
+    cmd("load-preferences", &[], "<preferences-file>", 1, Some(1), FILE, "load apt_preferences(5) pins"),
+    cmd("save-snapshot", &[], "<file>", 1, Some(1), FILE, "save everything loaded to a binary snapshot"),
+    cmd("load-snapshot", &[], "<file>", 1, Some(1), FILE, "replace everything loaded with a saved snapshot"),
+    cmd("diff", &[], "<left> <right>", 2, Some(2), &[Arg::File, Arg::File],
+        "compare two Packages indexes, status files or snapshots"),
+    cmd("policy", &[], "[<pkg>]", 0, Some(1), PKG, "show a package's candidate versions and priorities, or the loaded pins"),
+    cmd("info", &[], "<pkg>", 1, Some(1), PKG, "show a package's versions, md5sum and dependencies"),
+    cmd("deps", &[], "<pkg>", 1, Some(1), PKG, "show a package's dependencies"),
+    cmd("deps-available", &[], "<pkg>", 1, Some(1), PKG, "show which of a package's dependencies are installed"),
+    cmd("transitive-dep-solution", &[], "<pkg>", 1, Some(1), PKG, "list a package's dependencies, transitively"),
+    cmd("how-to-install", &[], "<pkg>", 1, Some(1), PKG, "list the packages to install along with a package"),
+    cmd("install-order", &[], "<pkg>", 1, Some(1), PKG, "order how-to-install's packages for dpkg"),
+    cmd("source-of", &[], "<pkg>", 1, Some(1), PKG, "show the source package a package is built from"),
+    cmd("binaries-of", &[], "<srcpkg>", 1, Some(1), &[Arg::Other], "list the packages built from a source package"),
+    cmd("source-skew", &[], "", 0, Some(0), &[], "list source packages whose binaries have different versions"),
+    cmd("export-graph", &[], "<pkg|--all> <file> [--format dot|graphml] [--depth N]", 2, Some(6), &[Arg::Package, Arg::File],
+        "write a dependency graph as Graphviz DOT or GraphML"),
+    cmd("graph-stats", &[], "", 0, Some(0), &[], "summarise the dependency graph"),
+    cmd("check", &[], "", 0, Some(0), &[], "check the dependencies of every installed package"),
+    cmd("lint", &[], "<file>", 1, Some(1), FILE, "check a Packages file for mistakes before publishing it"),
+    cmd("installability", &[], "[--jobs N]", 0, Some(2), &[Arg::Choice(&["--jobs"]), Arg::Other],
+        "list available packages that can't be installed"),
+    cmd("search", &[], "[--names-only] [<regex>] [--field <Field>=<value>]...", 1, None, &[Arg::Other],
+        "find packages by name or description, and by field values"),
+    cmd("query", &[], "<pattern>", 1, None, &[Arg::Other], "find packages matching an aptitude-style search pattern"),
+    cmd("set-format", &[], "<text|json>", 1, Some(1), &[Arg::Choice(&["text", "json"])], "print query results as text or JSON"),
+    cmd("set-strict", &[], "<on|off>", 1, Some(1), &[Arg::Choice(&["on", "off"])],
+        "stop loading a file at its first problem instead of skipping the stanza or warning"),
+    cmd("load-errors", &[], "", 0, Some(0), &[], "list the problems found in the files loaded"),
+    cmd("set-server", &[], "<server>", 1, Some(1), &[Arg::Other], "set the server enq-verify asks for md5sums"),
+    cmd("enq-verify", &[], "<pkg> [<version>]", 1, Some(2), PKG, "queue a request for a package's md5sum"),
+    cmd("execute", &[], "", 0, Some(0), &[], "run the queued enq-verify requests"),
+    cmd("output-md5s", &[], "<output-file>", 1, Some(1), FILE, "write the available packages' md5sums as CSV"),
+    cmd("test-version-compare", &[], "<version1> <version2>", 2, Some(2), &[], "compare two Debian version numbers"),
+    cmd("help", &[], "[<cmd>]", 0, Some(1), &[Arg::Command], "list commands, or show one command's syntax"),
+    cmd("quit", &[], "", 0, Some(0), &[], "run the queued requests and quit"),
+];
+
+/// Finds a command by name or alias.
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|c| c.name == name || c.aliases.contains(&name))
+}
+
+pub fn print_help(name: Option<&str>) -> bool {
+    let commands : Vec<&Command> = match name {
+        None => COMMANDS.iter().collect(),
+        Some(name) => match find(name) {
+            None => { println!("no such command {}", name); return false }
+            Some(c) => vec![c]
+        }
+    };
+    for c in commands {
+        let mut names = c.name.to_string();
+        for alias in c.aliases {
+            names.push_str(&format!(" | {}", alias));
+        }
+        println!("{}", format!("{} {}", names, c.syntax).trim_end());
+        println!("    {}", c.help);
+    }
+    true
+}
+
+/// Completes command names and aliases, then each argument according to its kind.
+pub struct ShellHelper {
+    /// sorted, for prefix search; refresh after loading packages
+    pub package_names : Vec<String>,
+    files : FilenameCompleter,
+}
+
+impl ShellHelper {
+    pub fn new() -> ShellHelper {
+        ShellHelper { package_names: vec![], files: FilenameCompleter::new() }
+    }
+
+    fn pairs<'a, I: Iterator<Item=&'a str>>(candidates: I) -> Vec<Pair> {
+        candidates.map(|c| Pair { display: c.to_string(), replacement: c.to_string() }).collect()
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = line.rfind(' ').map_or(0, |i| i + 1);
+        let word = &line[start..];
+        let words : Vec<&str> = line[..start].split_whitespace().collect();
+        if words.is_empty() {
+            let names = COMMANDS.iter().flat_map(|c| std::iter::once(&c.name).chain(c.aliases.iter()));
+            return Ok((start, Self::pairs(names.copied().filter(|n| n.starts_with(word)))));
+        }
+        let command = match find(words[0]) {
+            None => return Ok((start, vec![])),
+            Some(c) => c
+        };
+        match command.arg_kind(words.len() - 1) {
+            Arg::File => self.files.complete(line, pos, ctx),
+            Arg::Package => {
+                let first = self.package_names.partition_point(|n| n.as_str() < word);
+                let matches = self.package_names[first..].iter().take_while(|n| n.starts_with(word));
+                Ok((start, Self::pairs(matches.map(|n| n.as_str()))))
+            }
+            Arg::Command => Ok((start, Self::pairs(COMMANDS.iter().map(|c| c.name).filter(|n| n.starts_with(word))))),
+            Arg::Choice(choices) => Ok((start, Self::pairs(choices.iter().copied().filter(|c| c.starts_with(word))))),
+            Arg::Other => Ok((start, vec![])),
+        }
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Borrowed(hint)
+    }
+}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
@@ -3,6 +3,7 @@ use std::string::ParseError;
 use std::cmp::Ordering::{Less, Equal, Greater};
 use std::str::FromStr;
 
+#[derive(Clone, PartialEq)]
 pub enum VersionRelation {
     StrictlyLess, // <<
     LessOrEqual, // <=
@@ -13,7 +14,7 @@ pub enum VersionRelation {
 
 impl fmt::Display for VersionRelation {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        return match self {
+        match self {
             VersionRelation::StrictlyLess =>    write!(f, "<<"),
             VersionRelation::LessOrEqual =>     write!(f, "<="),
             VersionRelation::Equal =>           write!(f, "="),
@@ -24,22 +25,28 @@ impl fmt::Display for VersionRelation {
 }
 
 impl FromStr for VersionRelation {
-    type Err = ParseError;
+    type Err = String;
 
+    /// Also accepts the deprecated "<" and ">", which mean "<=" and ">="; see is_deprecated_relation.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "<<" => Ok(VersionRelation::StrictlyLess),
-            "<=" => Ok(VersionRelation::LessOrEqual),
+            "<=" | "<" => Ok(VersionRelation::LessOrEqual),
             "=" =>  Ok(VersionRelation::Equal),
-            ">=" => Ok(VersionRelation::GreaterOrEqual),
+            ">=" | ">" => Ok(VersionRelation::GreaterOrEqual),
             ">>" => Ok(VersionRelation::StrictlyGreater),
-            _ => panic!("bad version relation {}", s) // gah this should be a ParseError but I don't know how to do that, accepting PRs
+            _ => Err(format!("bad version relation {:?}", s))
         }
     }
 }
 
+/// Whether s is one of the relations policy no longer allows, "<" and ">".
+pub fn is_deprecated_relation(s: &str) -> bool {
+    s == "<" || s == ">"
+}
+
 
-#[derive(PartialEq,Eq)]
+#[derive(PartialEq,Eq,Clone)]
 pub struct DebianVersionNum {
     epoch : String,
     upstream : String,
@@ -74,23 +81,23 @@ impl FromStr for DebianVersionNum {
     }
 }
 
-impl Ord for DebianVersionNum {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+impl PartialOrd for DebianVersionNum {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-impl PartialOrd for DebianVersionNum {
+impl Ord for DebianVersionNum {
     // https://www.debian.org/doc/debian-policy/ch-controlfields.html#version
     // wow this is painful
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         fn extract_nonnum(s: &str) -> (&str,&str) {
             let first_digit = s.find(|c:char| c.is_ascii_digit());
             match first_digit {
                 None => (s, ""),
                 Some(fd) => {
                     let (nonnum, rest) = s.split_at(fd);
-                    return (nonnum, rest);
+                    (nonnum, rest)
                 }
             }
         }
@@ -100,7 +107,7 @@ impl PartialOrd for DebianVersionNum {
                 None => (s, ""),
                 Some(fd) => {
                     let (num, rest) = s.split_at(fd);
-                    return (num, rest);
+                    (num, rest)
                 }
             }
         }
@@ -123,17 +130,12 @@ impl PartialOrd for DebianVersionNum {
 
         fn debian_nonnum_cmp(s: &str, o: &str) -> std::cmp::Ordering {
             let (d_s, d_o) = (to_debian_chars(s), to_debian_chars(o));
-            for it in d_s.iter().zip(d_o.iter()) {
-                let (c_s, c_o) = it;
+            // the end of a part sorts after a tilde and before everything else: aa~ < aa < aaa
+            for i in 0..d_s.len().max(d_o.len()) {
+                let (c_s, c_o) = (d_s.get(i).copied().unwrap_or(0), d_o.get(i).copied().unwrap_or(0));
                 if c_s < c_o { return Less; }
                 if c_s > c_o { return Greater; }
             }
-            // aa < aaa
-            if s.len() < o.len() { return Less; }
-            // aa~ < aa
-            if s.len() > o.len() && s.ends_with('~') { return Less; }
-            // aaa > aa
-            if s.len() > o.len() { return Greater; }
             Equal
         }
 
@@ -156,8 +158,8 @@ impl PartialOrd for DebianVersionNum {
                 let (other_num, other_rest1) = extract_num(other_rest);
 
                 let (sn_i, on_i) = (
-                    match self_num.parse::<i32>() { Err(_) => 0, Ok(e) => e },
-                    match other_num.parse::<i32>() { Err(_) => 0, Ok(e) => e });
+                    self_num.parse::<i32>().unwrap_or_default(),
+                    other_num.parse::<i32>().unwrap_or_default());
 
                 if sn_i != on_i {
                     return sn_i.partial_cmp(&on_i).unwrap();
@@ -167,21 +169,54 @@ impl PartialOrd for DebianVersionNum {
         }
 
         let (epoch, other_epoch) = (
-            match self.epoch.parse::<i32>() { Err(_) => 0, Ok(e) => e },
-            match other.epoch.parse::<i32>() { Err(_) => 0, Ok(e) => e });
+            self.epoch.parse::<i32>().unwrap_or_default(),
+            other.epoch.parse::<i32>().unwrap_or_default());
         if epoch != other_epoch {
-            return Some(epoch.partial_cmp(&other_epoch).unwrap());
+            return epoch.cmp(&other_epoch);
         }
         let ups = debian_cmp(&self.upstream, &other.upstream);
         if ups != Equal {
-            return Some(ups);
+            return ups;
         }
-        return Some(debian_cmp(&self.debian, &other.debian));
+        debian_cmp(&self.debian, &other.debian)
     }
 }
 
+/// Checks s against the version syntax of Debian policy 5.6.12, [epoch:]upstream_version[-debian_revision].
+/// Parsing as DebianVersionNum accepts anything; this says what's wrong with a version that policy wouldn't.
+pub fn check_version_syntax(s: &str) -> Result<(), String> {
+    let (epoch, rest) = match s.split_once(':') {
+        None => (None, s),
+        Some((e, r)) => (Some(e), r),
+    };
+    if let Some(e) = epoch {
+        if e.is_empty() || !e.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("epoch {:?} isn't a number", e));
+        }
+    }
+    let (upstream, revision) = match rest.rfind('-') {
+        None => (rest, None),
+        Some(d) => (&rest[..d], Some(&rest[d + 1..])),
+    };
+    if !upstream.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(format!("upstream version {:?} doesn't start with a digit", upstream));
+    }
+    if let Some(c) = upstream.chars().find(|c| !(c.is_ascii_alphanumeric() || ".+~-".contains(*c))) {
+        return Err(format!("upstream version {:?} contains {:?}", upstream, c));
+    }
+    if let Some(r) = revision {
+        if r.is_empty() {
+            return Err(String::from("empty Debian revision after \"-\""));
+        }
+        if let Some(c) = r.chars().find(|c| !(c.is_ascii_alphanumeric() || ".+~".contains(*c))) {
+            return Err(format!("Debian revision {:?} contains {:?}", r, c));
+        }
+    }
+    Ok(())
+}
+
 pub fn cmp_debversion_with_op(op:&VersionRelation, first: &DebianVersionNum, second: &DebianVersionNum) -> bool {
-    return match op {
+    match op {
         VersionRelation::StrictlyLess => first < second,
         VersionRelation::LessOrEqual => first <= second,
         VersionRelation::Equal => first == second,
@@ -189,3 +224,333 @@ pub fn cmp_debversion_with_op(op:&VersionRelation, first: &DebianVersionNum, sec
         VersionRelation::StrictlyGreater => first > second
     }
 }
+
+/// One end of a VersionInterval.
+#[derive(Clone, PartialEq)]
+pub enum Bound {
+    Unbounded,
+    Inclusive(DebianVersionNum),
+    Exclusive(DebianVersionNum),
+}
+
+impl Bound {
+    fn version(&self) -> Option<(&DebianVersionNum, bool)> {
+        match self {
+            Bound::Unbounded => None,
+            Bound::Inclusive(v) => Some((v, true)),
+            Bound::Exclusive(v) => Some((v, false)),
+        }
+    }
+}
+
+// orders lower bounds by how early they start
+fn cmp_lower(a: &Bound, b: &Bound) -> std::cmp::Ordering {
+    match (a.version(), b.version()) {
+        (None, None) => Equal,
+        (None, Some(_)) => Less,
+        (Some(_), None) => Greater,
+        (Some((av, ai)), Some((bv, bi))) => av.cmp(bv).then(bi.cmp(&ai)),
+    }
+}
+
+// orders upper bounds by how late they end
+fn cmp_upper(a: &Bound, b: &Bound) -> std::cmp::Ordering {
+    match (a.version(), b.version()) {
+        (None, None) => Equal,
+        (None, Some(_)) => Greater,
+        (Some(_), None) => Less,
+        (Some((av, ai)), Some((bv, bi))) => av.cmp(bv).then(ai.cmp(&bi)),
+    }
+}
+
+// whether an interval ending at upper and one starting at lower overlap or meet
+fn connects(upper: &Bound, lower: &Bound) -> bool {
+    match (upper.version(), lower.version()) {
+        (None, _) | (_, None) => true,
+        (Some((uv, ui)), Some((lv, li))) => match uv.cmp(lv) {
+            Greater => true,
+            Less => false,
+            Equal => ui || li,
+        }
+    }
+}
+
+/// The versions between two bounds.
+#[derive(Clone, PartialEq)]
+pub struct VersionInterval {
+    pub lower : Bound,
+    pub upper : Bound,
+}
+
+impl VersionInterval {
+    pub fn is_empty(&self) -> bool {
+        match (self.lower.version(), self.upper.version()) {
+            (None, _) | (_, None) => false,
+            (Some((lv, li)), Some((uv, ui))) => match lv.cmp(uv) {
+                Greater => true,
+                Less => false,
+                Equal => !(li && ui),
+            }
+        }
+    }
+
+    pub fn contains(&self, v: &DebianVersionNum) -> bool {
+        let above = match self.lower.version() {
+            None => true,
+            Some((lv, inclusive)) => if inclusive { v >= lv } else { v > lv },
+        };
+        let below = match self.upper.version() {
+            None => true,
+            Some((uv, inclusive)) => if inclusive { v <= uv } else { v < uv },
+        };
+        above && below
+    }
+
+    fn intersection(&self, other: &VersionInterval) -> VersionInterval {
+        let lower = if cmp_lower(&self.lower, &other.lower) == Greater { &self.lower } else { &other.lower };
+        let upper = if cmp_upper(&self.upper, &other.upper) == Less { &self.upper } else { &other.upper };
+        VersionInterval { lower: lower.clone(), upper: upper.clone() }
+    }
+}
+
+impl fmt::Display for VersionInterval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.lower, &self.upper) {
+            (Bound::Unbounded, Bound::Unbounded) => write!(f, "any"),
+            (Bound::Inclusive(l), Bound::Inclusive(u)) if l == u => write!(f, "= {}", l),
+            (lower, upper) => {
+                let lower = match lower {
+                    Bound::Unbounded => None,
+                    Bound::Inclusive(v) => Some(format!(">= {}", v)),
+                    Bound::Exclusive(v) => Some(format!(">> {}", v)),
+                };
+                let upper = match upper {
+                    Bound::Unbounded => None,
+                    Bound::Inclusive(v) => Some(format!("<= {}", v)),
+                    Bound::Exclusive(v) => Some(format!("<< {}", v)),
+                };
+                let parts : Vec<String> = lower.into_iter().chain(upper).collect();
+                write!(f, "{}", parts.join(", "))
+            }
+        }
+    }
+}
+
+/// A set of versions, such as the versions allowed by several constraints on the same package:
+/// a union of disjoint intervals, kept sorted.
+#[derive(Clone, PartialEq)]
+pub struct VersionRange {
+    intervals : Vec<VersionInterval>,
+}
+
+impl VersionRange {
+    /// Every version.
+    pub fn any() -> VersionRange {
+        VersionRange { intervals: vec![VersionInterval { lower: Bound::Unbounded, upper: Bound::Unbounded }] }
+    }
+
+    /// No version at all.
+    pub fn none() -> VersionRange {
+        VersionRange { intervals: vec![] }
+    }
+
+    /// The versions v for which "v op version" holds, as in a dependency "foo (op version)".
+    pub fn from_relation(op: &VersionRelation, version: &DebianVersionNum) -> VersionRange {
+        let v = || version.clone();
+        let (lower, upper) = match op {
+            VersionRelation::StrictlyLess => (Bound::Unbounded, Bound::Exclusive(v())),
+            VersionRelation::LessOrEqual => (Bound::Unbounded, Bound::Inclusive(v())),
+            VersionRelation::Equal => (Bound::Inclusive(v()), Bound::Inclusive(v())),
+            VersionRelation::GreaterOrEqual => (Bound::Inclusive(v()), Bound::Unbounded),
+            VersionRelation::StrictlyGreater => (Bound::Exclusive(v()), Bound::Unbounded),
+        };
+        VersionRange { intervals: vec![VersionInterval { lower, upper }] }
+    }
+
+    pub fn intervals(&self) -> &[VersionInterval] {
+        &self.intervals
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    pub fn contains(&self, v: &DebianVersionNum) -> bool {
+        self.intervals.iter().any(|i| i.contains(v))
+    }
+
+    pub fn intersection(&self, other: &VersionRange) -> VersionRange {
+        let mut intervals = vec![];
+        for a in &self.intervals {
+            for b in &other.intervals {
+                let i = a.intersection(b);
+                if !i.is_empty() {
+                    intervals.push(i);
+                }
+            }
+        }
+        VersionRange::normalized(intervals)
+    }
+
+    pub fn union(&self, other: &VersionRange) -> VersionRange {
+        VersionRange::normalized(self.intervals.iter().chain(&other.intervals).cloned().collect())
+    }
+
+    // sorts non-empty intervals and merges the ones that overlap or meet
+    fn normalized(mut intervals: Vec<VersionInterval>) -> VersionRange {
+        intervals.sort_by(|a, b| cmp_lower(&a.lower, &b.lower));
+        let mut merged : Vec<VersionInterval> = vec![];
+        for i in intervals {
+            match merged.last_mut() {
+                Some(last) if connects(&last.upper, &i.lower) => {
+                    if cmp_upper(&i.upper, &last.upper) == Greater {
+                        last.upper = i.upper;
+                    }
+                }
+                _ => merged.push(i),
+            }
+        }
+        VersionRange { intervals: merged }
+    }
+}
+
+impl fmt::Display for VersionRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.intervals.is_empty() {
+            return write!(f, "none");
+        }
+        let parts : Vec<String> = self.intervals.iter().map(|i| i.to_string()).collect();
+        write!(f, "{}", parts.join(" | "))
+    }
+}
+
+impl FromStr for VersionRange {
+    type Err = String;
+
+    /// Parses constraints as written in a dependency, with or without parentheses: "(>= 1.2), (<< 2.0)" or
+    /// ">= 1.2, << 2.0". Commas intersect and "|" takes the union; "any" or "*" stands for every version.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut range = VersionRange::none();
+        for alternative in s.split('|') {
+            let mut allowed = VersionRange::any();
+            for constraint in alternative.split(',') {
+                let c = constraint.trim();
+                let c = c.strip_prefix('(').and_then(|c| c.strip_suffix(')')).unwrap_or(c).trim();
+                if c == "any" || c == "*" {
+                    continue;
+                }
+                let op_len = c.find(|ch: char| !"<=>".contains(ch)).unwrap_or(c.len());
+                let op = c[..op_len].parse::<VersionRelation>()
+                    .map_err(|_| format!("bad version constraint {:?}", constraint.trim()))?;
+                let version = c[op_len..].trim();
+                if version.is_empty() || version.contains(char::is_whitespace) {
+                    return Err(format!("bad version constraint {:?}", constraint.trim()));
+                }
+                let version = version.parse::<DebianVersionNum>().unwrap();
+                allowed = allowed.intersection(&VersionRange::from_relation(&op, &version));
+            }
+            range = range.union(&allowed);
+        }
+        Ok(range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(s: &str) -> VersionRange {
+        s.parse().unwrap()
+    }
+
+    fn v(s: &str) -> DebianVersionNum {
+        s.parse().unwrap()
+    }
+
+    // each version sorts before the next
+    fn assert_ascending(versions: &[&str]) {
+        for pair in versions.windows(2) {
+            assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+            assert!(v(pair[1]) > v(pair[0]), "{} > {}", pair[1], pair[0]);
+        }
+    }
+
+    #[test]
+    fn tilde_sorts_before_everything() {
+        assert_ascending(&["1.0~~", "1.0~~a", "1.0~", "1.0~rc1", "1.0", "1.0a"]);
+        assert_ascending(&["3.1~", "3.1", "3.1-3"]);
+        assert!(v("1.0~rc1") == v("1.0~rc1"));
+    }
+
+    #[test]
+    fn end_of_part_sorts_before_letters_and_symbols() {
+        assert_ascending(&["1.0", "1.0a", "1.0aa"]);
+        assert_ascending(&["1.0", "1.0+b1"]);
+        assert_ascending(&["1.0", "1.0.1"]);
+        assert_ascending(&["1.0-1", "1.0-1build1"]);
+    }
+
+    #[test]
+    fn letters_sort_before_non_letters() {
+        assert_ascending(&["1.0A", "1.0Z", "1.0a", "1.0z", "1.0+", "1.0."]);
+        // a hyphen is part of the upstream version only if there's a revision after it
+        assert_ascending(&["1.0z-1", "1.0+x-1", "1.0-x-1", "1.0.x-1"]);
+        assert_ascending(&["1.0z", "1.0+a"]);
+        assert_ascending(&["1.0a1", "1.0+1"]);
+    }
+
+    #[test]
+    fn touching_inclusive_ends_merge() {
+        assert_eq!(range("<= 1.0 | >= 1.0").to_string(), "any");
+        assert_eq!(range("<< 1.0 | >= 1.0").to_string(), "any");
+        assert_eq!(range(">= 1.0, <= 1.0").to_string(), "= 1.0");
+        assert_eq!(range(">= 1.0, <= 2.0 | >= 2.0, << 3.0").to_string(), ">= 1.0, << 3.0");
+    }
+
+    #[test]
+    fn touching_exclusive_ends_stay_apart() {
+        let r = range("<< 1.0 | >> 1.0");
+        assert_eq!(r.to_string(), "<< 1.0 | >> 1.0");
+        assert!(!r.contains(&v("1.0")));
+        assert!(r.contains(&v("0.9")) && r.contains(&v("1.0-1")));
+        assert!(range(">= 1.0, << 1.0").is_empty());
+        assert!(range(">> 1.0, <= 1.0").is_empty());
+    }
+
+    #[test]
+    fn equal_against_strictly_greater() {
+        let (eq, gt) = (range("= 1.0"), range(">> 1.0"));
+        assert!(eq.intersection(&gt).is_empty());
+        assert_eq!(eq.union(&gt).to_string(), ">= 1.0");
+        assert_eq!(range("= 1.0 | >> 1.0").to_string(), ">= 1.0");
+        assert!(range("= 1.0, >= 1.0").contains(&v("1.0")));
+    }
+
+    #[test]
+    fn empty_results() {
+        let r = range(">> 2.0, << 1.0");
+        assert!(r.is_empty());
+        assert_eq!(r.to_string(), "none");
+        assert!(!r.contains(&v("1.5")));
+        assert!(r.intersection(&VersionRange::any()).is_empty());
+        assert_eq!(r.union(&range("= 1.0")).to_string(), "= 1.0");
+        assert!(VersionRange::none().union(&VersionRange::none()).is_empty());
+    }
+
+    #[test]
+    fn from_str_with_alternatives_and_commas() {
+        let r = range("(>= 1.2), (<< 2.0) | = 3.0");
+        assert_eq!(r.to_string(), ">= 1.2, << 2.0 | = 3.0");
+        assert!(r == range(">= 1.2, << 2.0 | (= 3.0)"));
+        assert!(r.contains(&v("1.2")) && r.contains(&v("1.9")) && r.contains(&v("3.0")));
+        assert!(!r.contains(&v("2.0")) && !r.contains(&v("2.5")));
+        assert_eq!(range("any").to_string(), "any");
+        assert_eq!(range("* | = 1.0").to_string(), "any");
+        assert_eq!(range(">= 1.0, any").to_string(), ">= 1.0");
+        // the deprecated operators mean <= and >=
+        assert_eq!(range("< 1.0 | > 2.0").to_string(), "<= 1.0 | >= 2.0");
+        for bad in ["", "=> 1.0", ">=", ">= 1.0 2.0", ">= 1.0,", "1.0"] {
+            assert!(bad.parse::<VersionRange>().is_err(), "{:?} parsed", bad);
+        }
+    }
+}
@@ -1 +1,5 @@
 pub mod debversion;
+pub mod openpgp;
+pub mod packages;
+
+pub use packages::Packages;
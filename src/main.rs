@@ -1,58 +1,135 @@
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
+use itertools::Itertools;
+
 use rpkg::debversion;
-use crate::packages::Packages;
+use rpkg::Packages;
+use rpkg::packages::graph::GraphFormat;
+use rpkg::packages::output::OutputFormat;
+use rpkg::packages::search::SearchQuery;
+use crate::commands::ShellHelper;
+
+mod cmdline;
+mod commands;
 
-mod packages;
+#[derive(PartialEq)]
+enum Status {
+    Ok,
+    // the command printed an error: bad syntax, no such package, unreadable file, ...
+    Failed,
+    Quit,
+}
 
-fn check_syntax(n: usize, cmd_fragments:&Vec<&str>, arg: &str) -> bool {
-    let cmd : &str = &cmd_fragments.get(0).unwrap();
-    if cmd_fragments.len() != n {
-        println!("syntax: {} {}", cmd, arg);
-        return false
+/// Tokenizes cmdline and runs its commands in order, stopping at the first that fails or quits.
+fn process_command(state: &mut Packages, cmdline: &str) -> Status {
+    let commands = match cmdline::parse(cmdline) {
+        Err(e) => { eprintln!("{}", e); return Status::Failed }
+        Ok(commands) => commands
+    };
+    for command in commands {
+        let cmd_fragments : Vec<&str> = command.args.iter().map(String::as_str).collect();
+        let status = if command.is_plain() {
+            run_command(state, cmd_fragments)
+        } else {
+            match cmdline::run_captured(&command, || run_command(state, cmd_fragments)) {
+                Err(e) => { eprintln!("{}", e); Status::Failed }
+                Ok(status) => status
+            }
+        };
+        if status != Status::Ok { return status }
     }
-    return true
+    Status::Ok
 }
 
-fn process_command(state: &mut Packages, cmdline: &str) -> bool {
-    let cmd_fragments: Vec<&str> = cmdline.split(" ").collect();
-    if cmdline.is_empty() { return false }
-    let cmd : &str = &cmd_fragments.get(0).unwrap();
-    match cmd {
+fn run_command(state: &mut Packages, cmd_fragments: Vec<&str>) -> Status {
+    let cmd : &str = cmd_fragments.first().unwrap();
+    let spec = match commands::find(cmd) {
+        None => {
+            eprintln!("couldn't understand cmd {:?}", cmd);
+            return Status::Failed
+        }
+        Some(spec) => spec
+    };
+    if !spec.accepts(cmd_fragments.len() - 1) {
+        eprintln!("syntax: {} {}", cmd, spec.syntax);
+        return Status::Failed
+    }
+    match spec.name {
         "quit" => { 
-            return true 
+            return Status::Quit
         },
-        "load-csv" | "lc" => {
-            if !check_syntax(2, &cmd_fragments, "<csvfile-name>") { return false; }
+        "load-csv" => {
             let arg = cmd_fragments.get(1).unwrap();
             state.parse_csv(arg)
         }
         // parsers.rs
-        "load-packages" | "lp" => {
-            if !check_syntax(2, &cmd_fragments, "<pkgfile-name>") { return false; }
+        "load-packages" => {
             let arg = cmd_fragments.get(1).unwrap();
             state.parse_packages(arg)
         }
-        "load-installed" | "li" => {
-            if !check_syntax(2, &cmd_fragments, "<pkgfile-name>") { return false; }
+        "load-installed" => {
             let arg = cmd_fragments.get(1).unwrap();
             state.parse_installed(arg)
         }
-        // convenience function, also depends on parsers.rs
-        "load-defaults" | "ld" => {
-            state.parse_packages("data/mirror.csclub.uwaterloo.ca_debian_dists_sid_main_binary-amd64_Packages");
+        // release.rs
+        "load-release" => {
+            let arg = cmd_fragments.get(1).unwrap();
+            state.load_release(arg)
+        }
+        "unload-release" => {
+            state.unload_release();
+        }
+        "verify-index" => {
+            let arg = cmd_fragments.get(1).unwrap();
+            state.print_verify_index(arg)
+        }
+        "verify-release" => {
+            let release = cmd_fragments.get(1).unwrap();
+            let keyring = cmd_fragments.get(2).unwrap();
+            state.verify_release(release, keyring, cmd_fragments.get(3).copied())
+        }
+        // repo.rs
+        "load-repo" => {
+            let root = cmd_fragments.get(1).unwrap();
+            let suite = cmd_fragments.get(2).unwrap();
+            state.load_repo(root, suite, &cmd_fragments[3..])
+        }
+        // preferences.rs
+        "load-preferences" => {
+            let arg = cmd_fragments.get(1).unwrap();
+            state.load_preferences(arg)
+        }
+        "policy" => {
+            match cmd_fragments.get(1) {
+                None => state.print_preferences(),
+                Some(pkg) => state.print_policy(pkg)
+            }
+        }
+        // snapshot.rs
+        "save-snapshot" => {
+            state.save_snapshot(cmd_fragments[1])
+        }
+        "load-snapshot" => {
+            state.load_snapshot(cmd_fragments[1])
+        }
+        // diff.rs
+        "diff" => {
+            state.print_diff(cmd_fragments[1], cmd_fragments[2])
+        }
+        // convenience function, also depends on parsers.rs: what data/ ships with
+        "load-defaults" => {
+            state.parse_csv("data/packages.csv");
+            if state.take_failure() { return Status::Failed }
             state.parse_installed("data/installed-packages")
         }
 
         "info" => {
-            if !check_syntax(2, &cmd_fragments, "<pkg>") { return false; }
             let pkg = cmd_fragments.get(1).unwrap();
             state.print_info(pkg)
         }
         "deps" => {
             // test: deps 0ad
-            if !check_syntax(2, &cmd_fragments, "<pkg>") { return false; }
             let pkg = cmd_fragments.get(1).unwrap();
             state.print_deps(pkg)
         }
@@ -60,7 +137,6 @@ fn process_command(state: &mut Packages, cmdline: &str) -> bool {
         // deps-available.rs
         "deps-available" => {
             // test: deps-available 3depict
-            if !check_syntax(2, &cmd_fragments, "<pkg>") { return false; }
             let pkg = cmd_fragments.get(1).unwrap();
             state.deps_available(pkg)
         }
@@ -68,18 +144,128 @@ fn process_command(state: &mut Packages, cmdline: &str) -> bool {
         // solvers.rs, and deps-available.rs for how-to-install
         "transitive-dep-solution" => {
             // test: transitive-dep-solution 0ad
-            if !check_syntax(2, &cmd_fragments, "<pkg>") { return false; }
             let pkg = cmd_fragments.get(1).unwrap();
             state.print_transitive_dep_solution(pkg)
         }
         "how-to-install" => {
-            if !check_syntax(2, &cmd_fragments, "<pkg>") { return false; }
             let pkg = cmd_fragments.get(1).unwrap();
             state.print_how_to_install(pkg)
         }
 
+        // sources.rs
+        "source-of" => {
+            let pkg = cmd_fragments.get(1).unwrap();
+            state.print_source_of(pkg)
+        }
+        "binaries-of" => {
+            let src = cmd_fragments.get(1).unwrap();
+            state.print_binaries_of(src)
+        }
+        "source-skew" => {
+            state.print_source_skew()
+        }
+
+        // ordering.rs
+        "install-order" => {
+            let pkg = cmd_fragments.get(1).unwrap();
+            state.print_install_order(pkg)
+        }
+        // graph.rs
+        "export-graph" => {
+            let usage = || eprintln!("syntax: {} {}", cmd, spec.syntax);
+            let mut positional = vec![];
+            let mut format = GraphFormat::Dot;
+            let mut depth = None;
+            let mut args = cmd_fragments[1..].iter();
+            while let Some(arg) = args.next() {
+                match *arg {
+                    "--format" => match args.next().map(|f| f.parse::<GraphFormat>()) {
+                        Some(Ok(f)) => format = f,
+                        Some(Err(e)) => { eprintln!("{}", e); return Status::Failed }
+                        None => { usage(); return Status::Failed }
+                    },
+                    "--depth" => match args.next().map(|d| d.parse::<usize>()) {
+                        Some(Ok(d)) => depth = Some(d),
+                        _ => { usage(); return Status::Failed }
+                    },
+                    _ => positional.push(*arg),
+                }
+            }
+            if positional.len() != 2 { usage(); return Status::Failed }
+            let pkg = if positional[0] == "--all" { None } else { Some(positional[0]) };
+            state.export_graph(pkg, positional[1], format, depth)
+        }
+        // graph_stats.rs
+        "graph-stats" => {
+            state.print_graph_stats()
+        }
+        // check.rs
+        "check" => {
+            state.print_check();
+        }
+        // lint.rs
+        "lint" => {
+            state.print_lint(cmd_fragments[1]);
+        }
+        // installability.rs
+        "installability" => {
+            let jobs = match cmd_fragments[1..] {
+                [] => std::thread::available_parallelism().map_or(1, |n| n.get()),
+                ["--jobs", n] => match n.parse::<usize>() {
+                    Ok(n) if n > 0 => n,
+                    _ => { eprintln!("syntax: {} {}", cmd, spec.syntax); return Status::Failed }
+                },
+                _ => { eprintln!("syntax: {} {}", cmd, spec.syntax); return Status::Failed }
+            };
+            state.print_installability(jobs)
+        }
+
+        // search.rs
+        "search" => {
+            let mut query = SearchQuery { pattern: None, names_only: false, fields: vec![] };
+            let mut args = cmd_fragments[1..].iter();
+            while let Some(arg) = args.next() {
+                match *arg {
+                    "--names-only" => query.names_only = true,
+                    "--field" => match args.next().and_then(|f| f.split_once('=')) {
+                        Some((key, value)) => query.fields.push((key.to_string(), value.to_string())),
+                        None => { eprintln!("syntax: {} {}", cmd, spec.syntax); return Status::Failed }
+                    },
+                    _ if query.pattern.is_none() => query.pattern = Some(arg.to_string()),
+                    _ => { eprintln!("syntax: {} {}", cmd, spec.syntax); return Status::Failed }
+                }
+            }
+            if query.pattern.is_none() && query.fields.is_empty() {
+                eprintln!("syntax: {} {}", cmd, spec.syntax);
+                return Status::Failed
+            }
+            state.print_search(&query)
+        }
+
+        // query.rs
+        "query" => {
+            state.print_query(&cmd_fragments[1..].join(" "))
+        }
+
+        // output.rs
+        "set-format" => {
+            match cmd_fragments.get(1).unwrap().parse::<OutputFormat>() {
+                Err(e) => { eprintln!("{}", e); return Status::Failed }
+                Ok(format) => state.set_format(format)
+            }
+        }
+        // parsers.rs
+        "set-strict" => {
+            match cmd_fragments[1] {
+                "on" => state.set_strict(true),
+                "off" => state.set_strict(false),
+                _ => { eprintln!("syntax: {} {}", cmd, spec.syntax); return Status::Failed }
+            }
+        }
+        "load-errors" => {
+            state.print_load_errors();
+        }
         "set-server" => {
-            if !check_syntax(2, &cmd_fragments, "<server>") { return false; }
             let server = cmd_fragments.get(1).unwrap();
             state.set_server(server)
         }
@@ -87,11 +273,6 @@ fn process_command(state: &mut Packages, cmdline: &str) -> bool {
             state.execute();
         }
         "enq-verify" => {
-            let cmd : &str = &cmd_fragments.get(0).unwrap();
-            if cmd_fragments.len() < 2 || cmd_fragments.len() > 3 {
-                println!("syntax: {} <pkg> [<version>]", cmd);
-                return false
-            }
             let pkg = cmd_fragments.get(1).unwrap();
             if cmd_fragments.len() == 2 {
                 state.enq_verify(pkg);
@@ -102,12 +283,10 @@ fn process_command(state: &mut Packages, cmdline: &str) -> bool {
         }
 
         "output-md5s" => {
-            if !check_syntax(2, &cmd_fragments, "<output-file>") { return false; }
             let fname = cmd_fragments.get(1).unwrap();
             state.output_md5s(fname);
         }
         "test-version-compare" => {
-            if !check_syntax(3, &cmd_fragments, "<version1> <version2>") { return false; }
             let v1 = cmd_fragments.get(1).unwrap().parse::<debversion::DebianVersionNum>().unwrap();
             let v2 = cmd_fragments.get(2).unwrap().parse::<debversion::DebianVersionNum>().unwrap();
             println!("{} and {}: {:?}", v1, v2, v1.cmp(&v2));
@@ -119,25 +298,49 @@ fn process_command(state: &mut Packages, cmdline: &str) -> bool {
             // 2-a vs 1-b
             // a vs ~a
         }
+        // commands.rs
+        "help" => {
+            if !commands::print_help(cmd_fragments.get(1).copied()) { return Status::Failed }
+        }
         _ => {
-            println!("couldn't understand cmd {:?}", cmd)
+            eprintln!("Error: {} is in the command table but not implemented", spec.name);
+            return Status::Failed
         }
     }
-    return false;
+    if state.take_failure() { Status::Failed } else { Status::Ok }
 }
 
-fn main() {
-    let mut state : Packages = Packages::new();
+const USAGE : &str = "usage: rpkg [--format text|json] [--strict] [--packages <file>] [--installed <file>] [-c \"<cmd>; <cmd>...\" | -f <script> | <cmd> [<arg>...]]";
 
-    // bonus (0 points): implement command completion!
-    let mut rl = Editor::<()>::new();
-    if rl.load_history("history.txt").is_err() {}
+/// Runs cmdlines in order, stopping at the first that fails. Returns the exit status.
+fn run_batch(state: &mut Packages, cmdlines: Vec<String>) -> i32 {
+    for cmdline in cmdlines {
+        match process_command(state, cmdline.trim()) {
+            Status::Ok => (),
+            Status::Quit => break,
+            Status::Failed => return 1,
+        }
+    }
+    0
+}
+
+fn repl(state: &mut Packages) {
+    let mut rl = Editor::<ShellHelper>::new();
+    rl.set_helper(Some(ShellHelper::new()));
+    // there's no history the first time
+    let _ = rl.load_history("history.txt");
     loop {
+        // package names for completion, refreshed whenever a command has loaded more
+        let helper = rl.helper_mut().unwrap();
+        if helper.package_names.len() != state.package_count() {
+            helper.package_names = state.get_package_names().into_iter().map(String::from).collect();
+            helper.package_names.sort();
+        }
         let readline = rl.readline("$ ");
         match readline {
             Ok(line) => {
                 rl.add_history_entry(line.as_str());
-                if process_command(&mut state, &line) { break }
+                if process_command(state, &line) == Status::Quit { break }
             },
             Err(ReadlineError::Interrupted) => {
                 break
@@ -146,10 +349,91 @@ fn main() {
                 break
             },
             Err(err) => {
-                println!("Error: {:?}", err);
+                eprintln!("Error: {:?}", err);
                 break
             }
         }
     }
     rl.save_history("history.txt").unwrap();
 }
+
+/// Without arguments, runs the interactive shell. Otherwise loads the files given by --packages and
+/// --installed, then runs the commands from -c, -f or the rest of the command line, and exits with
+/// status 1 if one of them fails (2 for bad arguments). With only files to load, starts the shell.
+/// rpkg's own flags can come before or after the command; everything after "--" belongs to the command.
+fn main() {
+    let mut state : Packages = Packages::new();
+    let args : Vec<String> = std::env::args().skip(1).collect();
+    let usage = || -> ! { eprintln!("{}", USAGE); std::process::exit(2) };
+
+    let mut loads : Vec<(&str, &str)> = vec![];
+    let mut script : Option<Vec<String>> = None;
+    // the command and its arguments, already split into words by the calling shell
+    let mut words : Vec<&str> = vec![];
+    let mut i = 0;
+    while i < args.len() {
+        let flag = args[i].as_str();
+        i += 1;
+        match flag {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "--" => {
+                words.extend(args[i..].iter().map(String::as_str));
+                break;
+            }
+            "--strict" => {
+                state.set_strict(true);
+                continue;
+            }
+            "--packages" | "--installed" | "--format" | "-c" | "-f" => (),
+            _ if !words.is_empty() || !flag.starts_with('-') => {
+                words.push(flag);
+                continue;
+            }
+            _ => usage(),
+        }
+        let value = match args.get(i) {
+            Some(v) => v.as_str(),
+            None => usage(),
+        };
+        i += 1;
+        match flag {
+            // after the command, a --format that isn't rpkg's (like export-graph's --format dot) is the command's
+            "--format" if !words.is_empty() && value.parse::<OutputFormat>().is_err() => words.extend([flag, value]),
+            "--format" => match value.parse::<OutputFormat>() {
+                Err(e) => { eprintln!("{}", e); std::process::exit(2) }
+                Ok(format) => state.set_format(format)
+            },
+            "-c" | "-f" if script.is_some() => usage(),
+            "-c" => script = Some(vec![value.to_string()]),
+            "-f" => match std::fs::read_to_string(value) {
+                Err(e) => { eprintln!("can't read {}: {}", value, e); std::process::exit(2) }
+                Ok(text) => script = Some(text.lines().map(String::from).collect()),
+            },
+            _ => loads.push((flag, value)),
+        }
+    }
+    let cmdlines = match (script, words.is_empty()) {
+        (Some(_), false) => usage(),
+        (Some(script), true) => Some(script),
+        (None, false) => Some(vec![words.iter().map(|w| cmdline::quote(w)).join(" ")]),
+        (None, true) => None,
+    };
+
+    for (flag, filename) in loads {
+        if flag == "--packages" {
+            state.parse_packages(filename);
+        } else {
+            state.parse_installed(filename);
+        }
+        if state.take_failure() {
+            std::process::exit(1);
+        }
+    }
+    match cmdlines {
+        None => repl(&mut state),
+        Some(cmdlines) => std::process::exit(run_batch(&mut state, cmdlines)),
+    }
+}
@@ -0,0 +1,630 @@
+// Just enough OpenPGP (RFC 4880, plus the Ed25519 key type from RFC 9580) to check repository
+// signatures offline: v4 RSA and Ed25519 public keys, v4 signatures, ASCII armor and the cleartext
+// signature framework used by InRelease files.
+//
+// The keyring is trusted as a whole: subkey binding signatures, expiry and revocation are not checked.
+
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+
+use base64::Engine;
+use ed25519_dalek::Verifier;
+use rsa::{BigUint, Pkcs1v15Sign, RsaPublicKey};
+use sha1::Sha1;
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+
+#[derive(Debug)]
+pub enum PgpError {
+    Io(String, std::io::Error),
+    Armor(String),
+    Packet(String),
+    NotClearsigned,
+}
+
+impl fmt::Display for PgpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PgpError::Io(file, e) => write!(f, "can't read {}: {}", file, e),
+            PgpError::Armor(msg) => write!(f, "bad ASCII armor: {}", msg),
+            PgpError::Packet(msg) => write!(f, "bad OpenPGP packet: {}", msg),
+            PgpError::NotClearsigned => write!(f, "not a clear-signed message"),
+        }
+    }
+}
+
+const TAG_SIGNATURE : u8 = 2;
+const TAG_PUBLIC_KEY : u8 = 6;
+const TAG_PUBLIC_SUBKEY : u8 = 14;
+
+const ALGO_RSA : [u8; 3] = [1, 2, 3];
+const ALGO_EDDSA_LEGACY : u8 = 22;
+const ALGO_ED25519 : u8 = 27;
+
+// 1.3.6.1.4.1.11591.15.1, the curve OID of legacy EdDSA Ed25519 keys
+const ED25519_LEGACY_OID : [u8; 9] = [0x2b, 0x06, 0x01, 0x04, 0x01, 0xda, 0x47, 0x0f, 0x01];
+
+enum KeyMaterial {
+    Rsa(RsaPublicKey),
+    Ed25519(ed25519_dalek::VerifyingKey),
+    Unsupported(u8),
+}
+
+pub struct PublicKey {
+    /// hex v4 fingerprint of this key
+    pub fingerprint : String,
+    /// hex v4 fingerprint of the primary key; equal to fingerprint unless this is a subkey
+    pub primary_fingerprint : String,
+    material : KeyMaterial,
+}
+
+impl PublicKey {
+    pub fn key_id(&self) -> &str {
+        &self.fingerprint[self.fingerprint.len() - 16..]
+    }
+}
+
+pub struct Keyring {
+    pub keys : Vec<PublicKey>,
+}
+
+pub enum SignatureStatus {
+    Good,
+    Bad,
+    UnknownKey,
+    Unsupported(String),
+}
+
+pub struct SignatureCheck {
+    /// key ID or fingerprint the signature names as its issuer
+    pub issuer : String,
+    /// the key that verified (or failed to verify) the signature, when it is in the keyring
+    pub key : Option<(String, String)>,
+    pub status : SignatureStatus,
+}
+
+pub struct Verification {
+    pub signatures : Vec<SignatureCheck>,
+}
+
+impl Verification {
+    /// A message is trusted if some signature is good and none is bad; signatures by keys that
+    /// aren't in the keyring are ignored, as apt does when an archive is signed by several keys.
+    pub fn is_valid(&self) -> bool {
+        self.signatures.iter().any(|s| matches!(s.status, SignatureStatus::Good))
+            && !self.signatures.iter().any(|s| matches!(s.status, SignatureStatus::Bad))
+    }
+
+    /// Fingerprints (of the primary keys) that made good signatures.
+    pub fn good_fingerprints(&self) -> Vec<&str> {
+        self.signatures.iter().filter_map(|s| match (&s.status, &s.key) {
+            (SignatureStatus::Good, Some((_, primary))) => Some(&primary[..]),
+            _ => None
+        }).collect()
+    }
+}
+
+struct Packet<'a> {
+    tag : u8,
+    body : &'a [u8],
+}
+
+fn parse_packets(mut data: &[u8]) -> Result<Vec<Packet<'_>>, PgpError> {
+    let short = || PgpError::Packet(String::from("truncated packet"));
+    let mut packets = vec![];
+    while !data.is_empty() {
+        let header = data[0];
+        if header & 0x80 == 0 {
+            return Err(PgpError::Packet(format!("bad packet header byte {:#x}", header)));
+        }
+        let (tag, len, header_len) = if header & 0x40 != 0 {
+            // new format
+            let tag = header & 0x3f;
+            let first = *data.get(1).ok_or_else(short)? as usize;
+            match first {
+                0..=191 => (tag, first, 2),
+                192..=223 => {
+                    let second = *data.get(2).ok_or_else(short)? as usize;
+                    (tag, ((first - 192) << 8) + second + 192, 3)
+                }
+                255 => {
+                    let b = data.get(2..6).ok_or_else(short)?;
+                    (tag, u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize, 6)
+                }
+                _ => return Err(PgpError::Packet(String::from("partial body lengths are not supported"))),
+            }
+        } else {
+            // old format
+            let tag = (header >> 2) & 0x0f;
+            match header & 0x03 {
+                0 => (tag, *data.get(1).ok_or_else(short)? as usize, 2),
+                1 => {
+                    let b = data.get(1..3).ok_or_else(short)?;
+                    (tag, u16::from_be_bytes([b[0], b[1]]) as usize, 3)
+                }
+                2 => {
+                    let b = data.get(1..5).ok_or_else(short)?;
+                    (tag, u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize, 5)
+                }
+                _ => (tag, data.len() - 1, 1),
+            }
+        };
+        let body = data.get(header_len..header_len + len).ok_or_else(short)?;
+        packets.push(Packet { tag, body });
+        data = &data[header_len + len..];
+    }
+    Ok(packets)
+}
+
+/// Reads an MPI (two-byte bit count followed by the big-endian value) from the front of data.
+fn read_mpi<'a>(data: &mut &'a [u8]) -> Result<&'a [u8], PgpError> {
+    if data.len() < 2 {
+        return Err(PgpError::Packet(String::from("truncated MPI")));
+    }
+    let bits = u16::from_be_bytes([data[0], data[1]]) as usize;
+    let len = bits.div_ceil(8);
+    let value = data.get(2..2 + len).ok_or_else(|| PgpError::Packet(String::from("truncated MPI")))?;
+    *data = &data[2 + len..];
+    Ok(value)
+}
+
+fn left_pad(value: &[u8], len: usize) -> Vec<u8> {
+    let mut padded = vec![0; len.saturating_sub(value.len())];
+    padded.extend_from_slice(value);
+    padded
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn parse_public_key(body: &[u8], primary_fingerprint: Option<&str>) -> Result<Option<PublicKey>, PgpError> {
+    // only v4 keys have SHA-1 fingerprints computed this way; skip anything else
+    if body.len() < 6 || body[0] != 4 {
+        return Ok(None);
+    }
+    let mut hasher = Sha1::new();
+    hasher.update([0x99]);
+    hasher.update((body.len() as u16).to_be_bytes());
+    hasher.update(body);
+    let fingerprint = to_hex(&hasher.finalize());
+
+    let algo = body[5];
+    let mut rest = &body[6..];
+    let material = if ALGO_RSA.contains(&algo) {
+        let n = read_mpi(&mut rest)?;
+        let e = read_mpi(&mut rest)?;
+        match RsaPublicKey::new(BigUint::from_bytes_be(n), BigUint::from_bytes_be(e)) {
+            Ok(key) => KeyMaterial::Rsa(key),
+            Err(_) => KeyMaterial::Unsupported(algo),
+        }
+    } else if algo == ALGO_EDDSA_LEGACY {
+        let oid_len = *rest.first().ok_or_else(|| PgpError::Packet(String::from("truncated EdDSA key")))? as usize;
+        let oid = rest.get(1..1 + oid_len).ok_or_else(|| PgpError::Packet(String::from("truncated EdDSA key")))?;
+        rest = &rest[1 + oid_len..];
+        let point = read_mpi(&mut rest)?;
+        // native point encoding: 0x40 followed by the 32-byte key
+        match (oid == ED25519_LEGACY_OID, point.len() == 33 && point[0] == 0x40) {
+            (true, true) => {
+                let bytes : [u8; 32] = point[1..].try_into().unwrap();
+                match ed25519_dalek::VerifyingKey::from_bytes(&bytes) {
+                    Ok(key) => KeyMaterial::Ed25519(key),
+                    Err(_) => KeyMaterial::Unsupported(algo),
+                }
+            }
+            _ => KeyMaterial::Unsupported(algo),
+        }
+    } else if algo == ALGO_ED25519 {
+        match rest.get(..32).map(|b| <[u8; 32]>::try_from(b).unwrap()) {
+            Some(bytes) => match ed25519_dalek::VerifyingKey::from_bytes(&bytes) {
+                Ok(key) => KeyMaterial::Ed25519(key),
+                Err(_) => KeyMaterial::Unsupported(algo),
+            },
+            None => return Err(PgpError::Packet(String::from("truncated Ed25519 key"))),
+        }
+    } else {
+        KeyMaterial::Unsupported(algo)
+    };
+
+    let primary_fingerprint = primary_fingerprint.unwrap_or(&fingerprint).to_string();
+    Ok(Some(PublicKey { fingerprint, primary_fingerprint, material }))
+}
+
+/// Decodes every ASCII-armored block of the given kind ("PUBLIC KEY BLOCK", "SIGNATURE") in text.
+fn dearmor(text: &str, kind: &str) -> Result<Vec<Vec<u8>>, PgpError> {
+    let begin = format!("-----BEGIN PGP {}-----", kind);
+    let end = format!("-----END PGP {}-----", kind);
+    let mut blocks = vec![];
+    let mut lines = text.lines().map(|l| l.trim_end());
+    while let Some(line) = lines.next() {
+        if line != begin { continue; }
+        let mut body = String::new();
+        let mut in_headers = true;
+        let mut finished = false;
+        for line in &mut lines {
+            if line == end { finished = true; break; }
+            if in_headers {
+                in_headers = false;
+                if line.is_empty() { continue; }
+                if line.contains(": ") {
+                    // skip the remaining armor headers up to the blank line
+                    in_headers = true;
+                    continue;
+                }
+            }
+            // the CRC-24 checksum line
+            if line.starts_with('=') { continue; }
+            body.push_str(line);
+        }
+        if !finished {
+            return Err(PgpError::Armor(format!("missing {:?}", end)));
+        }
+        let decoded = base64::engine::general_purpose::STANDARD.decode(body.as_bytes())
+            .map_err(|e| PgpError::Armor(e.to_string()))?;
+        blocks.push(decoded);
+    }
+    Ok(blocks)
+}
+
+impl Keyring {
+    /// Parses a keyring, either binary (as in /usr/share/keyrings/*.gpg) or ASCII-armored.
+    pub fn from_bytes(data: &[u8]) -> Result<Keyring, PgpError> {
+        let blocks = match std::str::from_utf8(data) {
+            Ok(text) if text.contains("-----BEGIN PGP PUBLIC KEY BLOCK-----") => dearmor(text, "PUBLIC KEY BLOCK")?,
+            _ => vec![data.to_vec()],
+        };
+        let mut keys = vec![];
+        for block in &blocks {
+            let mut primary : Option<String> = None;
+            for packet in parse_packets(block)? {
+                match packet.tag {
+                    TAG_PUBLIC_KEY => {
+                        let key = parse_public_key(packet.body, None)?;
+                        primary = key.as_ref().map(|k| k.fingerprint.clone());
+                        keys.extend(key);
+                    }
+                    TAG_PUBLIC_SUBKEY => {
+                        if let Some(primary) = &primary {
+                            keys.extend(parse_public_key(packet.body, Some(primary))?);
+                        }
+                    }
+                    _ => ()
+                }
+            }
+        }
+        Ok(Keyring { keys })
+    }
+
+    pub fn from_file(filename: &str) -> Result<Keyring, PgpError> {
+        let data = std::fs::read(filename).map_err(|e| PgpError::Io(filename.to_string(), e))?;
+        Keyring::from_bytes(&data)
+    }
+
+    /// Finds a key by key ID or fingerprint (hex, any case).
+    fn find(&self, issuer: &str) -> Option<&PublicKey> {
+        let issuer = issuer.to_uppercase();
+        self.keys.iter().find(|k| k.fingerprint == issuer || k.key_id() == issuer)
+    }
+}
+
+struct Signature<'a> {
+    sig_type : u8,
+    pub_algo : u8,
+    hash_algo : u8,
+    hashed_part : &'a [u8],
+    hash_prefix : [u8; 2],
+    issuer : Option<String>,
+    values : Vec<&'a [u8]>,
+}
+
+fn parse_signature(body: &[u8]) -> Result<Signature<'_>, PgpError> {
+    let short = || PgpError::Packet(String::from("truncated signature"));
+    if body.first() != Some(&4) {
+        return Err(PgpError::Packet(format!("unsupported signature version {:?}", body.first())));
+    }
+    let header = body.get(..6).ok_or_else(short)?;
+    let hashed_len = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let hashed_part = body.get(..6 + hashed_len).ok_or_else(short)?;
+    let mut rest = &body[6 + hashed_len..];
+    let unhashed_len = u16::from_be_bytes([*rest.first().ok_or_else(short)?, *rest.get(1).ok_or_else(short)?]) as usize;
+    let unhashed = rest.get(2..2 + unhashed_len).ok_or_else(short)?;
+    rest = &rest[2 + unhashed_len..];
+    let hash_prefix = [*rest.first().ok_or_else(short)?, *rest.get(1).ok_or_else(short)?];
+    rest = &rest[2..];
+
+    // issuer fingerprint (33) is preferred to the 8-byte issuer key ID (16)
+    let mut issuer_fpr = None;
+    let mut issuer_id = None;
+    for subpackets in [&hashed_part[6..], unhashed] {
+        let mut sp = subpackets;
+        while !sp.is_empty() {
+            let (len, skip) = match sp[0] {
+                0..=191 => (sp[0] as usize, 1),
+                192..=254 => (((sp[0] as usize - 192) << 8) + *sp.get(1).ok_or_else(short)? as usize + 192, 2),
+                255 => {
+                    let b = sp.get(1..5).ok_or_else(short)?;
+                    (u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize, 5)
+                }
+            };
+            let subpacket = sp.get(skip..skip + len).ok_or_else(short)?;
+            match subpacket.first().map(|t| t & 0x7f) {
+                Some(16) if subpacket.len() == 9 => issuer_id = Some(to_hex(&subpacket[1..])),
+                Some(33) if subpacket.len() == 22 => issuer_fpr = Some(to_hex(&subpacket[2..])),
+                _ => ()
+            }
+            sp = &sp[skip + len..];
+        }
+    }
+
+    let pub_algo = header[2];
+    let values = if ALGO_RSA.contains(&pub_algo) {
+        vec![read_mpi(&mut rest)?]
+    } else if pub_algo == ALGO_EDDSA_LEGACY {
+        vec![read_mpi(&mut rest)?, read_mpi(&mut rest)?]
+    } else if pub_algo == ALGO_ED25519 {
+        vec![rest.get(..64).ok_or_else(short)?]
+    } else {
+        vec![]
+    };
+
+    Ok(Signature {
+        sig_type: header[1],
+        pub_algo,
+        hash_algo: header[3],
+        hashed_part,
+        hash_prefix,
+        issuer: issuer_fpr.or(issuer_id),
+        values,
+    })
+}
+
+/// Computes the v4 signature hash over data: the data, the hashed part of the signature and its trailer.
+fn signature_digest<D: Digest>(sig: &Signature, data: &[u8]) -> Vec<u8> {
+    let mut hasher = D::new();
+    hasher.update(data);
+    hasher.update(sig.hashed_part);
+    hasher.update([0x04, 0xff]);
+    hasher.update((sig.hashed_part.len() as u32).to_be_bytes());
+    hasher.finalize().to_vec()
+}
+
+fn check_signature(sig: &Signature, key: &PublicKey, data: &[u8]) -> SignatureStatus {
+    let (digest, scheme) = match sig.hash_algo {
+        2 => (signature_digest::<Sha1>(sig, data), Pkcs1v15Sign::new::<Sha1>()),
+        8 => (signature_digest::<Sha256>(sig, data), Pkcs1v15Sign::new::<Sha256>()),
+        9 => (signature_digest::<Sha384>(sig, data), Pkcs1v15Sign::new::<Sha384>()),
+        10 => (signature_digest::<Sha512>(sig, data), Pkcs1v15Sign::new::<Sha512>()),
+        11 => (signature_digest::<Sha224>(sig, data), Pkcs1v15Sign::new::<Sha224>()),
+        h => return SignatureStatus::Unsupported(format!("hash algorithm {}", h)),
+    };
+    if digest[..2] != sig.hash_prefix {
+        return SignatureStatus::Bad;
+    }
+    let good = match (&key.material, sig.pub_algo) {
+        (KeyMaterial::Rsa(rsa_key), algo) if ALGO_RSA.contains(&algo) => {
+            let s = left_pad(sig.values[0], rsa::traits::PublicKeyParts::size(rsa_key));
+            rsa_key.verify(scheme, &digest, &s).is_ok()
+        }
+        (KeyMaterial::Ed25519(ed_key), ALGO_EDDSA_LEGACY) => {
+            let mut bytes = left_pad(sig.values[0], 32);
+            bytes.extend(left_pad(sig.values[1], 32));
+            match ed25519_dalek::Signature::from_slice(&bytes) {
+                Ok(s) => ed_key.verify(&digest, &s).is_ok(),
+                Err(_) => false,
+            }
+        }
+        (KeyMaterial::Ed25519(ed_key), ALGO_ED25519) => {
+            match ed25519_dalek::Signature::from_slice(sig.values[0]) {
+                Ok(s) => ed_key.verify(&digest, &s).is_ok(),
+                Err(_) => false,
+            }
+        }
+        (KeyMaterial::Unsupported(algo), _) => return SignatureStatus::Unsupported(format!("key algorithm {}", algo)),
+        (_, algo) => return SignatureStatus::Unsupported(format!("signature algorithm {} for this key", algo)),
+    };
+    if good { SignatureStatus::Good } else { SignatureStatus::Bad }
+}
+
+/// Converts line endings to CRLF, as required for text-mode (type 0x01) signatures.
+fn canonicalize_text(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for (i, &b) in data.iter().enumerate() {
+        if b == b'\n' && (i == 0 || data[i - 1] != b'\r') {
+            out.push(b'\r');
+        }
+        out.push(b);
+    }
+    out
+}
+
+fn verify_packets(signature_blocks: &[Vec<u8>], keyring: &Keyring, binary: &[u8], text: &[u8]) -> Result<Verification, PgpError> {
+    let mut signatures = vec![];
+    for block in signature_blocks {
+        for packet in parse_packets(block)? {
+            if packet.tag != TAG_SIGNATURE { continue; }
+            let sig = parse_signature(packet.body)?;
+            let issuer = sig.issuer.clone().unwrap_or_else(|| String::from("unknown"));
+            let check = match keyring.find(&issuer) {
+                None => SignatureCheck { issuer, key: None, status: SignatureStatus::UnknownKey },
+                Some(key) => {
+                    let data = match sig.sig_type {
+                        0x00 => binary,
+                        0x01 => text,
+                        t => {
+                            signatures.push(SignatureCheck {
+                                issuer, key: None, status: SignatureStatus::Unsupported(format!("signature type {:#04x}", t))
+                            });
+                            continue;
+                        }
+                    };
+                    SignatureCheck {
+                        issuer,
+                        key: Some((key.fingerprint.clone(), key.primary_fingerprint.clone())),
+                        status: check_signature(&sig, key, data),
+                    }
+                }
+            };
+            signatures.push(check);
+        }
+    }
+    if signatures.is_empty() {
+        return Err(PgpError::Packet(String::from("no signature packets found")));
+    }
+    Ok(Verification { signatures })
+}
+
+/// Splits a clear-signed message into its signed text (dash-unescaped) and its armored signature.
+pub fn split_clearsigned(contents: &str) -> Result<(String, String), PgpError> {
+    let mut lines = contents.lines();
+    if lines.next().map(|l| l.trim_end()) != Some("-----BEGIN PGP SIGNED MESSAGE-----") {
+        return Err(PgpError::NotClearsigned);
+    }
+    for line in &mut lines {
+        if line.trim_end().is_empty() { break; }
+    }
+    let mut text : Vec<&str> = vec![];
+    let mut signature = String::new();
+    for line in &mut lines {
+        if line.trim_end() == "-----BEGIN PGP SIGNATURE-----" {
+            signature.push_str(line);
+            signature.push('\n');
+            break;
+        }
+        text.push(line.strip_prefix("- ").unwrap_or(line));
+    }
+    if signature.is_empty() {
+        return Err(PgpError::Armor(String::from("clear-signed message has no signature")));
+    }
+    for line in lines {
+        signature.push_str(line);
+        signature.push('\n');
+    }
+    Ok((text.join("\n") + "\n", signature))
+}
+
+/// Verifies a clear-signed message such as an InRelease file.
+pub fn verify_clearsigned(contents: &str, keyring: &Keyring) -> Result<Verification, PgpError> {
+    let (text, armored) = split_clearsigned(contents)?;
+    // the signed data has trailing whitespace stripped, CRLF line endings and no final line ending
+    let canonical = text.lines()
+        .map(|l| l.trim_end_matches([' ', '\t', '\r']))
+        .collect::<Vec<&str>>()
+        .join("\r\n");
+    let blocks = dearmor(&armored, "SIGNATURE")?;
+    verify_packets(&blocks, keyring, canonical.as_bytes(), canonical.as_bytes())
+}
+
+/// Verifies a detached signature (armored or binary, e.g. Release.gpg) over data.
+pub fn verify_detached(data: &[u8], signature: &[u8], keyring: &Keyring) -> Result<Verification, PgpError> {
+    let blocks = match std::str::from_utf8(signature) {
+        Ok(text) if text.contains("-----BEGIN PGP SIGNATURE-----") => dearmor(text, "SIGNATURE")?,
+        _ => vec![signature.to_vec()],
+    };
+    verify_packets(&blocks, keyring, data, &canonicalize_text(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // made by tests/openpgp/generate.sh
+    const MESSAGE : &str = include_str!("../tests/openpgp/message");
+    const RSA_KEY : &[u8] = include_bytes!("../tests/openpgp/rsa.asc");
+    const RSA_FPR : &str = include_str!("../tests/openpgp/rsa.fpr");
+    const RSA_CLEARSIGNED : &str = include_str!("../tests/openpgp/rsa.clearsigned");
+    const RSA_DETACHED : &[u8] = include_bytes!("../tests/openpgp/rsa.sig");
+    const ED25519_KEY : &[u8] = include_bytes!("../tests/openpgp/ed25519.asc");
+    const ED25519_FPR : &str = include_str!("../tests/openpgp/ed25519.fpr");
+    const ED25519_CLEARSIGNED : &str = include_str!("../tests/openpgp/ed25519.clearsigned");
+    const ED25519_DETACHED : &[u8] = include_bytes!("../tests/openpgp/ed25519.sig.asc");
+
+    fn keyring(keys: &[&[u8]]) -> Keyring {
+        Keyring::from_bytes(&keys.concat()).unwrap()
+    }
+
+    fn only_signature(verification: &Verification) -> &SignatureCheck {
+        assert_eq!(verification.signatures.len(), 1);
+        &verification.signatures[0]
+    }
+
+    fn assert_good(verification: &Verification, fingerprint: &str) {
+        let check = only_signature(verification);
+        assert!(matches!(check.status, SignatureStatus::Good));
+        assert_eq!(check.key, Some((fingerprint.to_string(), fingerprint.to_string())));
+        assert_eq!(verification.good_fingerprints(), [fingerprint]);
+        assert!(verification.is_valid());
+    }
+
+    fn assert_bad(verification: &Verification, fingerprint: &str) {
+        let check = only_signature(verification);
+        assert!(matches!(check.status, SignatureStatus::Bad));
+        assert_eq!(check.key, Some((fingerprint.to_string(), fingerprint.to_string())));
+        assert!(verification.good_fingerprints().is_empty());
+        assert!(!verification.is_valid());
+    }
+
+    #[test]
+    fn keyring_has_both_keys() {
+        let keyring = keyring(&[RSA_KEY, ED25519_KEY]);
+        let fingerprints : Vec<&str> = keyring.keys.iter().map(|k| &k.fingerprint[..]).collect();
+        assert_eq!(fingerprints, [RSA_FPR.trim(), ED25519_FPR.trim()]);
+        assert!(keyring.keys.iter().all(|k| k.fingerprint == k.primary_fingerprint));
+    }
+
+    #[test]
+    fn good_clearsigned_rsa() {
+        assert_good(&verify_clearsigned(RSA_CLEARSIGNED, &keyring(&[RSA_KEY])).unwrap(), RSA_FPR.trim());
+    }
+
+    #[test]
+    fn good_clearsigned_ed25519() {
+        assert_good(&verify_clearsigned(ED25519_CLEARSIGNED, &keyring(&[ED25519_KEY])).unwrap(), ED25519_FPR.trim());
+    }
+
+    #[test]
+    fn good_detached_binary_rsa() {
+        let verification = verify_detached(MESSAGE.as_bytes(), RSA_DETACHED, &keyring(&[RSA_KEY])).unwrap();
+        assert_good(&verification, RSA_FPR.trim());
+    }
+
+    #[test]
+    fn good_detached_text_ed25519() {
+        let verification = verify_detached(MESSAGE.as_bytes(), ED25519_DETACHED, &keyring(&[ED25519_KEY])).unwrap();
+        assert_good(&verification, ED25519_FPR.trim());
+        // a text signature doesn't depend on line endings
+        let crlf = MESSAGE.replace('\n', "\r\n");
+        assert_good(&verify_detached(crlf.as_bytes(), ED25519_DETACHED, &keyring(&[ED25519_KEY])).unwrap(), ED25519_FPR.trim());
+    }
+
+    #[test]
+    fn changed_text_is_bad() {
+        let keyring = keyring(&[RSA_KEY, ED25519_KEY]);
+        for (clearsigned, fingerprint) in [(RSA_CLEARSIGNED, RSA_FPR), (ED25519_CLEARSIGNED, ED25519_FPR)] {
+            let changed = clearsigned.replace("Codename: sid", "Codename: trixie");
+            assert_bad(&verify_clearsigned(&changed, &keyring).unwrap(), fingerprint.trim());
+        }
+        let changed = MESSAGE.replace("Codename: sid", "Codename: trixie");
+        assert_bad(&verify_detached(changed.as_bytes(), RSA_DETACHED, &keyring).unwrap(), RSA_FPR.trim());
+        assert_bad(&verify_detached(changed.as_bytes(), ED25519_DETACHED, &keyring).unwrap(), ED25519_FPR.trim());
+    }
+
+    #[test]
+    fn unknown_key() {
+        for (clearsigned, fingerprint, other_key) in [(RSA_CLEARSIGNED, RSA_FPR, ED25519_KEY), (ED25519_CLEARSIGNED, ED25519_FPR, RSA_KEY)] {
+            let verification = verify_clearsigned(clearsigned, &keyring(&[other_key])).unwrap();
+            let check = only_signature(&verification);
+            assert!(matches!(check.status, SignatureStatus::UnknownKey));
+            assert!(check.key.is_none());
+            assert!(fingerprint.trim().ends_with(&check.issuer.to_uppercase()));
+            assert!(!verification.is_valid());
+        }
+    }
+
+    #[test]
+    fn split_clearsigned_keeps_the_text_as_written() {
+        // trailing whitespace is kept, and "- " escapes are undone
+        let (text, signature) = split_clearsigned(RSA_CLEARSIGNED).unwrap();
+        assert_eq!(text, MESSAGE);
+        assert!(signature.starts_with("-----BEGIN PGP SIGNATURE-----\n"));
+        assert!(matches!(split_clearsigned(MESSAGE), Err(PgpError::NotClearsigned)));
+    }
+}
@@ -1,3 +1,6 @@
+// execute() is still to be written; these imports and Collector are for it
+#![allow(unused_imports, dead_code)]
+
 use urlencoding::encode;
 
 use curl::easy::{Easy2, Handler, WriteError};
@@ -9,10 +12,10 @@ use std::sync::atomic::{AtomicI32, Ordering};
 
 use crate::Packages;
 
-struct Collector(Box<String>);
+struct Collector(String);
 impl Handler for Collector {
     fn write(&mut self, data: &[u8]) -> Result<usize, WriteError> {
-        (*self.0).push_str(str::from_utf8(&data.to_vec()).unwrap());
+        self.0.push_str(str::from_utf8(data).unwrap());
         Ok(data.len())
     }
 }
@@ -24,8 +27,6 @@ impl Drop for Packages {
     }
 }
 
-static EASYKEY_COUNTER: AtomicI32 = AtomicI32::new(0);
-
 pub struct AsyncState {
     server : String,
 }
@@ -47,17 +48,17 @@ impl Packages {
     pub fn enq_verify(&mut self, pkg:&str) {
         let version = self.get_available_debver(pkg);
         match version {
-            None => { println!("Error: package {} not defined.", pkg); return },
+            None => { self.fail(&format!("Error: package {} not defined.", pkg)); },
             Some(v) => { 
                 let vs = &v.to_string();
                 self.enq_verify_with_version(pkg, vs); 
             }
-        };
+        }
     }
 
     /// Enqueues a request for the provided version/package information. Stores any needed state to async_state so that execute() can handle the results and print out needed output.
-    pub fn enq_verify_with_version(&mut self, pkg:&str, version:&str) {
-        let url = format!("TODO");
+    pub fn enq_verify_with_version(&mut self, _pkg:&str, _version:&str) {
+        let url = "TODO".to_string();
         println!("queueing request {}", url);
     }
 
@@ -0,0 +1,170 @@
+use crate::debversion::DebianVersionNum;
+use crate::Packages;
+use crate::packages::Dependency;
+use crate::packages::interner::PackageId;
+use crate::packages::output::{BrokenDependencyReport, CheckReport, FixReport, format_dependency};
+
+/// An installed package's dependency that no installed package satisfies.
+pub struct BrokenDependency {
+    pub package_num : PackageId,
+    pub dependency : Dependency,
+    /// the first alternative whose available version would satisfy the dependency, if any
+    pub fix : Option<(PackageId, DebianVersionNum)>,
+}
+
+/// Result of checking every installed package, like apt-get check.
+pub struct CheckSummary {
+    pub installed : usize,
+    pub broken_packages : usize,
+    pub broken_dependencies : Vec<BrokenDependency>,
+}
+
+impl CheckSummary {
+    pub fn is_consistent(&self) -> bool {
+        self.broken_dependencies.is_empty()
+    }
+
+    pub fn fixable(&self) -> usize {
+        self.broken_dependencies.iter().filter(|b| b.fix.is_some()).count()
+    }
+}
+
+impl Packages {
+    /// Evaluates every dependency of every installed package with dep_is_satisfied, and for each
+    /// unsatisfied one looks for an available version that would fix it.
+    pub fn check_installed(&self) -> CheckSummary {
+        let mut installed : Vec<PackageId> = self.installed_debvers.keys().cloned().collect();
+        installed.sort_by_key(|pn| self.get_package_name(*pn));
+        let mut broken_dependencies = vec![];
+        let mut broken_packages = 0;
+        for package_num in &installed {
+            let mut broken = false;
+            for dep in self.installed_dependencies.get(package_num).into_iter().flatten() {
+                if self.dep_is_satisfied(dep).is_some() { continue; }
+                broken = true;
+                let fix = dep.iter().find_map(|alt| match self.available_debvers.get(&alt.package_num) {
+                    Some(v) if alt.is_satisfied_by(v) => Some((alt.package_num, v.clone())),
+                    _ => None
+                });
+                broken_dependencies.push(BrokenDependency { package_num: *package_num, dependency: dep.clone(), fix });
+            }
+            if broken { broken_packages += 1; }
+        }
+        CheckSummary { installed: installed.len(), broken_packages, broken_dependencies }
+    }
+
+    /// The report print_check prints (or emits as JSON) for summary.
+    pub fn check_report(&self, summary: &CheckSummary) -> CheckReport {
+        let unsatisfied : Vec<BrokenDependencyReport> = summary.broken_dependencies.iter().map(|broken| {
+            let fix = broken.fix.as_ref().map(|(pn, v)| {
+                let name = self.get_package_name(*pn);
+                let action = match self.get_installed_debver(name) {
+                    None => "install",
+                    Some(iv) if iv < v => "upgrade",
+                    Some(_) => "downgrade",
+                };
+                FixReport { action: action.to_string(), package: name.to_string(), version: v.to_string() }
+            });
+            BrokenDependencyReport {
+                package: self.get_package_name(broken.package_num).to_string(),
+                dependency: self.dependency_report(&broken.dependency),
+                fix,
+            }
+        }).collect();
+        CheckReport {
+            installed: summary.installed,
+            broken: summary.broken_packages,
+            unsatisfied,
+            fixable: summary.fixable(),
+            status: String::from(if summary.is_consistent() { "ok" } else { "broken" }),
+        }
+    }
+
+    /// Prints the broken installed packages, then a one-line key=value summary for scripts.
+    pub fn print_check(&self) -> CheckSummary {
+        let summary = self.check_installed();
+        let report = self.check_report(&summary);
+        if !self.emit_json(&report) {
+            for broken in &report.unsatisfied {
+                let fix = match &broken.fix {
+                    None => String::from("no fix available"),
+                    Some(FixReport { action, package, version }) if action == "install" => format!("fix: install {} {}", package, version),
+                    Some(FixReport { action, package, version }) => format!("fix: {} {} to {}", action, package, version),
+                };
+                println!("{}: unsatisfied dependency {}; {}", broken.package, format_dependency(&broken.dependency), fix);
+            }
+            println!("check: installed={} broken={} unsatisfied={} fixable={} status={}",
+                     report.installed, report.broken, report.unsatisfied.len(), report.fixable, report.status);
+        }
+        if !summary.is_consistent() {
+            self.mark_failed();
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Packages;
+
+    // app needs an awk (which mawk provides) and a newer libfoo; daemon needs a logger nothing has
+    const STATUS : &str = "\
+Package: app\nStatus: install ok installed\nVersion: 1.0\nDepends: awk, libfoo (>= 2.0)\n\n\
+Package: mawk\nStatus: install ok installed\nVersion: 1.3\nProvides: awk\n\n\
+Package: libfoo\nStatus: install ok installed\nVersion: 1.0\n\n\
+Package: daemon\nStatus: install ok installed\nVersion: 1.0\nDepends: logger\n";
+
+    const AVAILABLE : &str = "Package: libfoo\nVersion: 2.1\n";
+
+    fn loaded() -> Packages {
+        let mut p = Packages::new();
+        p.parse_installed_reader("status", STATUS.as_bytes());
+        let nums = p.parse_packages_reader("Packages", AVAILABLE.as_bytes());
+        p.record_candidates(&nums, None);
+        p.select_candidates();
+        p
+    }
+
+    #[test]
+    fn reports_broken_dependencies_and_their_fixes() {
+        let p = loaded();
+        let summary = p.check_installed();
+        assert_eq!(summary.installed, 4);
+        assert_eq!(summary.broken_packages, 2);
+        let broken : Vec<(&str, String)> = summary.broken_dependencies.iter()
+            .map(|b| (p.get_package_name(b.package_num), p.dep2str(&b.dependency)))
+            .collect();
+        assert_eq!(broken, [("app", String::from("libfoo (>= 2.0)")), ("daemon", String::from("logger"))]);
+        assert_eq!(summary.fixable(), 1);
+        let (fix, version) = summary.broken_dependencies[0].fix.as_ref().unwrap();
+        assert_eq!((p.get_package_name(*fix), version.to_string()), ("libfoo", String::from("2.1")));
+        assert!(!summary.is_consistent());
+    }
+
+    #[test]
+    fn a_provider_satisfies_a_virtual_dependency() {
+        let p = loaded();
+        let summary = p.check_installed();
+        assert!(summary.broken_dependencies.iter().all(|b| !p.dep2str(&b.dependency).contains("awk")));
+        let app = p.get_package_num("app");
+        let awk = &p.installed_dependencies[&app][0];
+        assert_eq!(p.dep_is_satisfied(awk), Some("mawk"));
+    }
+
+    #[test]
+    fn json_report() {
+        let p = loaded();
+        let report = p.check_report(&p.check_installed());
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["installed"], 4);
+        assert_eq!(json["broken"], 2);
+        assert_eq!(json["fixable"], 1);
+        assert_eq!(json["status"], "broken");
+        let first = &json["unsatisfied"][0];
+        assert_eq!(first["package"], "app");
+        assert_eq!(first["fix"]["action"], "upgrade");
+        assert_eq!(first["fix"]["package"], "libfoo");
+        assert_eq!(first["fix"]["version"], "2.1");
+        assert!(json["unsatisfied"][1]["fix"].is_null());
+    }
+}
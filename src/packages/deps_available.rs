@@ -1,37 +1,48 @@
-use rpkg::debversion;
+use crate::debversion;
 use crate::Packages;
 use crate::packages::Dependency;
+use crate::packages::output::*;
 
 impl Packages {
-    /// Gets the dependencies of package_name, and prints out whether they are satisfied
+    /// Gets the dependencies of package_name, and whether they are satisfied
     /// (and by which library/version) or not.
-    pub fn deps_available(&self, package_name: &str) {
+    pub fn deps_available_report(&self, package_name: &str) -> Result<DepsAvailableReport, String> {
         if !self.package_exists(package_name) {
-            println!("no such package {}", package_name);
-            return;
+            return Err(format!("no such package {}", package_name));
         }
-        println!("Package {}:", package_name);
-        // println!("+ {} satisfied by installed version {}", "dep", "459");
-        // some sort of for loop...
+        let mut dependencies = vec![];
         let package_num = self.get_package_num(package_name);
-        if let Some(deps) = self.dependencies.get(package_num){
+        if let Some(deps) = self.dependencies.get(&package_num){
             for dep in deps {
-                println!("- dependency {:?}", self.dep2str(dep));
-                match self.dep_is_satisfied(dep) {
-                    None => println!("-> not satisfied"),
-                    Some(x) => {
-                        if let Some(ver) =
-                            self.installed_debvers.get(self.get_package_num(x)) {
-                            println!("+ {} satisfied by installed version {}", x, ver);
-                        };
-
-                    }
-                }
+                let satisfied_by = self.dep_is_satisfied(dep).and_then(|x| {
+                    self.installed_debvers.get(&self.get_package_num(x))
+                        .map(|ver| SatisfiedByReport { package: x.to_string(), version: ver.to_string() })
+                });
+                dependencies.push(DependencyStatusReport { dependency: self.dependency_report(dep), satisfied_by });
             }
         };
+        Ok(DepsAvailableReport { package: package_name.to_string(), dependencies })
     }
 
-    /// Returns Some(package) which satisfies dependency dd, or None if not satisfied.
+    /// Prints out whether the dependencies of package_name are satisfied (and by which library/version) or not.
+    pub fn deps_available(&self, package_name: &str) {
+        let report = match self.deps_available_report(package_name) {
+            Err(e) => { self.fail(&e); return }
+            Ok(r) => r
+        };
+        if self.emit_json(&report) { return; }
+        println!("Package {}:", package_name);
+        for dep in &report.dependencies {
+            println!("- dependency {:?}", format_dependency(&dep.dependency));
+            match &dep.satisfied_by {
+                None => println!("-> not satisfied"),
+                Some(s) => println!("+ {} satisfied by installed version {}", s.package, s.version),
+            }
+        }
+    }
+
+    /// Returns Some(package) which satisfies dependency dd, either directly or through its Provides,
+    /// or None if not satisfied.
     pub fn dep_is_satisfied(&self, dd:&Dependency) -> Option<&str> {
         // presumably you should loop on dd
         for dep in dd {
@@ -40,7 +51,7 @@ impl Packages {
                 Some(v) => {
                     if let Some((op, ver)) = &dep.rel_version {
                         let ver = ver.parse::<debversion::DebianVersionNum>().unwrap();
-                        if debversion::cmp_debversion_with_op(&op, &v, &ver) {
+                        if debversion::cmp_debversion_with_op(op, v, &ver) {
                             let package = self.get_package_name(dep.package_num);
                             return Some(package);
                         }
@@ -51,8 +62,14 @@ impl Packages {
                     // let (op, iv) = (dep.rel_version.unwrap().0, dep.rel_version.unwrap().1);
                 }
             }
+            // a virtual package provided by an installed one; a versioned dependency needs a versioned Provides
+            for (provider, provided_ver) in self.installed_provides.get(&dep.package_num).into_iter().flatten() {
+                if dep.is_satisfied_by_provides(provided_ver.as_ref()) {
+                    return Some(self.get_package_name(*provider));
+                }
+            }
         }
-        return None;
+        None
     }
 
     /// Returns a Vec of packages which would satisfy dependency dd but for the version.
@@ -64,12 +81,12 @@ impl Packages {
         for dep in dd {
             match self.installed_debvers.get(&dep.package_num) {
                 None => (),
-                Some(v) => {
+                Some(_) => {
                     (result.push(self.get_package_name(dep.package_num)));
                 }
             };
         }
-        return result;
+        result
     }
 }
 
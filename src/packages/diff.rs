@@ -0,0 +1,198 @@
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+
+use itertools::Itertools;
+
+use crate::debversion::DebianVersionNum;
+use crate::Packages;
+use crate::packages::Severity;
+use crate::packages::output::*;
+use crate::packages::snapshot::is_snapshot;
+
+#[derive(Clone, Copy)]
+enum Side {
+    Available,
+    Installed,
+}
+
+/// Loads a snapshot, dpkg status file or Packages index into a new Packages, printing nothing.
+/// A file with a Status: field is taken to be a status file. A file whose every stanza is bad is an error.
+fn load_universe(filename: &str) -> Result<Packages, String> {
+    let data = std::fs::read(filename).map_err(|e| format!("can't read {}: {}", filename, e))?;
+    let mut universe = Packages::new();
+    if is_snapshot(&data) {
+        universe.restore_snapshot(&data).map_err(|e| format!("{}: {}", filename, e))?;
+    } else if data.split(|b| *b == b'\n').any(|line| line.starts_with(b"Status:")) {
+        universe.parse_installed_reader(filename, &data[..]);
+    } else {
+        let stanzas = universe.parse_packages_reader(filename, &data[..]);
+        universe.record_candidates(&stanzas, None);
+        universe.select_candidates();
+    }
+    // an empty file is an empty universe, but one with only bad stanzas is more likely not a control file at all
+    if universe.available_debvers.is_empty() && universe.installed_debvers.is_empty() {
+        if let Some(d) = universe.load_diagnostics().iter().find(|d| d.severity == Severity::Error) {
+            return Err(format!("{} has no valid stanza; the first problem is {}", filename, d));
+        }
+    }
+    Ok(universe)
+}
+
+impl Packages {
+    fn side_names(&self, side: Side) -> impl Iterator<Item=&str> {
+        let versions = match side {
+            Side::Available => &self.available_debvers,
+            Side::Installed => &self.installed_debvers,
+        };
+        versions.keys().map(move |pn| self.get_package_name(*pn))
+    }
+
+    fn side_version(&self, side: Side, package_name: &str) -> Option<&DebianVersionNum> {
+        match side {
+            Side::Available => self.get_available_debver(package_name),
+            Side::Installed => self.get_installed_debver(package_name),
+        }
+    }
+
+    /// Pre-Depends and Depends of one side of package_name, by name so that they compare across universes.
+    fn side_dependencies(&self, side: Side, package_name: &str) -> Vec<DependencyReport> {
+        let package_num = self.get_package_num(package_name);
+        match side {
+            Side::Available => self.install_dependencies(package_num).map(|dep| self.dependency_report(dep)).collect(),
+            Side::Installed => self.installed_dependencies.get(&package_num).into_iter().flatten()
+                .map(|dep| self.dependency_report(dep)).collect(),
+        }
+    }
+
+    /// Compares one side of self (the older universe) with the same side of other.
+    fn diff_side(&self, other: &Packages, side: Side) -> SideDiffReport {
+        let mut report = SideDiffReport::default();
+        let names : BTreeSet<&str> = self.side_names(side).chain(other.side_names(side)).collect();
+        for name in names {
+            let (old, new) = match (self.side_version(side, name), other.side_version(side, name)) {
+                (None, None) => continue,
+                (None, Some(v)) => {
+                    report.added.push(PackageVersionReport { package: name.to_string(), version: v.to_string() });
+                    continue
+                }
+                (Some(v), None) => {
+                    report.removed.push(PackageVersionReport { package: name.to_string(), version: v.to_string() });
+                    continue
+                }
+                (Some(old), Some(new)) => (old, new)
+            };
+            let change = || VersionChangeReport {
+                package: name.to_string(), old_version: old.to_string(), new_version: new.to_string()
+            };
+            match old.cmp(new) {
+                Ordering::Less => report.upgraded.push(change()),
+                Ordering::Greater => report.downgraded.push(change()),
+                Ordering::Equal => {
+                    if let Side::Available = side {
+                        match (self.get_md5sum(name), other.get_md5sum(name)) {
+                            (Some(a), Some(b)) if a != b => report.hashes_changed.push(HashChangeReport {
+                                package: name.to_string(), version: new.to_string(),
+                                old_md5sum: a.to_string(), new_md5sum: b.to_string()
+                            }),
+                            _ => ()
+                        }
+                    }
+                }
+            }
+
+            let old_deps = self.side_dependencies(side, name);
+            let new_deps = other.side_dependencies(side, name);
+            let old_strs : BTreeSet<String> = old_deps.iter().map(format_dependency).collect();
+            let new_strs : BTreeSet<String> = new_deps.iter().map(format_dependency).collect();
+            let added : Vec<DependencyReport> = new_deps.into_iter().filter(|d| !old_strs.contains(&format_dependency(d))).collect();
+            let removed : Vec<DependencyReport> = old_deps.into_iter().filter(|d| !new_strs.contains(&format_dependency(d))).collect();
+            if !added.is_empty() || !removed.is_empty() {
+                report.dependencies_changed.push(DependencyChangeReport { package: name.to_string(), added, removed });
+            }
+        }
+        report
+    }
+
+    /// Loads left and right into universes of their own (each a Packages index, dpkg status file or
+    /// snapshot) and compares them. What's loaded here is left alone.
+    pub fn diff_report(&self, left: &str, right: &str) -> Result<DiffReport, String> {
+        let old = load_universe(left)?;
+        let new = load_universe(right)?;
+        Ok(DiffReport {
+            left: left.to_string(),
+            right: right.to_string(),
+            available: old.diff_side(&new, Side::Available),
+            installed: old.diff_side(&new, Side::Installed),
+        })
+    }
+
+    pub fn print_diff(&self, left: &str, right: &str) {
+        let report = match self.diff_report(left, right) {
+            Err(e) => { self.fail(&format!("Error: {}", e)); return }
+            Ok(report) => report
+        };
+        if self.emit_json(&report) {
+            return;
+        }
+        println!("Comparing {} with {}", report.left, report.right);
+        print_side_diff("Available", &report.available);
+        print_side_diff("Installed", &report.installed);
+    }
+}
+
+fn print_side_diff(label: &str, side: &SideDiffReport) {
+    println!("{}: {} added, {} removed, {} upgraded, {} downgraded, {} with changed dependencies, {} with changed hashes",
+             label, side.added.len(), side.removed.len(), side.upgraded.len(), side.downgraded.len(),
+             side.dependencies_changed.len(), side.hashes_changed.len());
+    for p in &side.added {
+        println!("  added {} {}", p.package, p.version);
+    }
+    for p in &side.removed {
+        println!("  removed {} {}", p.package, p.version);
+    }
+    for c in &side.upgraded {
+        println!("  upgraded {} {} -> {}", c.package, c.old_version, c.new_version);
+    }
+    for c in &side.downgraded {
+        println!("  downgraded {} {} -> {}", c.package, c.old_version, c.new_version);
+    }
+    for c in &side.dependencies_changed {
+        let mut changes = c.added.iter().map(|d| format!("+{}", format_dependency(d)))
+            .chain(c.removed.iter().map(|d| format!("-{}", format_dependency(d))));
+        println!("  dependencies of {}: {}", c.package, changes.join(", "));
+    }
+    for h in &side.hashes_changed {
+        println!("  hash of {} {}: {} -> {}", h.package, h.version, h.old_md5sum, h.new_md5sum);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crate::Packages;
+
+    const INSTALLED : &str = concat!(env!("CARGO_MANIFEST_DIR"), "/data/installed-packages");
+
+    fn file_with(text: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn file_without_a_valid_stanza_is_an_error() {
+        let file = file_with("localhost\n");
+        let name = file.path().to_str().unwrap();
+        let err = Packages::new().diff_report(name, INSTALLED).err().unwrap();
+        assert!(err.starts_with(&format!("{} has no valid stanza; the first problem is {}:1: error:", name, name)), "{}", err);
+        assert!(Packages::new().diff_report(INSTALLED, name).is_err());
+    }
+
+    #[test]
+    fn empty_file_is_an_empty_universe() {
+        let file = file_with("");
+        let report = Packages::new().diff_report(file.path().to_str().unwrap(), INSTALLED).unwrap();
+        assert_eq!(report.installed.added.len(), 3774);
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::str::FromStr;
+
+use petgraph::graph::{Graph, NodeIndex};
+use petgraph::visit::EdgeRef;
+
+use crate::Packages;
+use crate::packages::interner::PackageId;
+
+pub enum GraphNode {
+    Package(PackageId),
+    /// stands for a dependency with alternatives, A | B | C; it has an edge to each alternative
+    Or,
+}
+
+/// An edge from a package (or OR node) to a package it depends on, with the version constraint if any.
+pub struct DepEdge {
+    pub constraint : Option<String>,
+}
+
+pub enum GraphFormat {
+    Dot,
+    GraphMl,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<GraphFormat, String> {
+        match s {
+            "dot" => Ok(GraphFormat::Dot),
+            "graphml" => Ok(GraphFormat::GraphMl),
+            _ => Err(format!("unknown graph format {}; expected dot or graphml", s)),
+        }
+    }
+}
+
+impl Packages {
+    /// Builds a petgraph Graph of the dependencies of root (or of every package, if root is None),
+    /// following at most depth levels of dependencies from root. Edges run from a package to its
+    /// dependencies; a dependency with alternatives goes through an Or node.
+    pub fn dependency_graph(&self, root: Option<&str>, depth: Option<usize>) -> Graph<GraphNode, DepEdge> {
+        let mut graph = Graph::new();
+        let mut nodes : HashMap<PackageId, NodeIndex> = HashMap::new();
+        let mut queue : VecDeque<(PackageId, usize)> = VecDeque::new();
+
+        let mut roots : Vec<PackageId> = match root {
+            Some(name) => vec![self.get_package_num(name)],
+            None => self.package_ids.ids().collect(),
+        };
+        roots.sort_by_key(|pn| self.get_package_name(*pn));
+        for pn in roots {
+            nodes.insert(pn, graph.add_node(GraphNode::Package(pn)));
+            queue.push_back((pn, 0));
+        }
+
+        while let Some((pn, level)) = queue.pop_front() {
+            if depth.is_some_and(|d| level >= d) { continue; }
+            let from = nodes[&pn];
+            for dep in self.install_dependencies(pn) {
+                let from = if dep.len() > 1 { graph.add_node(GraphNode::Or) } else { from };
+                if dep.len() > 1 {
+                    graph.add_edge(nodes[&pn], from, DepEdge { constraint: None });
+                }
+                for alt in dep {
+                    let to = match nodes.get(&alt.package_num) {
+                        Some(n) => *n,
+                        None => {
+                            let n = graph.add_node(GraphNode::Package(alt.package_num));
+                            nodes.insert(alt.package_num, n);
+                            queue.push_back((alt.package_num, level + 1));
+                            n
+                        }
+                    };
+                    let constraint = alt.rel_version.as_ref().map(|(op, ver)| format!("{} {}", op, ver));
+                    graph.add_edge(from, to, DepEdge { constraint });
+                }
+            }
+        }
+        graph
+    }
+
+    fn node_id(&self, graph: &Graph<GraphNode, DepEdge>, n: NodeIndex) -> String {
+        match graph[n] {
+            GraphNode::Package(pn) => self.get_package_name(pn).to_string(),
+            GraphNode::Or => format!("or{}", n.index()),
+        }
+    }
+
+    pub fn graph_to_dot(&self, graph: &Graph<GraphNode, DepEdge>) -> String {
+        let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+        let quote = |s: &str| format!("\"{}\"", escape(s));
+        let mut out = String::from("digraph dependencies {\n    node [shape=box];\n");
+        for n in graph.node_indices() {
+            let id = quote(&self.node_id(graph, n));
+            let attrs = match graph[n] {
+                GraphNode::Or => String::from("label=\"OR\", shape=diamond"),
+                GraphNode::Package(pn) => {
+                    let name = self.get_package_name(pn);
+                    let version = self.get_available_debver(name).or_else(|| self.get_installed_debver(name));
+                    let label = match version {
+                        None => quote(name),
+                        Some(v) => format!("\"{}\\n{}\"", escape(name), escape(&v.to_string())),
+                    };
+                    let style = if self.get_installed_debver(name).is_some() {
+                        "style=filled, fillcolor=lightgreen"
+                    } else if self.get_available_debver(name).is_none() {
+                        // neither installed nor available: virtual or missing
+                        "style=dashed"
+                    } else {
+                        "style=solid"
+                    };
+                    format!("label={}, {}", label, style)
+                }
+            };
+            writeln!(out, "    {} [{}];", id, attrs).unwrap();
+        }
+        for e in graph.edge_references() {
+            let (from, to) = (quote(&self.node_id(graph, e.source())), quote(&self.node_id(graph, e.target())));
+            match &e.weight().constraint {
+                None => writeln!(out, "    {} -> {};", from, to).unwrap(),
+                Some(c) => writeln!(out, "    {} -> {} [label={}];", from, to, quote(c)).unwrap(),
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    pub fn graph_to_graphml(&self, graph: &Graph<GraphNode, DepEdge>) -> String {
+        let escape = |s: &str| s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;");
+        let mut out = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"kind\" for=\"node\" attr.name=\"kind\" attr.type=\"string\"/>\n",
+            "  <key id=\"version\" for=\"node\" attr.name=\"version\" attr.type=\"string\"/>\n",
+            "  <key id=\"installed\" for=\"node\" attr.name=\"installed\" attr.type=\"boolean\"/>\n",
+            "  <key id=\"constraint\" for=\"edge\" attr.name=\"constraint\" attr.type=\"string\"/>\n",
+            "  <graph id=\"dependencies\" edgedefault=\"directed\">\n"));
+        for n in graph.node_indices() {
+            let id = escape(&self.node_id(graph, n));
+            match graph[n] {
+                GraphNode::Or => {
+                    writeln!(out, "    <node id=\"{}\"><data key=\"kind\">or</data></node>", id).unwrap();
+                }
+                GraphNode::Package(pn) => {
+                    let name = self.get_package_name(pn);
+                    write!(out, "    <node id=\"{}\"><data key=\"kind\">package</data>", id).unwrap();
+                    if let Some(v) = self.get_available_debver(name) {
+                        write!(out, "<data key=\"version\">{}</data>", escape(&v.to_string())).unwrap();
+                    }
+                    writeln!(out, "<data key=\"installed\">{}</data></node>", self.get_installed_debver(name).is_some()).unwrap();
+                }
+            }
+        }
+        for e in graph.edge_references() {
+            let (from, to) = (escape(&self.node_id(graph, e.source())), escape(&self.node_id(graph, e.target())));
+            match &e.weight().constraint {
+                None => writeln!(out, "    <edge source=\"{}\" target=\"{}\"/>", from, to).unwrap(),
+                Some(c) => writeln!(out, "    <edge source=\"{}\" target=\"{}\"><data key=\"constraint\">{}</data></edge>",
+                                    from, to, escape(c)).unwrap(),
+            }
+        }
+        out.push_str("  </graph>\n</graphml>\n");
+        out
+    }
+
+    /// Writes the dependency graph of package_name (or of everything, if None) to fname.
+    pub fn export_graph(&self, package_name: Option<&str>, fname: &str, format: GraphFormat, depth: Option<usize>) {
+        if let Some(name) = package_name {
+            if !self.package_exists(name) {
+                self.fail(&format!("no such package {}", name));
+                return;
+            }
+        }
+        let graph = self.dependency_graph(package_name, depth);
+        let contents = match format {
+            GraphFormat::Dot => self.graph_to_dot(&graph),
+            GraphFormat::GraphMl => self.graph_to_graphml(&graph),
+        };
+        match std::fs::write(fname, contents) {
+            Err(e) => self.fail(&format!("Error: can't write {}: {}", fname, e)),
+            Ok(()) => println!("Wrote {} nodes and {} edges to {}", graph.node_count(), graph.edge_count(), fname),
+        }
+    }
+}
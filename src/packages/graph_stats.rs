@@ -0,0 +1,252 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use itertools::Itertools;
+use petgraph::algo::tarjan_scc;
+use petgraph::graph::{Graph, NodeIndex};
+use petgraph::Direction;
+
+use crate::Packages;
+use crate::packages::RelVersionedPackageNum;
+use crate::packages::interner::PackageId;
+
+// how many packages to list in each top-N table
+const TOP_N : usize = 10;
+
+/// Buckets a degree into 0, 1, 2-3, 4-7, 8-15, ...
+fn degree_bucket(degree: usize) -> (usize, usize) {
+    if degree == 0 { return (0, 0); }
+    let low = 1 << (usize::BITS - 1 - degree.leading_zeros());
+    (low, 2 * low - 1)
+}
+
+fn print_distribution(title: &str, degrees: &[usize]) {
+    let mut buckets : BTreeMap<(usize, usize), usize> = BTreeMap::new();
+    for d in degrees {
+        *buckets.entry(degree_bucket(*d)).or_default() += 1;
+    }
+    let total : usize = degrees.iter().sum();
+    let max = degrees.iter().max().copied().unwrap_or(0);
+    println!("{} (mean {:.2}, max {}):", title, total as f64 / degrees.len().max(1) as f64, max);
+    for ((low, high), count) in buckets {
+        let range = if low == high { low.to_string() } else { format!("{}-{}", low, high) };
+        println!("  {:>9}: {}", range, count);
+    }
+}
+
+impl Packages {
+    /// Builds a graph with a node for every known package and an edge from a package to every
+    /// alternative of each of its Depends and Pre-Depends.
+    pub fn package_graph(&self) -> (Graph<PackageId, ()>, HashMap<PackageId, NodeIndex>) {
+        let mut graph = Graph::new();
+        let mut nodes = HashMap::new();
+        let mut package_nums : Vec<PackageId> = self.package_ids.ids().collect();
+        package_nums.sort_by_key(|pn| self.get_package_name(*pn));
+        for pn in package_nums {
+            nodes.insert(pn, graph.add_node(pn));
+        }
+        for (pn, n) in &nodes {
+            for dep in self.install_dependencies(*pn) {
+                for alt in dep {
+                    if let Some(to) = nodes.get(&alt.package_num) {
+                        graph.update_edge(*n, *to, ());
+                    }
+                }
+            }
+        }
+        (graph, nodes)
+    }
+
+    /// The installed or available packages that can satisfy alt, ignoring versions: the package itself, and
+    /// every package that provides it.
+    fn alt_satisfiers(&self, alt: &RelVersionedPackageNum) -> Vec<PackageId> {
+        let exists = |pn: &PackageId| self.available_debvers.contains_key(pn) || self.installed_debvers.contains_key(pn);
+        let providers = self.installed_provides.get(&alt.package_num).into_iter().flatten()
+            .chain(self.available_provides.get(&alt.package_num).into_iter().flatten())
+            .map(|(pn, _)| *pn);
+        let mut satisfiers : Vec<PackageId> = Some(alt.package_num).filter(exists).into_iter().chain(providers).collect();
+        satisfiers.sort();
+        satisfiers.dedup();
+        satisfiers
+    }
+
+    /// For every package, how many installed packages become uninstallable if it disappears.
+    /// A package is installable if it is installed or available and each of its dependencies has an
+    /// installable alternative, or an installable package providing one; version constraints are not considered.
+    pub fn removal_impact(&self) -> HashMap<PackageId, usize> {
+        let packages : Vec<PackageId> = self.package_ids.ids()
+            .filter(|pn| self.available_debvers.contains_key(pn) || self.installed_debvers.contains_key(pn))
+            .collect();
+        // for each dependency of a package, what can satisfy it
+        let mut deps_of : HashMap<PackageId, Vec<Vec<PackageId>>> = HashMap::new();
+        // satisfier -> dependent package, index into its dependencies
+        let mut rdeps : HashMap<PackageId, Vec<(PackageId, usize)>> = HashMap::new();
+        for pn in &packages {
+            let deps : Vec<Vec<PackageId>> = self.install_dependencies(*pn)
+                .map(|dep| dep.iter().flat_map(|alt| self.alt_satisfiers(alt)).sorted().dedup().collect())
+                .collect();
+            for (i, satisfiers) in deps.iter().enumerate() {
+                for s in satisfiers {
+                    rdeps.entry(*s).or_default().push((*pn, i));
+                }
+            }
+            deps_of.insert(*pn, deps);
+        }
+
+        // marks everything that breaks once the packages in worklist are gone
+        let propagate = |broken: &mut HashSet<PackageId>, baseline: &HashSet<PackageId>, mut worklist: Vec<PackageId>| {
+            while let Some(b) = worklist.pop() {
+                for (dependent, i) in rdeps.get(&b).into_iter().flatten() {
+                    if broken.contains(dependent) || baseline.contains(dependent) { continue; }
+                    let all_broken = deps_of[dependent][*i].iter().all(|s| broken.contains(s) || baseline.contains(s));
+                    if all_broken {
+                        broken.insert(*dependent);
+                        worklist.push(*dependent);
+                    }
+                }
+            }
+        };
+
+        // packages with a dependency nothing satisfies are broken already, and so is what needs them
+        let unsatisfiable : Vec<PackageId> = packages.iter().cloned()
+            .filter(|pn| deps_of[pn].iter().any(|satisfiers| satisfiers.is_empty()))
+            .collect();
+        let mut baseline : HashSet<PackageId> = unsatisfiable.iter().cloned().collect();
+        propagate(&mut baseline, &HashSet::new(), unsatisfiable);
+
+        let mut impact = HashMap::new();
+        for pn in packages {
+            if baseline.contains(&pn) { continue; }
+            let mut broken = HashSet::new();
+            broken.insert(pn);
+            propagate(&mut broken, &baseline, vec![pn]);
+            let count = broken.iter().filter(|b| **b != pn && self.installed_debvers.contains_key(b)).count();
+            if count > 0 {
+                impact.insert(pn, count);
+            }
+        }
+        impact
+    }
+
+    /// The strongly connected components of package_graph with more than one package (dependency cycles),
+    /// largest first, each sorted by name.
+    pub fn dependency_cycles(&self) -> Vec<Vec<PackageId>> {
+        let (graph, _) = self.package_graph();
+        let mut cycles : Vec<Vec<PackageId>> = tarjan_scc(&graph).into_iter()
+            .filter(|scc| scc.len() > 1)
+            .map(|scc| scc.iter().map(|n| graph[*n]).sorted_by_key(|pn| self.get_package_name(*pn)).collect())
+            .collect();
+        cycles.sort_by_key(|scc : &Vec<PackageId>| (std::cmp::Reverse(scc.len()), self.get_package_name(scc[0])));
+        cycles
+    }
+
+    /// Prints dependency cycles, degree distributions, the largest transitive closures, and the
+    /// packages whose removal would break the most installed packages.
+    pub fn print_graph_stats(&self) {
+        let (graph, _) = self.package_graph();
+        println!("Packages: {}, dependency edges: {}", graph.node_count(), graph.edge_count());
+
+        let cycles = self.dependency_cycles();
+        println!("Strongly connected components with more than one package: {}", cycles.len());
+        for scc in cycles.iter().take(TOP_N) {
+            let names : Vec<&str> = scc.iter().map(|pn| self.get_package_name(*pn)).collect();
+            let shown = names.iter().take(TOP_N).join(", ");
+            let more = if names.len() > TOP_N { format!(", ... ({} more)", names.len() - TOP_N) } else { String::new() };
+            println!("  {} packages: {}{}", names.len(), shown, more);
+        }
+
+        let out_degrees : Vec<usize> = graph.node_indices().map(|n| graph.neighbors_directed(n, Direction::Outgoing).count()).collect();
+        let in_degrees : Vec<usize> = graph.node_indices().map(|n| graph.neighbors_directed(n, Direction::Incoming).count()).collect();
+        print_distribution("Out-degree distribution", &out_degrees);
+        print_distribution("In-degree distribution", &in_degrees);
+        let most_depended_on = graph.node_indices()
+            .sorted_by_key(|n| (std::cmp::Reverse(in_degrees[n.index()]), self.get_package_name(graph[*n])))
+            .take(TOP_N)
+            .map(|n| format!("{} ({})", self.get_package_name(graph[n]), in_degrees[n.index()]))
+            .join(", ");
+        println!("Most depended on: {}", most_depended_on);
+
+        // every package in an SCC reaches the same set, so walk once per component
+        let sccs = tarjan_scc(&graph);
+        let mut closure_size = vec![0; graph.node_count()];
+        let mut seen = vec![usize::MAX; graph.node_count()];
+        for (i, scc) in sccs.iter().enumerate() {
+            let mut stack = vec![scc[0]];
+            seen[scc[0].index()] = i;
+            let mut size = 0;
+            while let Some(n) = stack.pop() {
+                size += 1;
+                for next in graph.neighbors_directed(n, Direction::Outgoing) {
+                    if seen[next.index()] != i {
+                        seen[next.index()] = i;
+                        stack.push(next);
+                    }
+                }
+            }
+            // the closure doesn't count the package itself
+            for n in scc {
+                closure_size[n.index()] = size - 1;
+            }
+        }
+        println!("Largest transitive closures:");
+        for n in graph.node_indices()
+            .sorted_by_key(|n| (std::cmp::Reverse(closure_size[n.index()]), self.get_package_name(graph[*n])))
+            .take(TOP_N) {
+            println!("  {}: {}", self.get_package_name(graph[n]), closure_size[n.index()]);
+        }
+
+        let impact = self.removal_impact();
+        println!("Installed packages made uninstallable by removing:");
+        for (pn, count) in impact.iter()
+            .sorted_by_key(|(pn, count)| (std::cmp::Reverse(**count), self.get_package_name(**pn)))
+            .take(TOP_N) {
+            println!("  {}: {}", self.get_package_name(*pn), count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Packages;
+
+    // the same stanzas serve as the status file and the index
+    const PACKAGES : &str = "\
+Package: app\nStatus: install ok installed\nVersion: 1.0\nDepends: awk, libx\n\n\
+Package: tool\nStatus: install ok installed\nVersion: 1.0\nDepends: app | app-ng\n\n\
+Package: mawk\nStatus: install ok installed\nVersion: 1.3\nProvides: awk\n\n\
+Package: libx\nStatus: install ok installed\nVersion: 2.0\nDepends: liby\n\n\
+Package: liby\nStatus: install ok installed\nVersion: 2.0\nDepends: libx\n\n\
+Package: broken\nStatus: install ok installed\nVersion: 1.0\nDepends: ghost, libx\n\n\
+Package: c1\nVersion: 1.0\nDepends: c2\n\n\
+Package: c2\nVersion: 1.0\nDepends: c3\n\n\
+Package: c3\nVersion: 1.0\nDepends: c1, awk\n";
+
+    fn loaded() -> Packages {
+        let mut packages = Packages::new();
+        packages.parse_installed_reader("status", PACKAGES.as_bytes());
+        let package_nums = packages.parse_packages_reader("Packages", PACKAGES.as_bytes());
+        packages.record_candidates(&package_nums, None);
+        packages.select_candidates();
+        packages
+    }
+
+    #[test]
+    fn cycles_are_the_components_with_more_than_one_package() {
+        let packages = loaded();
+        let cycles : Vec<Vec<&str>> = packages.dependency_cycles().iter()
+            .map(|scc| scc.iter().map(|pn| packages.get_package_name(*pn)).collect())
+            .collect();
+        assert_eq!(cycles, [vec!["c1", "c2", "c3"], vec!["libx", "liby"]]);
+    }
+
+    #[test]
+    fn impact_counts_installed_packages_broken_through_providers() {
+        let packages = loaded();
+        let mut impact : Vec<(&str, usize)> = packages.removal_impact().into_iter()
+            .map(|(pn, count)| (packages.get_package_name(pn), count))
+            .collect();
+        impact.sort();
+        // broken needs ghost, which nothing provides, so nothing breaks it; c1-c3 have no Status, so they
+        // count as installed, and c3 needs awk
+        assert_eq!(impact, [("app", 1), ("c1", 2), ("c2", 2), ("c3", 2), ("libx", 3), ("liby", 3), ("mawk", 5)]);
+    }
+}
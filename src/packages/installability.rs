@@ -0,0 +1,199 @@
+use std::collections::{HashMap, HashSet};
+use std::thread;
+
+use itertools::Itertools;
+
+use crate::debversion::DebianVersionNum;
+use crate::Packages;
+use crate::packages::{Dependency, RelVersionedPackageNum};
+use crate::packages::interner::PackageId;
+
+/// Why an available package can't be installed from the available set.
+pub struct Uninstallable {
+    pub package_num : PackageId,
+    /// package_num, then each uninstallable package it needs, down to the one with the blocking dependency
+    pub path : Vec<PackageId>,
+    /// the dependency of the last package in path that no available version satisfies
+    pub dependency : Dependency,
+}
+
+impl Packages {
+    /// The available packages that satisfy alt: the package itself at a suitable version, and those whose
+    /// Provides does.
+    fn alt_providers<'a>(&'a self, alt: &'a RelVersionedPackageNum) -> impl Iterator<Item=PackageId> + 'a {
+        let direct = self.available_debvers.get(&alt.package_num).filter(|v| alt.is_satisfied_by(v)).map(|_| alt.package_num);
+        let provided = self.available_providers(alt.package_num).filter(move |(_, v)| alt.is_satisfied_by_provides(*v));
+        direct.into_iter().chain(provided.map(|(pn, _)| pn))
+    }
+
+    /// The available packages providing package_num, with the provided version if any.
+    fn available_providers(&self, package_num: PackageId) -> impl Iterator<Item=(PackageId, Option<&DebianVersionNum>)> {
+        self.available_provides.get(&package_num).into_iter().flatten().map(|(pn, v)| (*pn, v.as_ref()))
+    }
+
+    /// The available packages that satisfy some alternative of dep, each once.
+    fn dep_providers(&self, dep: &Dependency) -> Vec<PackageId> {
+        let mut providers : Vec<PackageId> = dep.iter().flat_map(|alt| self.alt_providers(alt)).collect();
+        providers.sort();
+        providers.dedup();
+        providers
+    }
+
+    /// Checks whether the dependency closure of available package package_num can be satisfied from
+    /// the available versions alone, ignoring what is installed. A dependency on a virtual package is
+    /// satisfied by an available package providing it, at a suitable version if the dependency has one.
+    /// Returns None if it can.
+    pub fn check_installable(&self, package_num: PackageId) -> Option<Uninstallable> {
+        // everything reachable through alternatives that have a suitable available version
+        let mut closure = vec![package_num];
+        let mut seen : HashSet<PackageId> = closure.iter().cloned().collect();
+        let mut i = 0;
+        while i < closure.len() {
+            for dep in self.install_dependencies(closure[i]) {
+                for provider in self.dep_providers(dep) {
+                    if seen.insert(provider) {
+                        closure.push(provider);
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        // a package is broken once one of its dependencies has no usable, unbroken alternative left;
+        // broken_by remembers which dependency (its index in install_dependencies) did it
+        let mut usable : HashMap<(PackageId, usize), usize> = HashMap::new();
+        let mut dependents : HashMap<PackageId, Vec<(PackageId, usize)>> = HashMap::new();
+        let mut broken_by : HashMap<PackageId, usize> = HashMap::new();
+        let mut worklist = vec![];
+        for pn in &closure {
+            for (d, dep) in self.install_dependencies(*pn).enumerate() {
+                let alts = self.dep_providers(dep);
+                if alts.is_empty() && !broken_by.contains_key(pn) {
+                    broken_by.insert(*pn, d);
+                    worklist.push(*pn);
+                }
+                usable.insert((*pn, d), alts.len());
+                for alt in alts {
+                    dependents.entry(alt).or_default().push((*pn, d));
+                }
+            }
+        }
+        while let Some(b) = worklist.pop() {
+            for (pn, d) in dependents.get(&b).into_iter().flatten() {
+                let left = usable.get_mut(&(*pn, *d)).unwrap();
+                *left -= 1;
+                if *left == 0 && !broken_by.contains_key(pn) {
+                    broken_by.insert(*pn, *d);
+                    worklist.push(*pn);
+                }
+            }
+        }
+        if !broken_by.contains_key(&package_num) {
+            return None;
+        }
+
+        // follow broken alternatives down to a dependency nothing available satisfies; each step goes to a
+        // package that broke earlier, so this ends
+        let mut path = vec![package_num];
+        let mut pn = package_num;
+        loop {
+            let dep = self.install_dependencies(pn).nth(broken_by[&pn]).unwrap();
+            match self.dep_providers(dep).into_iter().find(|p| broken_by.contains_key(p) && !path.contains(p)) {
+                Some(provider) => {
+                    pn = provider;
+                    path.push(pn);
+                }
+                None => return Some(Uninstallable { package_num, path, dependency: dep.clone() }),
+            }
+        }
+    }
+
+    /// Runs check_installable on every available package, spread over jobs threads.
+    /// Returns the uninstallable packages, sorted by name.
+    pub fn installability(&self, jobs: usize) -> Vec<Uninstallable> {
+        let package_nums : Vec<PackageId> = self.available_debvers.keys().cloned().collect();
+        let jobs = jobs.max(1);
+        let mut uninstallable : Vec<Uninstallable> = thread::scope(|s| {
+            let workers : Vec<_> = (0..jobs).map(|j| {
+                let package_nums = &package_nums;
+                s.spawn(move || package_nums.iter().skip(j).step_by(jobs)
+                        .filter_map(|pn| self.check_installable(*pn))
+                        .collect::<Vec<Uninstallable>>())
+            }).collect();
+            workers.into_iter().flat_map(|w| w.join().unwrap()).collect()
+        });
+        uninstallable.sort_by_key(|u| self.get_package_name(u.package_num));
+        uninstallable
+    }
+
+    pub fn print_installability(&self, jobs: usize) {
+        let uninstallable = self.installability(jobs);
+        for u in &uninstallable {
+            let name = self.get_package_name(u.package_num);
+            let blocked = self.get_package_name(*u.path.last().unwrap());
+            let alternatives = u.dependency.iter().map(|alt| {
+                let alt_name = self.get_package_name(alt.package_num);
+                let providers = self.available_providers(alt.package_num).map(|(pn, _)| self.get_package_name(pn)).join(", ");
+                match self.available_debvers.get(&alt.package_num) {
+                    Some(v) => format!("{} {} available", alt_name, v),
+                    None if !providers.is_empty() => format!("{} provided by {}", alt_name, providers),
+                    None => format!("{} not available", alt_name),
+                }
+            }).join(", ");
+            let path = if u.path.len() > 1 {
+                format!("{}; ", u.path.iter().map(|pn| self.get_package_name(*pn)).join(" -> "))
+            } else {
+                String::new()
+            };
+            println!("{} {}: {}{} depends on {}: {}", name, self.available_debvers[&u.package_num], path, blocked,
+                     self.dep2str(&u.dependency), alternatives);
+        }
+        println!("Installable: {}, uninstallable: {}", self.available_debvers.len() - uninstallable.len(), uninstallable.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Packages;
+
+    const PACKAGES : &str = "\
+Package: base-files\nVersion: 12\nDepends: awk\n\n\
+Package: mawk\nVersion: 1.3.4\nProvides: awk\n\n\
+Package: perl-mod\nVersion: 1.0\nDepends: perlapi-5.32.0\n\n\
+Package: perl-base\nVersion: 5.32.1\nProvides: perlapi-5.32.0\n\n\
+Package: app\nVersion: 1.0\nDepends: libfoo-abi (>= 2)\n\n\
+Package: old-app\nVersion: 1.0\nDepends: libfoo-abi (<< 2)\n\n\
+Package: unversioned-app\nVersion: 1.0\nDepends: awk (>= 1)\n\n\
+Package: libfoo\nVersion: 3.0\nProvides: libfoo-abi (= 2)\n";
+
+    fn loaded() -> Packages {
+        let mut packages = Packages::new();
+        let package_nums = packages.parse_packages_reader("Packages", PACKAGES.as_bytes());
+        packages.record_candidates(&package_nums, None);
+        packages.select_candidates();
+        packages
+    }
+
+    fn uninstallable(packages: &Packages) -> Vec<(&str, String)> {
+        packages.installability(2).iter()
+            .map(|u| (packages.get_package_name(u.package_num), packages.dep2str(&u.dependency)))
+            .collect()
+    }
+
+    #[test]
+    fn virtual_packages_are_satisfied_by_providers() {
+        let packages = loaded();
+        // a versioned dependency needs a versioned Provides that satisfies it
+        assert_eq!(uninstallable(&packages), [
+            ("old-app", String::from("libfoo-abi (<< 2)")),
+            ("unversioned-app", String::from("awk (>= 1)")),
+        ]);
+    }
+
+    #[test]
+    fn providers_survive_a_snapshot() {
+        let mut restored = Packages::new();
+        restored.restore_snapshot(&loaded().snapshot()).unwrap();
+        assert_eq!(uninstallable(&restored).len(), 2);
+    }
+}
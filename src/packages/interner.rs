@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// A package name interned in one Packages. Ids are dense, from 0 in the order names were first seen,
+/// and mean nothing to another Packages.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Default)]
+pub struct PackageId(u32);
+
+impl PackageId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for PackageId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Maps package names to dense PackageIds and back.
+#[derive(Default)]
+pub struct Interner {
+    names : Vec<String>,
+    ids : HashMap<String, PackageId>,
+}
+
+impl Interner {
+    /// Returns name's id, giving it the next one if it's new.
+    pub fn intern(&mut self, name: &str) -> PackageId {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = PackageId(self.names.len() as u32);
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    pub fn get(&self, name: &str) -> Option<PackageId> {
+        self.ids.get(name).copied()
+    }
+
+    /// Panics if id comes from another Interner.
+    pub fn name(&self, id: PackageId) -> &str {
+        &self.names[id.index()]
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Every id, in order.
+    pub fn ids(&self) -> impl Iterator<Item=PackageId> {
+        (0..self.names.len() as u32).map(PackageId)
+    }
+
+    /// Every name, in id order.
+    pub fn names(&self) -> impl Iterator<Item=&str> {
+        self.names.iter().map(|n| n.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Packages;
+
+    const PACKAGES : &str = "Package: app\nVersion: 1.0\nDepends: liba, libb\n\nPackage: liba\nVersion: 1.0\n";
+
+    #[test]
+    fn each_packages_numbers_its_own_names() {
+        let (mut a, mut b) = (Packages::new(), Packages::new());
+        let a_foo = a.get_package_num_inserting("foo");
+        let a_bar = a.get_package_num_inserting("bar");
+        let b_bar = b.get_package_num_inserting("bar");
+        let b_foo = b.get_package_num_inserting("foo");
+        assert_eq!([a_foo.index(), a_bar.index(), b_bar.index(), b_foo.index()], [0, 1, 0, 1]);
+        assert_eq!((a.get_package_name(a_bar), b.get_package_name(b_bar)), ("bar", "bar"));
+        assert_eq!(a.get_package_num_inserting("foo"), a_foo);
+        assert_eq!(a.package_count(), 2);
+    }
+
+    #[test]
+    fn loads_into_one_packages_leave_another_alone() {
+        let mut a = Packages::new();
+        a.parse_packages_reader("Packages", PACKAGES.as_bytes());
+        let mut b = Packages::new();
+        b.get_package_num_inserting("unrelated");
+        b.parse_packages_reader("Packages", PACKAGES.as_bytes());
+        a.parse_packages_reader("Packages", "Package: other\nVersion: 2.0\n".as_bytes());
+
+        let ids = |p: &Packages| p.get_package_names().into_iter().map(|n| (n.to_string(), p.get_package_num(n).index())).collect::<Vec<_>>();
+        assert_eq!(ids(&a), [("app", 0), ("liba", 1), ("libb", 2), ("other", 3)].map(|(n, i)| (n.to_string(), i)));
+        assert_eq!(ids(&b), [("unrelated", 0), ("app", 1), ("liba", 2), ("libb", 3)].map(|(n, i)| (n.to_string(), i)));
+    }
+}
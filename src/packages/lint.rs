@@ -0,0 +1,202 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::debversion::{self, VersionRelation};
+use crate::Packages;
+use crate::packages::output::{LintProblemReport, LintReport};
+use crate::packages::stanza::{self, Field, Stanza, Stanzas};
+
+const MANDATORY_FIELDS : [&str; 5] = ["Package", "Version", "Architecture", "Maintainer", "Description"];
+// relationship fields, and whether they may have alternatives
+const RELATION_FIELDS : [(&str, bool); 9] = [
+    ("Pre-Depends", true), ("Depends", true), ("Recommends", true), ("Suggests", true), ("Enhances", false),
+    ("Breaks", false), ("Conflicts", false), ("Replaces", false), ("Provides", false),
+];
+
+/// A problem lint found, at a line of the file.
+pub struct LintProblem {
+    pub line : usize,
+    // None when the stanza has no Package field
+    pub package : Option<String>,
+    pub message : String,
+}
+
+pub struct LintSummary {
+    pub stanzas : usize,
+    pub problems : Vec<LintProblem>,
+}
+
+struct Linter {
+    // every package name that a dependency can be satisfied by
+    known : HashSet<String>,
+    problems : Vec<LintProblem>,
+}
+
+impl Linter {
+    fn report(&mut self, line: usize, stanza: &Stanza, message: String) {
+        let package = stanza.get("Package").map(|p| p.value.to_string());
+        self.problems.push(LintProblem { line, package, message });
+    }
+
+    fn check_stanza(&mut self, stanza: &Stanza, seen: &mut HashMap<(String, String, String), usize>) {
+        for (line, message) in &stanza.problems {
+            self.report(*line, stanza, message.clone());
+        }
+        if stanza.fields.is_empty() {
+            return;
+        }
+        for name in MANDATORY_FIELDS.iter() {
+            if stanza.get(name).is_none() {
+                self.report(stanza.line, stanza, format!("missing {}", name));
+            }
+        }
+        if let Some(package) = stanza.get("Package") {
+            if !stanza::is_package_name(package.value) {
+                self.report(package.line, stanza, format!("invalid package name {:?}", package.value));
+            }
+        }
+        if let Some(version) = stanza.get("Version") {
+            if let Err(e) = debversion::check_version_syntax(version.value) {
+                self.report(version.line, stanza, format!("invalid version {:?}: {}", version.value, e));
+            }
+        }
+        let value = |name: &str| stanza.get(name).map(|f| f.value.to_string()).unwrap_or_default();
+        let key = (value("Package"), value("Version"), value("Architecture"));
+        match seen.get(&key) {
+            Some(first) if !key.0.is_empty() =>
+                self.report(stanza.line, stanza, format!("duplicate of the stanza at line {}", first)),
+            _ => { seen.insert(key, stanza.line); }
+        }
+        for (name, alternatives_allowed) in RELATION_FIELDS.iter() {
+            if let Some(field) = stanza.get(name) {
+                self.check_relations(stanza, field, *alternatives_allowed);
+            }
+        }
+    }
+
+    fn check_relations(&mut self, stanza: &Stanza, field: &Field, alternatives_allowed: bool) {
+        let own_name = stanza.get("Package").map(|p| p.value);
+        let needs_existing = field.name == "Depends" || field.name == "Pre-Depends";
+        for relation in field.value.split(',') {
+            let alternatives = match check_relation(field.name, relation) {
+                Err(e) => { self.report(field.line, stanza, format!("{}: {}", field.name, e)); continue }
+                Ok(alternatives) => alternatives
+            };
+            if alternatives.len() > 1 && !alternatives_allowed {
+                self.report(field.line, stanza, format!("{}: alternatives aren't allowed: {:?}", field.name, relation.trim()));
+            }
+            if !needs_existing {
+                continue;
+            }
+            let names : Vec<&str> = alternatives.iter().map(|(name, _)| *name).collect();
+            if names.iter().any(|n| Some(*n) == own_name) {
+                self.report(field.line, stanza, format!("{} on itself: {:?}", field.name, relation.trim()));
+            } else if !names.iter().any(|n| self.known.contains(*n)) {
+                self.report(field.line, stanza, format!("{} on {:?}, but no package is called that or provides it",
+                                                        field.name, relation.trim()));
+            }
+        }
+    }
+}
+
+/// Checks one relation of a relationship field, strictly; returns its alternatives.
+fn check_relation<'a>(field: &str, relation: &'a str) -> Result<Vec<stanza::Relation<'a>>, String> {
+    if relation.trim().is_empty() {
+        return Err(String::from("empty relation"));
+    }
+    let alternatives = stanza::parse_relations(relation, true, &mut vec![])?.remove(0);
+    if field == "Provides" && alternatives.iter().any(|(_, rel_version)| matches!(rel_version, Some((op, _)) if *op != VersionRelation::Equal)) {
+        return Err(format!("only \"=\" is allowed in Provides: {:?}", relation.trim()));
+    }
+    Ok(alternatives)
+}
+
+impl Packages {
+    /// Names of everything loaded that could satisfy a dependency: packages with an available or installed
+    /// version, and whatever those provide.
+    fn known_package_names(&self) -> HashSet<String> {
+        let mut known : HashSet<String> = HashSet::new();
+        for pn in self.available_debvers.keys().chain(self.installed_debvers.keys()) {
+            known.insert(self.get_package_name(*pn).to_string());
+            known.extend(self.provides_field(*pn).map(String::from));
+        }
+        for (pn, providers) in &self.installed_provides {
+            if !providers.is_empty() {
+                known.insert(self.get_package_name(*pn).to_string());
+            }
+        }
+        known
+    }
+
+    /// Checks a Packages file before it's published: mandatory fields, version syntax, relationship syntax,
+    /// self-dependencies, duplicate stanzas, and dependencies on packages that neither the file nor what's
+    /// loaded has or provides. Doesn't load anything.
+    pub fn lint(&self, filename: &str) -> Result<LintSummary, String> {
+        let data = std::fs::read(filename).map_err(|e| format!("can't read {}: {}", filename, e))?;
+        let stanzas : Vec<Stanza> = Stanzas::new(&data).collect();
+
+        let mut known = self.known_package_names();
+        for stanza in &stanzas {
+            known.extend(stanza.get("Package").map(|p| p.value.to_string()));
+            if let Some(provides) = stanza.get("Provides") {
+                known.extend(provides.value.split(',')
+                    .filter_map(|p| p.split(|c: char| c.is_whitespace() || c == '(').find(|n| !n.is_empty()))
+                    .map(String::from));
+            }
+        }
+        let mut linter = Linter { known, problems: vec![] };
+        let mut seen = HashMap::new();
+        for stanza in &stanzas {
+            linter.check_stanza(stanza, &mut seen);
+        }
+        linter.problems.sort_by_key(|p| p.line);
+        Ok(LintSummary { stanzas: stanzas.len(), problems: linter.problems })
+    }
+
+    pub fn print_lint(&self, filename: &str) {
+        let summary = match self.lint(filename) {
+            Err(e) => { self.fail(&format!("Error: {}", e)); return }
+            Ok(s) => s
+        };
+        let failed = !summary.problems.is_empty();
+        let report = LintReport {
+            file: filename.to_string(),
+            stanzas: summary.stanzas,
+            problems: summary.problems.into_iter()
+                .map(|p| LintProblemReport { line: p.line, package: p.package, message: p.message })
+                .collect(),
+            status: String::from(if failed { "failed" } else { "ok" }),
+        };
+        if !self.emit_json(&report) {
+            for p in &report.problems {
+                match &p.package {
+                    None => println!("{}:{}: {}", filename, p.line, p.message),
+                    Some(package) => println!("{}:{}: {}: {}", filename, p.line, package, p.message),
+                }
+            }
+            println!("lint: stanzas={} problems={} status={}", report.stanzas, report.problems.len(), report.status);
+        }
+        if failed {
+            self.mark_failed();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crate::Packages;
+
+    const STANZAS : &str = "Package: Bad_Name\nVersion: 1.0\nArchitecture: all\nMaintainer: A <a@example.org>\nDescription: x\n\n\
+                            Package: good\nVersion: 1.0\nArchitecture: all\nMaintainer: A <a@example.org>\nDescription: x\n";
+
+    #[test]
+    fn package_name_is_checked() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(STANZAS.as_bytes()).unwrap();
+        let summary = Packages::new().lint(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(summary.stanzas, 2);
+        let problems : Vec<(usize, &str)> = summary.problems.iter().map(|p| (p.line, p.message.as_str())).collect();
+        assert_eq!(problems, [(1, "invalid package name \"Bad_Name\"")]);
+    }
+}
@@ -1,89 +1,323 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::path::Path;
-use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use itertools::Itertools;
 
-use rpkg::debversion;
-use rpkg::debversion::{DebianVersionNum,VersionRelation};
+use crate::debversion;
+use crate::debversion::{DebianVersionNum,VersionRelation};
 
 mod deps_available;
 mod solvers;
 mod parsers;
 mod async_fns;
+mod sources;
+pub mod release;
+pub mod repo;
+pub mod preferences;
+pub mod ordering;
+pub mod graph;
+mod graph_stats;
+pub mod check;
+pub mod lint;
+pub mod stanza;
+pub mod installability;
+pub mod search;
+pub mod query;
+mod snapshot;
+mod diff;
+pub mod interner;
+pub mod output;
 
 use crate::packages::async_fns::AsyncState;
-
-static PACKAGE_COUNTER: AtomicI32 = AtomicI32::new(0);
+use crate::packages::release::Release;
+use crate::packages::repo::PackageOrigin;
+use crate::packages::preferences::Pin;
+use crate::packages::output::*;
+use crate::packages::search::SearchIndex;
+use crate::packages::interner::{Interner, PackageId};
 
 pub struct Packages {
-    dependencies : HashMap<i32,Vec<Dependency>>,
-    md5sums : HashMap<i32,String>,
-    available_debvers : HashMap<i32,DebianVersionNum>,
-    installed_debvers : HashMap<i32,DebianVersionNum>,
-    package_name_to_num : HashMap<String, i32>,
-    package_num_to_name : HashMap<i32, String>,
+    dependencies : HashMap<PackageId,Vec<Dependency>>,
+    pre_dependencies : HashMap<PackageId,Vec<Dependency>>,
+    md5sums : HashMap<PackageId,String>,
+    available_debvers : HashMap<PackageId,DebianVersionNum>,
+    installed_debvers : HashMap<PackageId,DebianVersionNum>,
+    // what the selected available version of a package provides, with the provided version if any
+    provides : HashMap<PackageId,Vec<(PackageId, Option<DebianVersionNum>)>>,
+    // virtual package -> available packages providing it; select_candidates keeps it in step with provides
+    available_provides : HashMap<PackageId,Vec<(PackageId, Option<DebianVersionNum>)>>,
+    // Pre-Depends and Depends of the installed version, from the status file
+    installed_dependencies : HashMap<PackageId,Vec<Dependency>>,
+    // virtual package -> installed packages providing it, with the provided version if any
+    installed_provides : HashMap<PackageId,Vec<(PackageId, Option<DebianVersionNum>)>>,
+    // every field of the selected available stanza, and of the installed one, for search
+    available_fields : HashMap<PackageId, Fields>,
+    installed_fields : HashMap<PackageId, Fields>,
+    search_index : SearchIndex,
+    // every package name seen, with the dense ids the maps here are keyed by
+    package_ids : Interner,
+    installed_sources : HashMap<PackageId, SourceRef>,
+    available_sources : HashMap<PackageId, SourceRef>,
+    // source name -> installed or available binaries built from it, sorted by name; kept up to date by
+    // select_candidates, and not saved in snapshots
+    source_binaries : HashMap<String, Vec<PackageId>>,
+    release : Option<Release>,
+    package_origins : HashMap<PackageId, PackageOrigin>,
+    available_candidates : HashMap<PackageId, Vec<Candidate>>,
+    preferences : Vec<Pin>,
+    // problems found in the files loaded, those of each file's latest load only
+    load_diagnostics : Vec<ParseDiagnostic>,
+    // whether loading stops at the first problem instead of skipping the stanza or warning
+    strict : bool,
     async_state : AsyncState,
+    // set when a command fails; non-interactive runs turn it into the exit status
+    failed : AtomicBool,
+    format : OutputFormat,
+}
+
+// Source: field of a binary stanza, e.g. "Source: aisleriot (1:3.22.9-1)".
+// version is None when the field omits it, meaning "same as the binary version".
+#[derive(Clone)]
+pub struct SourceRef {
+    name : String,
+    version : Option<DebianVersionNum>,
+}
+
+// One available version of a package, as read from some Packages index. The fields of Packages
+// describing available versions (available_debvers, dependencies, ...) hold the selected candidate.
+pub struct Candidate {
+    version : DebianVersionNum,
+    md5sum : Option<String>,
+    dependencies : Vec<Dependency>,
+    pre_dependencies : Vec<Dependency>,
+    provides : Vec<(PackageId, Option<DebianVersionNum>)>,
+    source : Option<SourceRef>,
+    origin : Option<PackageOrigin>,
+    fields : Fields,
+}
+
+// the fields of a stanza in order, with continuation lines joined by newlines
+pub type Fields = Vec<(String, String)>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    // something accepted, but that strict mode rejects
+    Warning,
+    // something that made the parser skip the stanza
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self { Severity::Warning => "warning", Severity::Error => "error" })
+    }
+}
+
+/// A problem found while loading a file, with where it is.
+#[derive(Clone)]
+pub struct ParseDiagnostic {
+    pub file : String,
+    pub line : usize,
+    // the Package of the stanza, when it has one
+    pub package : Option<String>,
+    pub severity : Severity,
+    pub message : String,
+}
+
+impl fmt::Display for ParseDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}: ", self.file, self.line, self.severity)?;
+        if let Some(package) = &self.package {
+            write!(f, "package {}: ", package)?;
+        }
+        f.write_str(&self.message)
+    }
 }
 
 // Dependency([X, Y, Z]) means X|Y|Z
+#[derive(Clone)]
 pub struct RelVersionedPackageNum {
-    package_num : i32,
+    package_num : PackageId,
     rel_version : Option<(VersionRelation, String)>
 }
 pub type Dependency = Vec<RelVersionedPackageNum>;
 
+impl RelVersionedPackageNum {
+    /// Whether version v of this package satisfies the version constraint, if any.
+    fn is_satisfied_by(&self, v: &DebianVersionNum) -> bool {
+        match &self.rel_version {
+            None => true,
+            Some((op, ver)) => match ver.parse::<DebianVersionNum>() {
+                Err(_) => false,
+                Ok(ver) => debversion::cmp_debversion_with_op(op, v, &ver),
+            }
+        }
+    }
+
+    /// Whether a Provides of this package, at version provided if it has one, satisfies the version
+    /// constraint; a versioned dependency needs a versioned Provides.
+    fn is_satisfied_by_provides(&self, provided: Option<&DebianVersionNum>) -> bool {
+        match (&self.rel_version, provided) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(_), Some(v)) => self.is_satisfied_by(v),
+        }
+    }
+}
+
+impl Default for Packages {
+    fn default() -> Packages {
+        Packages::new()
+    }
+}
+
 impl Packages {
     pub fn new() -> Packages {
         Packages { 
             dependencies : HashMap::new(), 
+            pre_dependencies : HashMap::new(),
             md5sums : HashMap::new(),
             available_debvers : HashMap::new(),
             installed_debvers : HashMap::new(),
-            package_name_to_num : HashMap::new(), 
-            package_num_to_name : HashMap::new(),
+            provides : HashMap::new(),
+            available_provides : HashMap::new(),
+            installed_dependencies : HashMap::new(),
+            installed_provides : HashMap::new(),
+            available_fields : HashMap::new(),
+            installed_fields : HashMap::new(),
+            search_index : SearchIndex::default(),
+            package_ids : Interner::default(),
+            installed_sources : HashMap::new(),
+            available_sources : HashMap::new(),
+            source_binaries : HashMap::new(),
+            release : None,
+            package_origins : HashMap::new(),
+            available_candidates : HashMap::new(),
+            preferences : vec![],
+            load_diagnostics : vec![],
+            strict : false,
             async_state : AsyncState::new(),
+            failed : AtomicBool::new(false),
+            format : OutputFormat::Text,
         }
     }
 
+    /// Marks the current command as failed.
+    fn mark_failed(&self) {
+        self.failed.store(true, Ordering::SeqCst);
+    }
+
+    /// Prints msg to stderr (as {"error": ...} in JSON mode) and marks the current command as failed.
+    fn fail(&self, msg: &str) {
+        let error = msg.strip_prefix("Error: ").unwrap_or(msg);
+        if self.format == OutputFormat::Json {
+            eprintln!("{}", serde_json::to_string(&ErrorReport { error }).unwrap());
+        } else {
+            eprintln!("{}", msg);
+        }
+        self.mark_failed();
+    }
+
+    /// Prints what loading a file did: to stdout, or to stderr in JSON mode so stdout stays valid JSON.
+    fn progress(&self, msg: &str) {
+        if self.format == OutputFormat::Json {
+            eprintln!("{}", msg);
+        } else {
+            println!("{}", msg);
+        }
+    }
+
+    /// In strict mode, loading a file stops at its first problem and fails, where it otherwise skips a broken
+    /// stanza or warns about a relation that uses a deprecated operator or unusual spacing.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// The problems found in the files loaded; for a file loaded more than once, those of its latest load.
+    pub fn load_diagnostics(&self) -> &[ParseDiagnostic] {
+        &self.load_diagnostics
+    }
+
+    /// Forgets the problems of an earlier load of filename; returns the number of problems left, after which
+    /// this load's go.
+    fn begin_load(&mut self, filename: &str) -> usize {
+        self.load_diagnostics.retain(|d| d.file != filename);
+        self.load_diagnostics.len()
+    }
+
+    /// In strict mode, returns the first problem of the load that started at diagnostic number since, as an error.
+    fn check_strict(&self, since: usize) -> Result<(), String> {
+        match self.load_diagnostics.get(since) {
+            Some(d) if self.strict => Err(d.to_string()),
+            _ => Ok(())
+        }
+    }
+
+    /// Says how many stanzas of filename were skipped and how many warnings it got, if any.
+    fn print_load_summary(&self, filename: &str) {
+        let count = |severity| self.load_diagnostics.iter().filter(|d| d.file == filename && d.severity == severity).count();
+        let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+        if errors + warnings > 0 {
+            self.progress(&format!("Warning: {}: skipped={} warnings={}; see load-errors", filename, errors, warnings));
+        }
+    }
+
+    /// Prints the problems found in the files loaded, then a key=value summary.
+    pub fn print_load_errors(&self) {
+        for d in &self.load_diagnostics {
+            println!("{}", d);
+        }
+        let errors = self.load_diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+        println!("load-errors: errors={} warnings={}", errors, self.load_diagnostics.len() - errors);
+    }
+
+    /// Returns whether a command failed since the last call, and clears the flag.
+    pub fn take_failure(&self) -> bool {
+        self.failed.swap(false, Ordering::SeqCst)
+    }
+
     // next few functions manipulate the list of packages and the name/number interface
     pub fn get_package_names(&self) -> Vec<&str> {
-        self.package_name_to_num.keys().map(|x| &x[..]).collect()
+        self.package_ids.names().collect()
+    }
+
+    /// The number of package names known, without collecting them like get_package_names.
+    pub fn package_count(&self) -> usize {
+        self.package_ids.len()
     }
 
-    fn get_package_name(&self, package_num: i32) -> &str {
-        return self.package_num_to_name.get(&package_num).unwrap();
+    /// The name of a package id returned by search, query and the like.
+    pub fn get_package_name(&self, package_num: PackageId) -> &str {
+        self.package_ids.name(package_num)
     }
 
     // panics if package_name doesn't already exist
-    fn get_package_num(&self, package_name: &str) -> &i32 {
-        return self.package_name_to_num.get(package_name).unwrap();
-    }
-
-    // inserts package_name into package_name_to_num if it doesn't already exist
-    fn get_package_num_inserting(&mut self, package_name: &str) -> i32 {
-        if !self.package_name_to_num.contains_key(package_name) {
-            let pnum = PACKAGE_COUNTER.load(Ordering::SeqCst);
-            self.package_name_to_num.insert(String::from(package_name), pnum);
-            self.package_num_to_name.insert(pnum, String::from(package_name));
-            self.dependencies.insert(pnum, vec![]);
-            PACKAGE_COUNTER.fetch_add(1, Ordering::SeqCst);
+    fn get_package_num(&self, package_name: &str) -> PackageId {
+        self.package_ids.get(package_name).unwrap()
+    }
+
+    // interns package_name if it doesn't already exist
+    fn get_package_num_inserting(&mut self, package_name: &str) -> PackageId {
+        if let Some(pnum) = self.package_ids.get(package_name) {
             return pnum;
-        } else {
-            return *self.package_name_to_num.get(package_name).unwrap();
         }
+        let pnum = self.package_ids.intern(package_name);
+        self.dependencies.insert(pnum, vec![]);
+        pnum
     }
 
     pub fn package_exists(&self, package_name: &str) -> bool {
-        return self.package_name_to_num.contains_key(package_name);
+        self.package_ids.get(package_name).is_some()
     }
 
     // accessor methods for various maps
     pub fn get_available_debver(&self, package_name: &str) -> Option<&DebianVersionNum> {
-        let package_num = self.package_name_to_num.get(package_name);
-        return match package_num {
+        let package_num = self.package_ids.get(package_name);
+        match package_num {
             None => None,
-            Some(x) => match self.available_debvers.get(x) {
+            Some(x) => match self.available_debvers.get(&x) {
                 None => None,
                 Some(y) => Some(y)
             }
@@ -91,10 +325,10 @@ impl Packages {
     }
 
     pub fn get_installed_debver(&self, package_name: &str) -> Option<&DebianVersionNum> {
-        let package_num = self.package_name_to_num.get(package_name);
-        return match package_num {
+        let package_num = self.package_ids.get(package_name);
+        match package_num {
             None => None,
-            Some(x) => match self.installed_debvers.get(x) {
+            Some(x) => match self.installed_debvers.get(&x) {
                 None => None,
                 Some(y) => Some(y)
             }
@@ -102,84 +336,156 @@ impl Packages {
     }
 
     pub fn get_md5sum(&self, package_name: &str) -> Option<&str> {
-        let package_num = self.package_name_to_num.get(package_name);
-        return match package_num {
+        let package_num = self.package_ids.get(package_name);
+        match package_num {
             None => { None },
-            Some(x) => match self.md5sums.get(x) {
+            Some(x) => match self.md5sums.get(&x) {
                 None => { None },
                 Some(y) => Some(y)
             }
         }
     }
 
-    // helper functions; these aren't structs so I can't make them implement Fmt::Display.
-    fn deps2str(&self, deps: &Vec<Dependency>) -> String {
-        return deps.iter().map(|dep| self.dep2str(dep)).format(", ").to_string();
+    /// The value of field key (compared case-insensitively) of the available stanza of package_num,
+    /// or else of its installed one.
+    pub fn get_field(&self, package_num: PackageId, key: &str) -> Option<&str> {
+        [&self.available_fields, &self.installed_fields].iter()
+            .filter_map(|fields| fields.get(&package_num))
+            .find_map(|fields| fields.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)))
+            .map(|(_, v)| v.as_str())
     }
 
+    // helper functions; these aren't structs so I can't make them implement Fmt::Display.
     fn dep2str(&self, dep: &Dependency) -> String {
-        return dep.iter().map(|d| {
+        dep.iter().map(|d| {
             let pn = self.get_package_name(d.package_num);
             match &d.rel_version {
                 None => String::from(pn),
-                Some((rel, ver)) => format!("{} ({} {})", pn, rel.to_string(), ver)
+                Some((rel, ver)) => format!("{} ({} {})", pn, rel, ver)
             }
-        }).format(" | ").to_string();
+        }).format(" | ").to_string()
     }
 
-    // output commands
-    pub fn print_deps(&self, package_name: &str) {
+    // queries behind the output commands; each returns a report that prints as text or JSON, and is
+    // what library users call instead of the print_ functions
+    pub fn deps_report(&self, package_name: &str) -> Result<DepsReport, String> {
         if !self.package_exists(package_name) {
-            println!("no such package {}", package_name);
-            return;
+            return Err(format!("no such package {}", package_name));
         }
-        let deps : &Vec<Dependency> = &*self.dependencies.get(self.get_package_num(package_name)).unwrap();
-        println!("{:?} depends on {:?}", package_name, self.deps2str(deps));
+        let deps : &Vec<Dependency> = self.dependencies.get(&self.get_package_num(package_name)).unwrap();
+        Ok(DepsReport {
+            package: package_name.to_string(),
+            depends: deps.iter().map(|dep| self.dependency_report(dep)).collect(),
+        })
     }
 
-    pub fn print_transitive_dep_solution(&self, package_name: &str) {
+    pub fn transitive_dep_solution_report(&self, package_name: &str) -> Result<TransitiveDepSolutionReport, String> {
         if !self.package_exists(package_name) {
-            println!("no such package {}", package_name);
-            return;
+            return Err(format!("no such package {}", package_name));
         }
-        let dep_solution : Vec<i32> = self.transitive_dep_solution(package_name);
-        println!("{:?} transitive dependency solution: {:?}", package_name, dep_solution.iter().map(|dep| self.get_package_name(*dep)).format(", ").to_string());
+        let dep_solution : Vec<PackageId> = self.transitive_dep_solution(package_name);
+        Ok(TransitiveDepSolutionReport {
+            package: package_name.to_string(),
+            solution: dep_solution.iter().map(|dep| self.get_package_name(*dep).to_string()).collect(),
+        })
     }
 
-    pub fn print_how_to_install(&self, package_name: &str) {
+    pub fn how_to_install_report(&self, package_name: &str) -> Result<InstallPlan, String> {
         if !self.package_exists(package_name) {
-            println!("no such package {}", package_name);
-            return;
+            return Err(format!("no such package {}", package_name));
         }
-        println!("Package {}:", package_name);
-        let pkgs_to_install:Vec<i32> = self.compute_how_to_install(package_name);
-        println!("{:?} to install: {:?}", package_name, pkgs_to_install.iter().map(|dep| self.get_package_name(*dep)).format(", ").to_string());
+        let pkgs_to_install : Vec<PackageId> = self.compute_how_to_install(package_name)
+            .map_err(|e| format!("{:?} can't be installed: {}", package_name, e))?;
+        Ok(InstallPlan {
+            package: package_name.to_string(),
+            to_install: pkgs_to_install.iter().map(|dep| self.get_package_name(*dep).to_string()).collect(),
+        })
     }
 
-    pub fn print_info(&self, package_name: &str) {
+    pub fn info_report(&self, package_name: &str) -> Result<PackageInfo, String> {
         if !self.package_exists(package_name) {
-            println!("no such package {}", package_name);
-            return;
+            return Err(format!("no such package {}", package_name));
         }
-        println!("Package: {}", package_name);
         let a = self.get_available_debver(package_name);
         let i = self.get_installed_debver(package_name);
-        match a {
-            None => (),
-            Some(a) => {
-                println!("Version: {}", a.to_string());
-                println!("MD5Sum: {}", self.get_md5sum(package_name).unwrap().to_string());
-                println!("Depends: {}", self.deps2str(&*self.dependencies.get(self.get_package_num(package_name)).unwrap()));
+        let depends = match a {
+            None => vec![],
+            Some(_) => self.dependencies.get(&self.get_package_num(package_name)).unwrap()
+                .iter().map(|dep| self.dependency_report(dep)).collect(),
+        };
+        let repository = match a {
+            None => None,
+            Some(_) => self.get_package_origin(package_name).map(|o| RepositoryReport {
+                origin: o.origin.clone(), suite: o.suite.clone(), component: o.component.clone(), architecture: o.architecture.clone(),
+            }),
+        };
+        Ok(PackageInfo {
+            package: package_name.to_string(),
+            version: a.map(|v| v.to_string()),
+            md5sum: a.and(self.get_md5sum(package_name)).map(String::from),
+            depends,
+            repository,
+            installed_version: i.map(|v| v.to_string()),
+            newer_available: match (a, i) {
+                (Some(_), Some(_)) => Some(self.is_upgradable(self.get_package_num(package_name))),
+                _ => None
+            },
+        })
+    }
+
+    // output commands
+    pub fn print_deps(&self, package_name: &str) {
+        let report = match self.deps_report(package_name) {
+            Err(e) => { self.fail(&e); return }
+            Ok(r) => r
+        };
+        if self.emit_json(&report) { return; }
+        println!("{:?} depends on {:?}", package_name, format_dependencies(&report.depends));
+    }
+
+    pub fn print_transitive_dep_solution(&self, package_name: &str) {
+        let report = match self.transitive_dep_solution_report(package_name) {
+            Err(e) => { self.fail(&e); return }
+            Ok(r) => r
+        };
+        if self.emit_json(&report) { return; }
+        println!("{:?} transitive dependency solution: {:?}", package_name, report.solution.join(", "));
+    }
+
+    pub fn print_how_to_install(&self, package_name: &str) {
+        if self.format == OutputFormat::Text && self.package_exists(package_name) {
+            println!("Package {}:", package_name);
+        }
+        let report = match self.how_to_install_report(package_name) {
+            Err(e) => { self.fail(&e); return }
+            Ok(r) => r
+        };
+        if self.emit_json(&report) { return; }
+        println!("{:?} to install: {:?}", package_name, report.to_install.join(", "));
+    }
+
+    pub fn print_info(&self, package_name: &str) {
+        let report = match self.info_report(package_name) {
+            Err(e) => { self.fail(&e); return }
+            Ok(r) => r
+        };
+        if self.emit_json(&report) { return; }
+        println!("Package: {}", package_name);
+        if let Some(version) = &report.version {
+            println!("Version: {}", version);
+            if let Some(md5sum) = &report.md5sum {
+                println!("MD5Sum: {}", md5sum);
+            }
+            println!("Depends: {}", format_dependencies(&report.depends));
+            if let Some(r) = &report.repository {
+                println!("Repository: {} {}/{} {}", r.origin, r.suite, r.component, r.architecture);
             }
         }
-        match i {
-            None => (),
-            Some(i) => { println!("Installed-Version: {}", i.to_string()) }
+        if let Some(installed_version) = &report.installed_version {
+            println!("Installed-Version: {}", installed_version);
         }
-        match (a, i) {
-            (Some(aa), Some(ii)) =>
-                { println!("Newer-Available: {:?}", aa > ii); }
-            _ => ()
+        if let Some(newer) = report.newer_available {
+            println!("Newer-Available: {:?}", newer);
         }
     }
 
@@ -188,12 +494,9 @@ impl Packages {
         let path = Path::new(fname);
         let mut md5s : String = "name,version,hash\n".to_owned();
         for pn in self.get_package_names() {
-            match (self.get_available_debver(pn), self.get_md5sum(pn)) {
-                (Some(v), Some(m)) => {
-                    let row = format!("{},{},{}\n",pn,v.to_string(),m);
-                    md5s.push_str(&row)
-                }
-                (_, _) => ()
+            if let (Some(v), Some(m)) = (self.get_available_debver(pn), self.get_md5sum(pn)) {
+                let row = format!("{},{},{}\n",pn,v,m);
+                md5s.push_str(&row)
             }
         }
         std::fs::write(path, md5s).unwrap();
@@ -201,7 +504,10 @@ impl Packages {
 
     // provided parse function to let students do the async io part independently
     pub fn parse_csv(&mut self, filename: &str) {
-        let mut rdr = csv::Reader::from_path(filename).unwrap();
+        let mut rdr = match csv::Reader::from_path(filename) {
+            Err(e) => { self.fail(&format!("Error: can't read {}: {}", filename, e)); return }
+            Ok(rdr) => rdr
+        };
         for line in rdr.records() {
             let line = line.unwrap();
             let package_name = String::from(line.get(0).unwrap());
@@ -213,7 +519,9 @@ impl Packages {
             self.md5sums.insert(package_num, md5sum);
 
         }
+        self.index_sources();
+        self.build_search_index();
 
-        println!("Packages available: {}", self.available_debvers.keys().len());
+        self.progress(&format!("Packages available: {}", self.available_debvers.keys().len()));
     }
 }
@@ -0,0 +1,246 @@
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+use itertools::Itertools;
+use petgraph::algo::tarjan_scc;
+use petgraph::graph::{Graph, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+
+use crate::Packages;
+use crate::packages::interner::PackageId;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DepKind {
+    PreDepends,
+    Depends,
+}
+
+/// A dependency cycle in an install plan, and the Depends edge dropped to break it.
+/// cycle reads "cycle[0] depends on cycle[1] depends on ... depends on cycle[0]".
+pub struct BrokenCycle {
+    pub cycle : Vec<PackageId>,
+    pub depender : PackageId,
+    pub dependency : PackageId,
+}
+
+/// An order dpkg can process an install plan in: packages needed by a Pre-Depends are unpacked and
+/// configured first (preconfigure, in order), then the rest are all unpacked (unpack, which only
+/// Pre-Depends constrain), then all configured (configure, each after everything it depends on).
+pub struct InstallOrder {
+    pub preconfigure : Vec<PackageId>,
+    pub unpack : Vec<PackageId>,
+    pub configure : Vec<PackageId>,
+    pub broken_cycles : Vec<BrokenCycle>,
+}
+
+impl Packages {
+    /// Builds the graph of Pre-Depends/Depends edges between the packages of plan. An edge runs from
+    /// a dependency to the package that needs it, so a topological order lists dependencies first.
+    fn plan_graph(&self, plan: &[PackageId]) -> (Graph<PackageId, DepKind>, HashMap<PackageId, NodeIndex>) {
+        let mut graph = Graph::new();
+        let mut nodes = HashMap::new();
+        for package_num in plan {
+            nodes.insert(*package_num, graph.add_node(*package_num));
+        }
+        for package_num in plan {
+            let pre_deps = self.pre_dependencies.get(package_num).into_iter().flatten().map(|d| (DepKind::PreDepends, d));
+            let deps = self.dependencies.get(package_num).into_iter().flatten().map(|d| (DepKind::Depends, d));
+            for (kind, dep) in pre_deps.chain(deps) {
+                // the alternative the plan chose, if any
+                let target = match dep.iter().find(|alt| nodes.contains_key(&alt.package_num)) {
+                    None => continue,
+                    Some(alt) => alt.package_num
+                };
+                if target == *package_num { continue; }
+                let (from, to) = (nodes[&target], nodes[package_num]);
+                match graph.find_edge(from, to) {
+                    None => { graph.add_edge(from, to, kind); }
+                    Some(e) => if kind == DepKind::PreDepends { graph[e] = DepKind::PreDepends; }
+                }
+            }
+        }
+        (graph, nodes)
+    }
+
+    /// Orders nodes so that each comes after the nodes it has a `follow` edge from, keeping plan
+    /// order where that leaves a choice. Nodes were added to the graph in plan order.
+    fn order_nodes(graph: &Graph<PackageId, DepKind>, nodes: &HashSet<NodeIndex>, follow: impl Fn(DepKind) -> bool) -> Result<Vec<NodeIndex>, String> {
+        let incoming = |n: NodeIndex| graph.edges_directed(n, Direction::Incoming)
+            .filter(|e| follow(*e.weight()) && nodes.contains(&e.source()))
+            .count();
+        let mut waiting : HashMap<NodeIndex, usize> = nodes.iter().map(|n| (*n, incoming(*n))).collect();
+        let mut ready : BTreeSet<NodeIndex> = waiting.iter().filter(|(_, c)| **c == 0).map(|(n, _)| *n).collect();
+        let mut order = vec![];
+        while let Some(n) = ready.iter().next().cloned() {
+            ready.remove(&n);
+            order.push(n);
+            for e in graph.edges_directed(n, Direction::Outgoing) {
+                if !follow(*e.weight()) { continue; }
+                if let Some(c) = waiting.get_mut(&e.target()) {
+                    *c -= 1;
+                    if *c == 0 { ready.insert(e.target()); }
+                }
+            }
+        }
+        if order.len() < nodes.len() {
+            return Err(String::from("dependency graph still has a cycle"));
+        }
+        Ok(order)
+    }
+
+    /// Orders the packages that how-to-install would install for package_name (and package_name
+    /// itself) so that dpkg can unpack and configure them. Dependency cycles are broken by dropping
+    /// Depends edges; a cycle made only of Pre-Depends can't be broken and is an error.
+    pub fn compute_install_order(&self, package_name: &str) -> Result<InstallOrder, String> {
+        if !self.package_exists(package_name) {
+            return Err(format!("no such package {}", package_name));
+        }
+        let mut plan = self.compute_how_to_install(package_name)?;
+        let package_num = self.get_package_num(package_name);
+        if !plan.contains(&package_num) {
+            plan.push(package_num);
+        }
+        let (mut graph, _) = self.plan_graph(&plan);
+
+        let mut broken_cycles = vec![];
+        loop {
+            let scc = match tarjan_scc(&graph).into_iter().find(|scc| scc.len() > 1) {
+                None => break,
+                Some(scc) => scc
+            };
+            let members : HashSet<NodeIndex> = scc.iter().cloned().collect();
+            let mut scc_sorted = scc.clone();
+            scc_sorted.sort_by_key(|n| self.get_package_name(graph[*n]));
+            let weak_edge = scc_sorted.iter()
+                .flat_map(|n| graph.edges_directed(*n, Direction::Outgoing).map(move |e| (*n, e)))
+                .find(|(_, e)| *e.weight() == DepKind::Depends && members.contains(&e.target()))
+                .map(|(n, e)| (n, e.target()));
+            let (from, to) = match weak_edge {
+                None => return Err(format!("unbreakable Pre-Depends cycle: {}",
+                                           scc_sorted.iter().map(|n| self.get_package_name(graph[*n])).format(", "))),
+                Some(edge) => edge
+            };
+
+            // find the rest of the cycle: a path from `to` back to `from` inside the component
+            let mut parent : HashMap<NodeIndex, NodeIndex> = HashMap::new();
+            let mut queue = VecDeque::from(vec![to]);
+            while let Some(n) = queue.pop_front() {
+                if n == from { break; }
+                for next in graph.neighbors_directed(n, Direction::Outgoing) {
+                    if members.contains(&next) && next != to && !parent.contains_key(&next) {
+                        parent.insert(next, n);
+                        queue.push_back(next);
+                    }
+                }
+            }
+            // `to` depends on `from`, and walking the parents back from `from` follows "depends on" too
+            let mut cycle = vec![graph[to]];
+            let mut n = from;
+            while n != to {
+                cycle.push(graph[n]);
+                n = parent[&n];
+            }
+
+            broken_cycles.push(BrokenCycle { cycle, depender: graph[to], dependency: graph[from] });
+            let edge = graph.find_edge(from, to).unwrap();
+            graph.remove_edge(edge);
+        }
+
+        // everything a Pre-Depends needs must be configured before its depender is unpacked
+        let mut early : HashSet<NodeIndex> = HashSet::new();
+        let mut worklist : Vec<NodeIndex> = graph.edge_indices()
+            .filter(|e| graph[*e] == DepKind::PreDepends)
+            .map(|e| graph.edge_endpoints(e).unwrap().0)
+            .collect();
+        while let Some(n) = worklist.pop() {
+            if early.insert(n) {
+                worklist.extend(graph.neighbors_directed(n, Direction::Incoming));
+            }
+        }
+
+        let rest : HashSet<NodeIndex> = graph.node_indices().filter(|n| !early.contains(n)).collect();
+        let ids = |order: Vec<NodeIndex>| order.into_iter().map(|n| graph[n]).collect::<Vec<PackageId>>();
+        let preconfigure = ids(Self::order_nodes(&graph, &early, |_| true)?);
+        // dpkg unpacks in any order but Pre-Depends'; all of rest is unpacked before any is configured
+        let unpack = ids(Self::order_nodes(&graph, &rest, |kind| kind == DepKind::PreDepends)?);
+        let configure = ids(Self::order_nodes(&graph, &rest, |_| true)?);
+        Ok(InstallOrder { preconfigure, unpack, configure, broken_cycles })
+    }
+
+    pub fn print_install_order(&self, package_name: &str) {
+        let order = match self.compute_install_order(package_name) {
+            Err(e) => { self.fail(&e); return }
+            Ok(o) => o
+        };
+        println!("Package {}:", package_name);
+        for broken in &order.broken_cycles {
+            let mut cycle = broken.cycle.iter().map(|pn| self.get_package_name(*pn)).collect::<Vec<&str>>();
+            cycle.push(self.get_package_name(broken.cycle[0]));
+            println!("Broke cycle {} by ignoring {} depends on {}", cycle.join(" -> "),
+                     self.get_package_name(broken.depender), self.get_package_name(broken.dependency));
+        }
+        let names = |pkgs: &Vec<PackageId>| pkgs.iter().map(|pn| self.get_package_name(*pn)).format(", ").to_string();
+        if !order.preconfigure.is_empty() {
+            println!("Pre-configure: {}", names(&order.preconfigure));
+        }
+        println!("Unpack: {}", names(&order.unpack));
+        println!("Configure: {}", names(&order.configure));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Packages;
+
+    // nothing is installed; how-to-install app plans base, libb, tool, dpkg-helper, liba, libc
+    const PACKAGES : &str = "\
+Package: app\nVersion: 1.0\nPre-Depends: base\nDepends: libb, tool\n\n\
+Package: base\nVersion: 1.0\nDepends: dpkg-helper\n\n\
+Package: dpkg-helper\nVersion: 1.0\n\n\
+Package: libb\nVersion: 1.0\nDepends: liba\n\n\
+Package: liba\nVersion: 1.0\nDepends: libb\n\n\
+Package: tool\nVersion: 1.0\nDepends: libc\n\n\
+Package: libc\nVersion: 1.0\n";
+
+    fn loaded(text: &str) -> Packages {
+        let mut p = Packages::new();
+        let nums = p.parse_packages_reader("Packages", text.as_bytes());
+        p.record_candidates(&nums, None);
+        p.select_candidates();
+        p
+    }
+
+    fn names(p: &Packages, pkgs: &[crate::packages::interner::PackageId]) -> Vec<String> {
+        pkgs.iter().map(|pn| p.get_package_name(*pn).to_string()).collect()
+    }
+
+    #[test]
+    fn pre_depends_are_configured_before_the_rest_is_unpacked() {
+        let p = loaded(PACKAGES);
+        let order = p.compute_install_order("app").unwrap();
+        assert_eq!(names(&p, &order.preconfigure), ["dpkg-helper", "base"]);
+        // unpacking keeps plan order; configuring waits for dependencies
+        assert_eq!(names(&p, &order.unpack), ["libb", "tool", "liba", "libc", "app"]);
+        assert_eq!(names(&p, &order.configure), ["libb", "liba", "libc", "tool", "app"]);
+    }
+
+    #[test]
+    fn a_depends_cycle_is_broken_and_reported() {
+        let p = loaded(PACKAGES);
+        let order = p.compute_install_order("app").unwrap();
+        assert_eq!(order.broken_cycles.len(), 1);
+        let broken = &order.broken_cycles[0];
+        assert_eq!(names(&p, &broken.cycle), ["libb", "liba"]);
+        assert_eq!(p.get_package_name(broken.depender), "libb");
+        assert_eq!(p.get_package_name(broken.dependency), "liba");
+    }
+
+    #[test]
+    fn a_pre_depends_cycle_is_an_error() {
+        let p = loaded("\
+Package: x\nVersion: 1.0\nPre-Depends: y\n\n\
+Package: y\nVersion: 1.0\nPre-Depends: x\n");
+        let err = p.compute_install_order("x").err().unwrap();
+        assert_eq!(err, "unbreakable Pre-Depends cycle: x, y");
+    }
+}
@@ -0,0 +1,261 @@
+use std::str::FromStr;
+
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::Packages;
+use crate::packages::Dependency;
+
+/// How query commands print their results: the usual text, or one JSON object per result.
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<OutputFormat, String> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("unknown output format {}; expected text or json", s)),
+        }
+    }
+}
+
+/// One alternative of a dependency, e.g. libc6 (>= 2.30).
+#[derive(Serialize)]
+pub struct AlternativeReport {
+    pub package : String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relation : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version : Option<String>,
+}
+
+/// A dependency A | B | C, as its list of alternatives.
+pub type DependencyReport = Vec<AlternativeReport>;
+
+/// Formats a dependency the way the text output always has: "a (>= 1) | b".
+pub fn format_dependency(dep: &DependencyReport) -> String {
+    dep.iter().map(|alt| match (&alt.relation, &alt.version) {
+        (Some(rel), Some(ver)) => format!("{} ({} {})", alt.package, rel, ver),
+        _ => alt.package.clone(),
+    }).join(" | ")
+}
+
+pub fn format_dependencies(deps: &[DependencyReport]) -> String {
+    deps.iter().map(format_dependency).join(", ")
+}
+
+#[derive(Serialize)]
+pub struct RepositoryReport {
+    pub origin : String,
+    pub suite : String,
+    pub component : String,
+    pub architecture : String,
+}
+
+#[derive(Serialize)]
+pub struct PackageInfo {
+    pub package : String,
+    pub version : Option<String>,
+    pub md5sum : Option<String>,
+    pub depends : Vec<DependencyReport>,
+    pub repository : Option<RepositoryReport>,
+    pub installed_version : Option<String>,
+    pub newer_available : Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct DepsReport {
+    pub package : String,
+    pub depends : Vec<DependencyReport>,
+}
+
+#[derive(Serialize)]
+pub struct SatisfiedByReport {
+    pub package : String,
+    pub version : String,
+}
+
+#[derive(Serialize)]
+pub struct DependencyStatusReport {
+    pub dependency : DependencyReport,
+    pub satisfied_by : Option<SatisfiedByReport>,
+}
+
+#[derive(Serialize)]
+pub struct DepsAvailableReport {
+    pub package : String,
+    pub dependencies : Vec<DependencyStatusReport>,
+}
+
+#[derive(Serialize)]
+pub struct TransitiveDepSolutionReport {
+    pub package : String,
+    pub solution : Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct InstallPlan {
+    pub package : String,
+    pub to_install : Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct VerifyIndexReport {
+    pub file : String,
+    pub verified : bool,
+    pub error : Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SignatureReport {
+    pub issuer : String,
+    /// good, bad, unknown-key or unsupported
+    pub status : String,
+    pub fingerprint : Option<String>,
+    pub primary_fingerprint : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unsupported : Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct VerifyReleaseReport {
+    pub file : String,
+    pub verified : bool,
+    pub signatures : Vec<SignatureReport>,
+}
+
+#[derive(Serialize)]
+pub struct SearchResultReport {
+    pub package : String,
+    pub version : Option<String>,
+    pub installed_version : Option<String>,
+    pub upgradable : bool,
+    pub description : Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SearchReport {
+    pub results : Vec<SearchResultReport>,
+}
+
+#[derive(Serialize)]
+pub struct PackageVersionReport {
+    pub package : String,
+    pub version : String,
+}
+
+#[derive(Serialize)]
+pub struct VersionChangeReport {
+    pub package : String,
+    pub old_version : String,
+    pub new_version : String,
+}
+
+#[derive(Serialize)]
+pub struct DependencyChangeReport {
+    pub package : String,
+    pub added : Vec<DependencyReport>,
+    pub removed : Vec<DependencyReport>,
+}
+
+#[derive(Serialize)]
+pub struct HashChangeReport {
+    pub package : String,
+    pub version : String,
+    pub old_md5sum : String,
+    pub new_md5sum : String,
+}
+
+// changes to one side (available or installed) of a universe
+#[derive(Serialize, Default)]
+pub struct SideDiffReport {
+    pub added : Vec<PackageVersionReport>,
+    pub removed : Vec<PackageVersionReport>,
+    pub upgraded : Vec<VersionChangeReport>,
+    pub downgraded : Vec<VersionChangeReport>,
+    pub dependencies_changed : Vec<DependencyChangeReport>,
+    pub hashes_changed : Vec<HashChangeReport>,
+}
+
+#[derive(Serialize)]
+pub struct DiffReport {
+    pub left : String,
+    pub right : String,
+    pub available : SideDiffReport,
+    pub installed : SideDiffReport,
+}
+
+/// What would fix a broken dependency: install, upgrade or downgrade package to version.
+#[derive(Serialize)]
+pub struct FixReport {
+    pub action : String,
+    pub package : String,
+    pub version : String,
+}
+
+#[derive(Serialize)]
+pub struct BrokenDependencyReport {
+    pub package : String,
+    pub dependency : DependencyReport,
+    pub fix : Option<FixReport>,
+}
+
+#[derive(Serialize)]
+pub struct CheckReport {
+    pub installed : usize,
+    pub broken : usize,
+    pub unsatisfied : Vec<BrokenDependencyReport>,
+    pub fixable : usize,
+    /// ok or broken
+    pub status : String,
+}
+
+#[derive(Serialize)]
+pub struct LintProblemReport {
+    pub line : usize,
+    pub package : Option<String>,
+    pub message : String,
+}
+
+#[derive(Serialize)]
+pub struct LintReport {
+    pub file : String,
+    pub stanzas : usize,
+    pub problems : Vec<LintProblemReport>,
+    /// ok or failed
+    pub status : String,
+}
+
+#[derive(Serialize)]
+pub struct ErrorReport<'a> {
+    pub error : &'a str,
+}
+
+impl Packages {
+    pub fn set_format(&mut self, format: OutputFormat) {
+        self.format = format;
+    }
+
+    pub fn dependency_report(&self, dep: &Dependency) -> DependencyReport {
+        dep.iter().map(|alt| AlternativeReport {
+            package: self.get_package_name(alt.package_num).to_string(),
+            relation: alt.rel_version.as_ref().map(|(rel, _)| rel.to_string()),
+            version: alt.rel_version.as_ref().map(|(_, ver)| ver.clone()),
+        }).collect()
+    }
+
+    /// In JSON mode, prints report as a line of JSON and returns true; otherwise returns false and
+    /// the caller prints text.
+    pub fn emit_json<T: Serialize>(&self, report: &T) -> bool {
+        if self.format != OutputFormat::Json {
+            return false;
+        }
+        println!("{}", serde_json::to_string(report).unwrap());
+        true
+    }
+}
@@ -1,156 +1,450 @@
 use std::fs::File;
-use std::io::{self, BufRead};
-use std::path::Path;
-
-use regex::Regex;
+use std::io::{BufRead, BufReader, Read};
 
 use crate::Packages;
-use crate::packages::{Dependency, RelVersionedPackageNum};
+use crate::packages::{Dependency, Fields, ParseDiagnostic, RelVersionedPackageNum, Severity, SourceRef};
+use crate::packages::interner::PackageId;
+use crate::packages::stanza::{self, Relation, Stanza, StanzaReader, Stanzas};
 
-use rpkg::debversion;
+use crate::debversion;
 
-const KEYVAL_REGEX : &str = r"(?P<key>(\w|-)+): (?P<value>.+)";
-const PKGNAME_AND_VERSION_REGEX : &str = r"(?P<pkg>(\w|\.|\+|-)+)( \((?P<op>(<|=|>)(<|=|>)?) (?P<ver>.*)\))?";
 
 impl Packages {
     /// Loads packages and version numbers from a file, calling get_package_num_inserting on the package name
     /// and inserting the appropriate value into the installed_debvers map with the parsed version number.
+    /// The Pre-Depends and Depends of each installed package go into installed_dependencies, and
+    /// its Provides into installed_provides. Every field of a stanza also goes into installed_fields.
+    /// Stanzas with an error are skipped; load-errors lists them.
     pub fn parse_installed(&mut self, filename: &str) {
-        let kv_regexp = Regex::new(KEYVAL_REGEX).unwrap();
-        if let Ok(lines) = read_lines(filename) {
-            let mut current_package_num = 0;
-            for line in lines {
-                if let Ok(ip) = line {
-                    // do something with ip
-                    match kv_regexp.captures(&ip) {
-                        None => (),
-                        Some(caps) => {
-                            let (key, value) = (caps.name("key").unwrap().as_str(),
-                                                caps.name("value").unwrap().as_str());
-                            if key == "Package" {
-                                current_package_num =
-                                    self.get_package_num_inserting(&value);
-                            }
-
-                            else if key == "Version" {
-                                let debver =
-                                    value.trim().parse::<debversion::DebianVersionNum>().unwrap();
-                                self.installed_debvers.insert(current_package_num, debver);
-                            }
-                        }
-                        }
+        match self.read_installed(filename) {
+            Err(e) => self.fail(&format!("Error: {}", e)),
+            Ok(()) => {
+                self.print_load_summary(filename);
+                self.progress(&format!("Packages installed: {}", self.installed_debvers.keys().len()));
+            }
+        }
+    }
+
+    /// Like parse_installed, but returns errors instead of printing them, and prints nothing; problems
+    /// are kept for load_diagnostics.
+    pub fn read_installed(&mut self, filename: &str) -> Result<(), String> {
+        let file = File::open(filename).map_err(|e| format!("can't read {}: {}", filename, e))?;
+        let since = self.begin_load(filename);
+        self.parse_installed_reader(filename, BufReader::new(file));
+        self.check_strict(since)?;
+        // the installed versions take part in choosing the available ones
+        self.select_candidates();
+        Ok(())
+    }
+
+    /// Does the work of parse_installed on an already-opened status file, a stanza at a time; problems are
+    /// reported against filename. In strict mode, stores nothing unless every stanza is clean.
+    pub fn parse_installed_reader<R: BufRead>(&mut self, filename: &str, reader: R) {
+        self.load_stanzas(filename, reader, |p, checked| p.store_installed(checked));
+    }
+
+    fn store_installed(&mut self, stanza: CheckedStanza) {
+        let current_package_num = self.get_package_num_inserting(stanza.package);
+        let previous_fields = self.installed_fields.insert(current_package_num, stanza.fields);
+        self.installed_sources.remove(&current_package_num);
+        self.installed_dependencies.remove(&current_package_num);
+        // forget what an earlier stanza of this package provided, which only its own Provides can name
+        if let Some((_, provides)) = previous_fields.iter().flatten().find(|(key, _)| key == "Provides") {
+            for (name, _) in stanza::parse_relations(provides, false, &mut vec![]).unwrap_or_default().into_iter().flatten() {
+                if let Some(providers) = self.package_ids.get(name).and_then(|pn| self.installed_provides.get_mut(&pn)) {
+                    providers.retain(|(p, _)| *p != current_package_num);
+                }
+            }
+        }
+        if let Some(debver) = stanza.version {
+            self.installed_debvers.insert(current_package_num, debver);
+        }
+        if let Some(source) = stanza.source {
+            self.installed_sources.insert(current_package_num, source);
+        }
+
+        // packages removed but for their config files keep a stanza; their dependencies don't matter
+        if !stanza.status.is_none_or(|s| s.ends_with(" installed")) {
+            return;
+        }
+        for (key, relations) in stanza.relations {
+            if key == "Depends" || key == "Pre-Depends" {
+                let deps = self.intern_relations(relations);
+                self.installed_dependencies.entry(current_package_num).or_default().extend(deps);
+            } else if key == "Provides" {
+                for (provided, version) in self.intern_provides(relations) {
+                    self.installed_provides.entry(provided).or_default().push((current_package_num, version));
                 }
             }
         }
-        println!("Packages installed: {}", self.installed_debvers.keys().len());
     }
 
     /// Loads packages, version numbers, dependencies, and md5sums from a file, calling get_package_num_inserting on the package name
-    /// and inserting the appropriate values into the dependencies, md5sum, and available_debvers maps.
+    /// and inserting the appropriate values into the dependencies, provides, md5sum, and available_debvers maps,
+    /// and every field of a stanza into available_fields. Stanzas with an error are skipped; load-errors lists them.
+    ///
+    /// If a Release file has been loaded, refuses a file whose size or hash doesn't match its listing.
     pub fn parse_packages(&mut self, filename: &str) {
-        let kv_regexp = Regex::new(KEYVAL_REGEX).unwrap();
-        let pkgver_regexp = Regex::new(PKGNAME_AND_VERSION_REGEX).unwrap();
-
-        if let Ok(lines) = read_lines(filename) {
-            let mut current_package_num = 0;
-            for line in lines {
-                if let Ok(ip) = line {
-                    // do more things with ip
-                    match kv_regexp.captures(&ip) {
-                        None => (),
-                        Some(caps) => {
-                            let (key, value) = (caps.name("key").unwrap().as_str(),
-                                                caps.name("value").unwrap().as_str());
-
-                            match key {
-                                "Package" => {
-                                    current_package_num =
-                                        self.get_package_num_inserting(value);
-                                    // println!("pkg: {} {}", value, current_package_num);
-                                },
-                                "Version" => {
-                                    // println!("ver-1: {} {}", current_package_num, value);
-                                    let debver =
-                                        value.trim().parse::<debversion::DebianVersionNum>().unwrap();
-                                    self.available_debvers.insert(current_package_num, debver);
-                                    // println!("ver-2: {} {}", current_package_num, value);
-                                },
-                                "MD5sum" => {
-                                    // println!("md5-1: {} {} {}", current_package_num, value, value.trim().to_string());
-                                    self.md5sums.insert(current_package_num, value.trim().to_string());
-                                    // println!("md5-2: {} {} {}", current_package_num, value, value.trim().to_string());
-                                },
-                                "Depends" => {
-                                    let all_deps = value.to_string();
-                                    let all_deps = all_deps.split(",");
-                                    let all_deps: Vec<&str> = all_deps.collect();
-                                    let mut vec_str_deps: Vec<Vec<&str>> = vec![];
-                                    let mut final_deps: Vec<Dependency> = vec![];
-                                    for a_deps in all_deps {
-                                        let a_deps = a_deps.split("|");
-                                        let a_deps: Vec<&str> = a_deps.collect();
-                                        vec_str_deps.push(a_deps);
-                                    }
-                                    for vec_str_dep in vec_str_deps {
-                                        let mut final_dep: Dependency = vec![];
-                                        for str_dep in vec_str_dep {
-                                            match pkgver_regexp.captures(str_dep) {
-                                                None => (),
-                                                Some(caps) => {
-                                                    let pkg = caps.name("pkg").unwrap().as_str();
-                                                    let package_num =
-                                                        self.get_package_num_inserting(pkg);
-
-                                                    let op = match caps.name("op") {
-                                                        Some(x) =>
-                                                            Some(x.as_str().parse::<debversion::VersionRelation>().unwrap()),
-                                                        None => None,
-                                                    };
-
-                                                    let ver = match caps.name("ver") {
-                                                        Some(x) => Some(x.as_str().to_string()),
-                                                        None => None,
-                                                    };
-
-                                                    match op {
-                                                        None => {
-                                                            let dep = RelVersionedPackageNum {
-                                                                package_num,
-                                                                rel_version: None,
-                                                            };
-                                                            final_dep.push(dep);
-                                                        }
-                                                        Some(x) => {
-                                                            let dep = RelVersionedPackageNum {
-                                                                package_num,
-                                                                rel_version: Some((x, ver.unwrap())),
-                                                            };
-                                                            final_dep.push(dep);
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        final_deps.push(final_dep);
-                                    }
-                                    // println!("{:?}", final_deps);
-                                    self.dependencies.insert(current_package_num, final_deps);
-                                },
-                                _ => (),
-                            }
-                        }
-                    }
+        if let Err(e) = self.read_packages(filename) {
+            self.fail(&format!("Error: {}", e));
+            return;
+        }
+        self.print_load_summary(filename);
+        self.progress(&format!("Packages available: {}", self.available_debvers.keys().len()));
+    }
+
+    /// Like parse_packages, but returns errors instead of printing them, and prints nothing; problems
+    /// are kept for load_diagnostics. Returns the number of stanzas read.
+    pub fn read_packages(&mut self, filename: &str) -> Result<usize, String> {
+        self.verify_index(filename).map_err(|e| e.to_string())?;
+        let file = File::open(filename).map_err(|e| format!("can't read {}: {}", filename, e))?;
+        let since = self.begin_load(filename);
+        let stanzas = self.parse_packages_reader(filename, BufReader::new(file));
+        self.check_strict(since)?;
+        self.record_candidates(&stanzas, None);
+        self.select_candidates();
+        Ok(stanzas.len())
+    }
+
+    /// Does the work of parse_packages on an already-opened (possibly decompressed) index, a stanza at a
+    /// time; problems are reported against filename. Returns the numbers of the packages that had a stanza
+    /// in it; callers should pass them to record_candidates so that the stanzas survive later loads.
+    /// In strict mode, stores nothing unless every stanza is clean.
+    pub fn parse_packages_reader<R: BufRead>(&mut self, filename: &str, reader: R) -> Vec<PackageId> {
+        let mut package_nums = vec![];
+        self.load_stanzas(filename, reader, |p, checked| package_nums.push(p.store_available(checked)));
+        package_nums
+    }
+
+    /// Checks the stanzas of reader, passing those to keep to store. Streams them unless in strict mode,
+    /// where the whole file is read and checked first so that a load that fails leaves nothing behind.
+    fn load_stanzas<R: BufRead, F>(&mut self, filename: &str, mut reader: R, mut store: F)
+        where F: FnMut(&mut Packages, CheckedStanza) {
+        if self.strict {
+            let data = match self.read_all(filename, &mut reader) {
+                None => return,
+                Some(data) => data
+            };
+            for checked in self.check_all(&Syntax::Hand, filename, Stanzas::new(&data)).unwrap_or_default() {
+                store(self, checked);
+            }
+            return;
+        }
+        let mut stanzas = StanzaReader::new(reader);
+        while let Some(stanza) = stanzas.next_stanza() {
+            if let Some(checked) = self.check_stanza(&Syntax::Hand, filename, &stanza) {
+                store(self, checked);
+            }
+        }
+    }
+
+    /// Reads all of reader, or records why it can't as an error against filename and returns None.
+    fn read_all<R: Read>(&mut self, filename: &str, reader: &mut R) -> Option<Vec<u8>> {
+        let mut data = vec![];
+        match reader.read_to_end(&mut data) {
+            Ok(_) => Some(data),
+            Err(e) => {
+                let line = data.iter().filter(|b| **b == b'\n').count() + 1;
+                self.load_diagnostics.push(ParseDiagnostic { file: filename.to_string(), line, package: None,
+                                                             severity: Severity::Error, message: format!("can't read: {}", e) });
+                None
+            }
+        }
+    }
+
+    /// Checks every stanza, returning those to keep, or None in strict mode if any has a problem.
+    fn check_all<'a, I: Iterator<Item = Stanza<'a>>>(&mut self, syntax: &Syntax, filename: &str, stanzas: I) -> Option<Vec<CheckedStanza<'a>>> {
+        let mut kept = vec![];
+        for stanza in stanzas {
+            match self.check_stanza(syntax, filename, &stanza) {
+                None if self.strict => return None,
+                None => (),
+                Some(checked) => kept.push(checked),
+            }
+        }
+        Some(kept)
+    }
+
+    /// In strict mode, checks every stanza of reader without storing any, and returns the first problem as an
+    /// error; this lets a load of several files check them all before storing anything.
+    pub(crate) fn check_packages_reader<R: BufRead>(&mut self, filename: &str, mut reader: R) -> Result<(), String> {
+        let since = self.begin_load(filename);
+        if let Some(data) = self.read_all(filename, &mut reader) {
+            self.check_all(&Syntax::Hand, filename, Stanzas::new(&data));
+        }
+        self.check_strict(since)
+    }
+
+    fn store_available(&mut self, stanza: CheckedStanza) -> PackageId {
+        let current_package_num = self.get_package_num_inserting(stanza.package);
+        self.available_sources.remove(&current_package_num);
+        self.package_origins.remove(&current_package_num);
+        self.md5sums.remove(&current_package_num);
+        self.dependencies.insert(current_package_num, vec![]);
+        self.pre_dependencies.remove(&current_package_num);
+        self.provides.remove(&current_package_num);
+        self.available_fields.insert(current_package_num, stanza.fields);
+
+        if let Some(debver) = stanza.version {
+            self.available_debvers.insert(current_package_num, debver);
+        }
+        if let Some(md5sum) = stanza.md5sum {
+            self.md5sums.insert(current_package_num, md5sum.to_string());
+        }
+        if let Some(source) = stanza.source {
+            self.available_sources.insert(current_package_num, source);
+        }
+        for (key, relations) in stanza.relations {
+            if key == "Depends" {
+                let deps = self.intern_relations(relations);
+                self.dependencies.insert(current_package_num, deps);
+            } else if key == "Pre-Depends" {
+                let deps = self.intern_relations(relations);
+                self.pre_dependencies.insert(current_package_num, deps);
+            } else if key == "Provides" {
+                let provided = self.intern_provides(relations);
+                self.provides.insert(current_package_num, provided);
+            }
+        }
+        current_package_num
+    }
+
+    /// Parses the fields of stanza that the maps here hold, recording its problems as diagnostics against filename.
+    /// Returns None when the stanza is to be skipped: it has an error or, in strict mode, any problem.
+    fn check_stanza<'a>(&mut self, syntax: &Syntax, filename: &str, stanza: &Stanza<'a>) -> Option<CheckedStanza<'a>> {
+        let mut warnings = vec![];
+        let checked = parse_stanza(syntax, stanza, &mut warnings);
+        if warnings.is_empty() && checked.is_ok() {
+            return checked.ok();
+        }
+        let package = stanza.get("Package").map(|f| f.value.to_string());
+        let problems = warnings.into_iter().map(|w| (Severity::Warning, w))
+            .chain(checked.as_ref().err().map(|e| (Severity::Error, e.clone())));
+        for (severity, (line, message)) in problems {
+            let file = filename.to_string();
+            self.load_diagnostics.push(ParseDiagnostic { file, line, package: package.clone(), severity, message });
+        }
+        match checked {
+            Ok(checked) if !self.strict => Some(checked),
+            _ => None
+        }
+    }
+
+    /// Turns relations as written into dependencies, calling get_package_num_inserting on every package named.
+    fn intern_relations(&mut self, relations: Vec<Vec<Relation>>) -> Vec<Dependency> {
+        let mut deps = Vec::with_capacity(relations.len());
+        for alternatives in relations {
+            let mut dep : Dependency = Vec::with_capacity(alternatives.len());
+            for (name, rel_version) in alternatives {
+                let package_num = self.get_package_num_inserting(name);
+                let rel_version = rel_version.map(|(op, ver)| (op, ver.to_string()));
+                dep.push(RelVersionedPackageNum { package_num, rel_version });
+            }
+            deps.push(dep);
+        }
+        deps
+    }
+
+    /// Turns a Provides field, parsed like a dependency list ("foo (= 1.0), bar"), into the packages provided
+    /// and their versions.
+    fn intern_provides(&mut self, relations: Vec<Vec<Relation>>) -> Vec<(PackageId, Option<debversion::DebianVersionNum>)> {
+        self.intern_relations(relations).into_iter().flatten()
+            .map(|p| (p.package_num, p.rel_version.and_then(|(_, v)| v.parse::<debversion::DebianVersionNum>().ok())))
+            .collect()
+    }
+}
+
+// the line-by-line, regex-based parser that the streaming one replaced, kept as the baseline of benches/parse.rs
+#[cfg(any(test, feature = "regex-baseline"))]
+mod regex_baseline;
+
+// how relations and Source: fields are parsed: by hand, or with the regexes of the original parser
+enum Syntax {
+    Hand,
+    #[cfg(any(test, feature = "regex-baseline"))]
+    Regex { pkgver : regex::Regex, source : regex::Regex },
+}
+
+impl Syntax {
+    fn relations<'a>(&self, value: &'a str, warnings: &mut Vec<String>) -> Result<Vec<Vec<Relation<'a>>>, String> {
+        match self {
+            Syntax::Hand => stanza::parse_relations(value, false, warnings),
+            #[cfg(any(test, feature = "regex-baseline"))]
+            Syntax::Regex { pkgver, .. } => regex_baseline::parse_relations(pkgver, value, warnings),
+        }
+    }
+
+    /// The name and version of a Source: field, "name" or "name (version)"; None if it's empty.
+    fn source<'a>(&self, value: &'a str) -> Option<(&'a str, Option<&'a str>)> {
+        let value = value.trim();
+        match self {
+            Syntax::Hand if value.is_empty() => None,
+            Syntax::Hand => match value.split_once(' ') {
+                None => Some((value, None)),
+                Some((name, rest)) => Some((name, rest.strip_prefix('(').and_then(|v| v.strip_suffix(')')).map(str::trim))),
+            },
+            #[cfg(any(test, feature = "regex-baseline"))]
+            Syntax::Regex { source, .. } => regex_baseline::parse_source(source, value),
+        }
+    }
+}
+
+// the fields of a stanza that the maps of Packages hold, parsed but not stored yet
+struct CheckedStanza<'a> {
+    package : &'a str,
+    version : Option<debversion::DebianVersionNum>,
+    md5sum : Option<&'a str>,
+    status : Option<&'a str>,
+    source : Option<SourceRef>,
+    // Pre-Depends, Depends and Provides, in the order of the stanza
+    relations : Vec<(&'a str, Vec<Vec<Relation<'a>>>)>,
+    // every field but Package
+    fields : Fields,
+}
+
+/// Parses a stanza; the error is the first thing that makes it unusable, with its line. Relations that
+/// only parse leniently go into warnings.
+fn parse_stanza<'a>(syntax: &Syntax, stanza: &Stanza<'a>, warnings: &mut Vec<(usize, String)>)
+                    -> Result<CheckedStanza<'a>, (usize, String)> {
+    if let Some(problem) = stanza.problems.first() {
+        return Err(problem.clone());
+    }
+    let package = stanza.get("Package").ok_or_else(|| (stanza.line, String::from("stanza has no Package field")))?;
+    let mut checked = CheckedStanza {
+        package: package.value, version: None, md5sum: None, status: None, source: None,
+        relations: vec![], fields: Vec::with_capacity(stanza.fields.len()),
+    };
+    for (i, field) in stanza.fields.iter().enumerate() {
+        let error = |message: String| (field.line, message);
+        if stanza.fields[..i].iter().any(|f| f.name == field.name) {
+            return Err(error(format!("duplicate field {}", field.name)));
+        }
+        match field.name {
+            "Package" => continue,
+            "Version" => {
+                debversion::check_version_syntax(field.value).map_err(|e| error(format!("invalid version {:?}: {}", field.value, e)))?;
+                checked.version = Some(field.value.parse::<debversion::DebianVersionNum>().unwrap());
+            }
+            "MD5sum" => checked.md5sum = Some(field.value),
+            "Status" => checked.status = Some(field.value),
+            "Source" => {
+                checked.source = match syntax.source(field.value) {
+                    None => None,
+                    Some((name, version)) => Some(source_ref(name, version).map_err(|e| error(format!("Source {:?}: {}", field.value, e)))?),
                 }
             }
+            "Depends" | "Pre-Depends" | "Provides" => {
+                let mut relation_warnings = vec![];
+                let relations = syntax.relations(field.value, &mut relation_warnings)
+                    .map_err(|e| error(format!("{}: {}", field.name, e)))?;
+                warnings.extend(relation_warnings.into_iter().map(|w| (field.line, w)));
+                checked.relations.push((field.name, relations));
+            }
+            _ => (),
         }
-        println!("Packages available: {}", self.available_debvers.keys().len());
+        checked.fields.push((field.name.to_string(), field.joined().into_owned()));
     }
+    Ok(checked)
 }
 
+fn source_ref(name: &str, version: Option<&str>) -> Result<SourceRef, String> {
+    let version = match version {
+        None => None,
+        Some(v) => {
+            debversion::check_version_syntax(v).map_err(|e| format!("invalid version {:?}: {}", v, e))?;
+            Some(v.parse::<debversion::DebianVersionNum>().unwrap())
+        }
+    };
+    Ok(SourceRef { name: name.to_string(), version })
+}
+
+/// Does the work of check_relation_syntax on the relation written, naming package.
+pub(crate) fn relation_syntax_warning(written: &str, package: &str, op: &str, ver: &str) -> Option<String> {
+    if debversion::is_deprecated_relation(op) {
+        let meaning = op.parse::<debversion::VersionRelation>().ok()?;
+        return Some(format!("deprecated relation \"{}\" in \"{}\", read as \"{}\"", op, written, meaning));
+    }
+    let canonical = format!("{} ({} {})", package, op, ver);
+    if written != canonical {
+        return Some(format!("unusual spacing in \"{}\", expected \"{}\"", written, canonical));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufRead;
 
-// standard template code downloaded from the Internet somewhere
-fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
-where P: AsRef<Path>, {
-    let file = File::open(filename)?;
-    Ok(io::BufReader::new(file).lines())
+    use crate::Packages;
+    use crate::packages::Severity;
+
+    const INSTALLED : &str = concat!(env!("CARGO_MANIFEST_DIR"), "/data/installed-packages");
+
+    const BAD_RELATION : &str = "Package: good\nVersion: 1.0\n\nPackage: bad\nVersion: 1.0\nDepends: foo (=> 1.0)\n";
+
+    fn errors(packages: &Packages) -> Vec<String> {
+        packages.load_diagnostics().iter().filter(|d| d.severity == Severity::Error).map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn bad_relation_operator_skips_the_stanza() {
+        let mut packages = Packages::new();
+        packages.parse_packages_reader("Packages", BAD_RELATION.as_bytes());
+        assert_eq!(errors(&packages), ["Packages:6: error: package bad: Depends: bad version relation \"=>\" in \"foo (=> 1.0)\""]);
+        assert!(packages.get_available_debver("good").is_some());
+        assert!(packages.get_available_debver("bad").is_none());
+    }
+
+    #[test]
+    fn bad_relation_operator_skips_the_stanza_in_the_regex_parser() {
+        let mut packages = Packages::new();
+        packages.parse_packages_lines("Packages", BAD_RELATION.as_bytes().lines());
+        assert_eq!(errors(&packages).len(), 1);
+        assert!(packages.get_available_debver("good").is_some());
+        assert!(packages.get_available_debver("bad").is_none());
+    }
+
+    #[test]
+    fn strict_load_with_a_bad_relation_stores_nothing() {
+        let mut packages = Packages::new();
+        packages.set_strict(true);
+        packages.parse_packages_reader("Packages", BAD_RELATION.as_bytes());
+        assert_eq!(errors(&packages).len(), 1);
+        assert!(packages.get_available_debver("good").is_none());
+    }
+
+    #[test]
+    fn a_reloaded_stanza_replaces_what_the_package_provided() {
+        let mut packages = Packages::new();
+        packages.parse_installed_reader("status", "Package: mawk\nVersion: 1.3\nProvides: awk, nawk\n".as_bytes());
+        packages.parse_installed_reader("status", "Package: mawk\nVersion: 1.4\nProvides: awk\n".as_bytes());
+        let providers = |name| packages.installed_provides.get(&packages.get_package_num(name)).map_or(0, |p| p.len());
+        assert_eq!((providers("awk"), providers("nawk")), (1, 0));
+    }
+
+    fn diagnostics(packages: &Packages) -> Vec<String> {
+        packages.load_diagnostics().iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn both_parsers_load_the_same_status_file() {
+        let data = std::fs::read(INSTALLED).unwrap();
+        let (mut streaming, mut regex) = (Packages::new(), Packages::new());
+        streaming.parse_installed_reader(INSTALLED, &data[..]);
+        regex.parse_installed_lines(INSTALLED, data[..].lines());
+        assert_eq!(streaming.installed_debvers.len(), 3774);
+        assert_eq!(diagnostics(&streaming), diagnostics(&regex));
+        assert!(streaming.snapshot() == regex.snapshot());
+    }
+
+    #[test]
+    fn both_parsers_load_the_same_index() {
+        let data = std::fs::read(INSTALLED).unwrap();
+        let (mut streaming, mut regex) = (Packages::new(), Packages::new());
+        streaming.parse_packages_reader(INSTALLED, &data[..]);
+        regex.parse_packages_lines(INSTALLED, data[..].lines());
+        assert_eq!(streaming.available_debvers.len(), 3774);
+        assert_eq!(diagnostics(&streaming), diagnostics(&regex));
+        assert!(streaming.snapshot() == regex.snapshot());
+    }
 }
@@ -0,0 +1,151 @@
+//! The line-by-line, regex-based parser that the streaming one replaced. It is only built for the tests,
+//! which check that both parsers agree, and with the regex-baseline feature, for benches/parse.rs.
+use std::io::{self, BufRead};
+
+use regex::{Captures, Regex};
+
+use crate::Packages;
+use crate::debversion;
+use crate::packages::interner::PackageId;
+use crate::packages::stanza::{Field, Relation, Stanza};
+use super::{relation_syntax_warning, Syntax};
+
+// whitespace around the parenthesis and the relation is optional, as policy allows; see check_relation_syntax
+const PKGNAME_AND_VERSION_REGEX : &str = r"(?P<pkg>(\w|\.|\+|-)+)(?P<arch>:(\w|-)+)?(\s*\(\s*(?P<op>(<|=|>)(<|=|>)?)\s*(?P<ver>[^\s()]+)\s*\))?";
+const SOURCE_REGEX : &str = r"(?P<src>\S+)( \((?P<ver>.*)\))?";
+
+impl Packages {
+    /// Same as parse_installed_reader, with the line-by-line, regex-based parser that it replaced.
+    pub fn parse_installed_lines<B: BufRead>(&mut self, filename: &str, lines: io::Lines<B>) {
+        let raws : Vec<RawStanza> = RawStanzas::new(lines).collect();
+        for checked in self.check_all(&Syntax::regex(), filename, raws.iter().map(RawStanza::view)).unwrap_or_default() {
+            self.store_installed(checked);
+        }
+    }
+
+    /// Same as parse_packages_reader, with the line-by-line, regex-based parser that it replaced.
+    pub fn parse_packages_lines<B: BufRead>(&mut self, filename: &str, lines: io::Lines<B>) -> Vec<PackageId> {
+        let raws : Vec<RawStanza> = RawStanzas::new(lines).collect();
+        let checked = self.check_all(&Syntax::regex(), filename, raws.iter().map(RawStanza::view)).unwrap_or_default();
+        checked.into_iter().map(|c| self.store_available(c)).collect()
+    }
+}
+
+impl Syntax {
+    fn regex() -> Syntax {
+        Syntax::Regex { pkgver: Regex::new(PKGNAME_AND_VERSION_REGEX).unwrap(), source: Regex::new(SOURCE_REGEX).unwrap() }
+    }
+}
+
+/// The name and version of a Source: field with SOURCE_REGEX.
+pub(super) fn parse_source<'a>(source: &Regex, value: &'a str) -> Option<(&'a str, Option<&'a str>)> {
+    let caps = source.captures(value)?;
+    Some((caps.name("src").unwrap().as_str(), caps.name("ver").map(|v| v.as_str().trim())))
+}
+
+/// Parses a comma-separated list of (possibly versioned) alternatives with PKGNAME_AND_VERSION_REGEX.
+/// Relations that parse only leniently add a warning to warnings.
+pub(super) fn parse_relations<'a>(pkgver_regexp: &Regex, value: &'a str, warnings: &mut Vec<String>) -> Result<Vec<Vec<Relation<'a>>>, String> {
+    let mut relations = vec![];
+    if value.is_empty() {
+        return Ok(relations);
+    }
+    for relation in value.split(',') {
+        let mut alternatives = vec![];
+        for alt in relation.split('|') {
+            let caps = pkgver_regexp.captures(alt).ok_or_else(|| format!("malformed relation {:?}", alt.trim()))?;
+            let rel_version = match (caps.name("op"), caps.name("ver")) {
+                (Some(op), Some(ver)) => {
+                    let op = op.as_str().parse::<debversion::VersionRelation>().map_err(|e| format!("{} in {:?}", e, alt.trim()))?;
+                    debversion::check_version_syntax(ver.as_str()).map_err(|e| format!("invalid version in {:?}: {}", alt.trim(), e))?;
+                    Some((op, ver.as_str()))
+                }
+                _ => None,
+            };
+            warnings.extend(check_relation_syntax(&caps));
+            alternatives.push((caps.name("pkg").unwrap().as_str(), rel_version));
+        }
+        relations.push(alternatives);
+    }
+    Ok(relations)
+}
+
+// a field as read line by line by RawStanzas, continuation lines joined by newlines
+struct RawField {
+    name : String,
+    value : String,
+    line : usize,
+}
+
+struct RawStanza {
+    line : usize,
+    fields : Vec<RawField>,
+    problems : Vec<(usize, String)>,
+}
+
+impl RawStanza {
+    fn new(line: usize) -> RawStanza {
+        RawStanza { line, fields: vec![], problems: vec![] }
+    }
+
+    fn view(&self) -> Stanza<'_> {
+        let fields = self.fields.iter().map(|f| Field { name: &f.name, value: &f.value, line: f.line }).collect();
+        Stanza { line: self.line, fields, problems: self.problems.clone() }
+    }
+}
+
+/// Splits a control file into stanzas line by line, as the original parser did. A line that is neither a
+/// field nor a continuation belongs to the stanza it's in, or starts one.
+struct RawStanzas<B> {
+    lines : std::iter::Enumerate<io::Lines<B>>,
+}
+
+impl<B: BufRead> RawStanzas<B> {
+    fn new(lines: io::Lines<B>) -> RawStanzas<B> {
+        RawStanzas { lines: lines.enumerate() }
+    }
+}
+
+impl<B: BufRead> Iterator for RawStanzas<B> {
+    type Item = RawStanza;
+
+    fn next(&mut self) -> Option<RawStanza> {
+        let mut stanza : Option<RawStanza> = None;
+        for (i, line) in &mut self.lines {
+            let n = i + 1;
+            let line = match line {
+                Err(_) => {
+                    stanza.get_or_insert_with(|| RawStanza::new(n)).problems.push((n, String::from("line isn't valid UTF-8")));
+                    continue
+                }
+                Ok(l) => l
+            };
+            if line.trim().is_empty() {
+                if stanza.is_some() { break } else { continue }
+            }
+            let s = stanza.get_or_insert_with(|| RawStanza::new(n));
+            if line.starts_with(' ') || line.starts_with('\t') {
+                match s.fields.last_mut() {
+                    None => s.problems.push((n, String::from("continuation line outside a field"))),
+                    Some(f) => { f.value.push('\n'); f.value.push_str(line.trim()); }
+                }
+                continue;
+            }
+            match line.split_once(':') {
+                Some((name, value)) if !name.is_empty() && !name.contains(char::is_whitespace) =>
+                    s.fields.push(RawField { name: name.to_string(), value: value.trim().to_string(), line: n }),
+                _ => s.problems.push((n, format!("not a field: {:?}", line))),
+            }
+        }
+        stanza
+    }
+}
+
+/// Returns what's wrong with a relation that parsed, but only leniently: a deprecated "<" or ">",
+/// or spacing other than "name (op version)".
+fn check_relation_syntax(caps: &Captures) -> Option<String> {
+    let (op, ver) = (caps.name("op")?.as_str(), caps.name("ver")?.as_str());
+    let written = caps.get(0).unwrap();
+    let qualified_end = caps.name("arch").unwrap_or_else(|| caps.name("pkg").unwrap()).end();
+    relation_syntax_warning(written.as_str(), &written.as_str()[..qualified_end - written.start()], op, ver)
+}
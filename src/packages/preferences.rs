@@ -0,0 +1,469 @@
+use std::fmt;
+
+use regex::Regex;
+
+use crate::debversion::DebianVersionNum;
+use crate::Packages;
+use crate::packages::Candidate;
+use crate::packages::repo::PackageOrigin;
+use crate::packages::interner::PackageId;
+
+// default priorities, as in apt_preferences(5)
+const DEFAULT_PRIORITY : i32 = 500;
+const NOT_AUTOMATIC_PRIORITY : i32 = 1;
+const BUT_AUTOMATIC_UPGRADES_PRIORITY : i32 = 100;
+const INSTALLED_PRIORITY : i32 = 100;
+// a version older than the installed one is only picked with a priority above this
+const DOWNGRADE_PRIORITY : i32 = 1000;
+
+/// A package name, version or release field pattern: a glob ("lib*"), or a regex between slashes ("/^lib/").
+pub struct Pattern {
+    text : String,
+    regex : Regex,
+}
+
+impl Pattern {
+    pub fn new(text: &str) -> Result<Pattern, String> {
+        let re = if text.len() > 1 && text.starts_with('/') && text.ends_with('/') {
+            text[1..text.len() - 1].to_string()
+        } else {
+            let mut re = String::from("^");
+            for c in text.chars() {
+                match c {
+                    '*' => re.push_str(".*"),
+                    '?' => re.push('.'),
+                    _ => re.push_str(&regex::escape(&c.to_string())),
+                }
+            }
+            re.push('$');
+            re
+        };
+        match Regex::new(&re) {
+            Ok(regex) => Ok(Pattern { text: text.to_string(), regex }),
+            Err(e) => Err(format!("bad pattern {:?}: {}", text, e)),
+        }
+    }
+
+    pub fn matches(&self, s: &str) -> bool {
+        self.regex.is_match(s)
+    }
+}
+
+/// What a pin selects, from the Pin: line of a preferences stanza.
+pub enum PinTarget {
+    /// Pin: version 2.1*
+    Version(Pattern),
+    /// Pin: release a=unstable, c=main; keys are a (suite), n (codename), o (origin), l (label),
+    /// c (component) and b (architecture)
+    Release(Vec<(char, Pattern)>),
+    /// Pin: origin <site>; the site is the root given to load-repo, and "" means flat files
+    Origin(String),
+}
+
+pub struct Pin {
+    packages : Vec<Pattern>,
+    target : PinTarget,
+    pub priority : i32,
+}
+
+impl fmt::Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let packages = self.packages.iter().map(|p| &p.text[..]).collect::<Vec<&str>>().join(" ");
+        let target = match &self.target {
+            PinTarget::Version(v) => format!("version {}", v.text),
+            PinTarget::Release(conds) =>
+                format!("release {}", conds.iter().map(|(k, v)| format!("{}={}", k, v.text)).collect::<Vec<String>>().join(", ")),
+            PinTarget::Origin(site) => format!("origin {:?}", site),
+        };
+        write!(f, "{} {}: {}", packages, target, self.priority)
+    }
+}
+
+impl Pin {
+    /// The pin as a preferences file stanza, which parse_preferences reads back.
+    pub fn to_stanza(&self) -> String {
+        let packages = self.packages.iter().map(|p| &p.text[..]).collect::<Vec<&str>>().join(" ");
+        let target = match &self.target {
+            PinTarget::Version(v) => format!("version {}", v.text),
+            PinTarget::Release(conds) =>
+                format!("release {}", conds.iter().map(|(k, v)| format!("{}={}", k, v.text)).collect::<Vec<String>>().join(", ")),
+            PinTarget::Origin(site) => format!("origin \"{}\"", site),
+        };
+        format!("Package: {}\nPin: {}\nPin-Priority: {}\n", packages, target, self.priority)
+    }
+
+    fn matches(&self, package_name: &str, version: &DebianVersionNum, origin: Option<&PackageOrigin>) -> bool {
+        if !self.packages.iter().any(|p| p.matches(package_name)) {
+            return false;
+        }
+        match (&self.target, origin) {
+            (PinTarget::Version(v), _) => v.matches(&version.to_string()),
+            (PinTarget::Origin(site), None) => site.is_empty(),
+            (PinTarget::Origin(site), Some(o)) => site == &o.site,
+            (PinTarget::Release(_), None) => false,
+            (PinTarget::Release(conds), Some(o)) => conds.iter().all(|(key, value)| {
+                let field = match key {
+                    'a' => &o.suite,
+                    'n' => &o.codename,
+                    'o' => &o.origin,
+                    'l' => &o.label,
+                    'c' => &o.component,
+                    'b' => &o.architecture,
+                    _ => return false,
+                };
+                value.matches(field)
+            }),
+        }
+    }
+}
+
+fn parse_pin_target(pin: &str) -> Result<PinTarget, String> {
+    let (kind, value) = match pin.trim().split_once(' ') {
+        None => return Err(format!("bad Pin: {:?}", pin)),
+        Some((k, v)) => (k, v.trim())
+    };
+    match kind {
+        "version" => Ok(PinTarget::Version(Pattern::new(value)?)),
+        "origin" => Ok(PinTarget::Origin(value.trim_matches('"').to_string())),
+        "release" => {
+            let mut conds = vec![];
+            for cond in value.split(',') {
+                let (key, val) = match cond.trim().split_once('=') {
+                    // a bare "release unstable" names the suite
+                    None => ("a", cond.trim()),
+                    Some((k, v)) => (k.trim(), v.trim())
+                };
+                match key {
+                    "a" | "n" | "o" | "l" | "c" | "b" => conds.push((key.chars().next().unwrap(), Pattern::new(val)?)),
+                    _ => return Err(format!("unsupported release key {:?} in Pin: {:?}", key, pin)),
+                }
+            }
+            Ok(PinTarget::Release(conds))
+        }
+        _ => Err(format!("unsupported Pin: {:?}", pin)),
+    }
+}
+
+/// Parses an apt_preferences(5) file: stanzas of Package:, Pin: and Pin-Priority: separated by blank lines,
+/// which as in control files are lines of nothing but whitespace.
+pub fn parse_preferences(contents: &str) -> Result<Vec<Pin>, String> {
+    let mut stanzas : Vec<Vec<&str>> = vec![vec![]];
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            if !stanzas.last().unwrap().is_empty() { stanzas.push(vec![]); }
+        } else {
+            stanzas.last_mut().unwrap().push(line);
+        }
+    }
+    let mut pins = vec![];
+    for (i, stanza) in stanzas.iter().enumerate() {
+        let mut packages = None;
+        let mut target = None;
+        let mut priority = None;
+        for line in stanza {
+            if line.starts_with('#') { continue; }
+            let (key, value) = match line.split_once(':') {
+                None => return Err(format!("stanza {}: bad line {:?}", i + 1, line)),
+                Some((k, v)) => (k.trim(), v.trim())
+            };
+            match key {
+                "Package" => packages = Some(value.split_whitespace().map(Pattern::new).collect::<Result<Vec<Pattern>, String>>()?),
+                "Pin" => target = Some(parse_pin_target(value)?),
+                "Pin-Priority" => priority = Some(value.parse::<i32>()
+                    .map_err(|_| format!("stanza {}: bad Pin-Priority {:?}", i + 1, value))?),
+                _ => ()
+            }
+        }
+        match (packages, target, priority) {
+            (None, None, None) => (),
+            (Some(packages), Some(target), Some(priority)) => pins.push(Pin { packages, target, priority }),
+            _ => return Err(format!("stanza {}: needs Package, Pin and Pin-Priority", i + 1)),
+        }
+    }
+    Ok(pins)
+}
+
+impl Packages {
+    /// Stores the stanzas just parsed for package_nums as candidates from origin, replacing an earlier
+    /// candidate with the same version from the same place. Call select_candidates afterwards.
+    pub fn record_candidates(&mut self, package_nums: &[PackageId], origin: Option<PackageOrigin>) {
+        for package_num in package_nums {
+            let version = match self.available_debvers.get(package_num) {
+                None => continue,
+                Some(v) => v.clone()
+            };
+            let candidate = Candidate {
+                version,
+                md5sum: self.md5sums.get(package_num).cloned(),
+                dependencies: self.dependencies.get(package_num).cloned().unwrap_or_default(),
+                pre_dependencies: self.pre_dependencies.get(package_num).cloned().unwrap_or_default(),
+                provides: self.provides.get(package_num).cloned().unwrap_or_default(),
+                source: self.available_sources.get(package_num).cloned(),
+                origin: origin.clone(),
+                fields: self.available_fields.get(package_num).cloned().unwrap_or_default(),
+            };
+            let same_place = |c: &Candidate| match (&c.origin, &candidate.origin) {
+                (None, None) => true,
+                (Some(a), Some(b)) => a.site == b.site && a.suite == b.suite && a.component == b.component && a.architecture == b.architecture,
+                _ => false
+            };
+            let candidates = self.available_candidates.entry(*package_num).or_default();
+            candidates.retain(|c| !(c.version == candidate.version && same_place(c)));
+            candidates.push(candidate);
+        }
+    }
+
+    /// Returns the priority of a candidate: that of the first matching pin, or the default for its origin.
+    fn candidate_priority(&self, package_name: &str, candidate: &Candidate) -> i32 {
+        for pin in &self.preferences {
+            if pin.matches(package_name, &candidate.version, candidate.origin.as_ref()) {
+                return pin.priority;
+            }
+        }
+        match &candidate.origin {
+            Some(o) if o.not_automatic && o.but_automatic_upgrades => BUT_AUTOMATIC_UPGRADES_PRIORITY,
+            Some(o) if o.not_automatic => NOT_AUTOMATIC_PRIORITY,
+            _ => DEFAULT_PRIORITY,
+        }
+    }
+
+    /// Index and priority of the candidate of package_num that keep allows with the highest non-negative
+    /// priority, then the highest version.
+    fn highest_candidate(&self, package_num: PackageId, keep: impl Fn(&Candidate, i32) -> bool) -> Option<(usize, i32)> {
+        let name = self.get_package_name(package_num);
+        let candidates = self.available_candidates.get(&package_num)?;
+        candidates.iter().enumerate()
+            .map(|(i, c)| (i, self.candidate_priority(name, c)))
+            .filter(|(i, priority)| *priority >= 0 && keep(&candidates[*i], *priority))
+            .max_by(|(i, p), (j, q)| p.cmp(q).then_with(|| candidates[*i].version.cmp(&candidates[*j].version)))
+    }
+
+    /// Like highest_candidate, but a version older than the installed one needs a priority above 1000.
+    fn highest_non_downgrade(&self, package_num: PackageId) -> Option<(usize, i32)> {
+        let installed = self.installed_debvers.get(&package_num);
+        self.highest_candidate(package_num, |c, priority|
+            priority > DOWNGRADE_PRIORITY || installed.is_none_or(|v| c.version >= *v))
+    }
+
+    /// Index of the candidate of package_num with the highest priority, then the highest version. As in apt,
+    /// the installed version competes at priority 100, and a version older than it is only picked with a
+    /// priority above 1000. None if no candidate has a non-negative priority, or the installed version
+    /// wins and no candidate has it.
+    fn best_candidate(&self, package_num: PackageId) -> Option<usize> {
+        let candidates = self.available_candidates.get(&package_num)?;
+        let installed = self.installed_debvers.get(&package_num);
+        let best = self.highest_non_downgrade(package_num);
+        match (best, installed) {
+            (Some((i, priority)), Some(v)) if priority < INSTALLED_PRIORITY && candidates[i].version > *v =>
+                candidates.iter().position(|c| c.version == *v),
+            (best, _) => best.map(|(i, _)| i),
+        }
+    }
+
+    /// True when package_num is installed and its installed version beats every candidate, so that no
+    /// available version is an upgrade even if it is newer.
+    fn installed_wins(&self, package_num: PackageId) -> bool {
+        self.installed_debvers.contains_key(&package_num) && self.available_candidates.contains_key(&package_num)
+            && self.best_candidate(package_num).is_none()
+    }
+
+    /// True when package_num is installed and the loaded preferences pick a newer available version.
+    pub(crate) fn is_upgradable(&self, package_num: PackageId) -> bool {
+        matches!((self.available_debvers.get(&package_num), self.installed_debvers.get(&package_num)), (Some(a), Some(i)) if a > i)
+            && !self.installed_wins(package_num)
+    }
+
+    /// Makes the best candidate of every package with candidates its available version, so that info,
+    /// how-to-install and Newer-Available all follow the loaded preferences. When the installed version
+    /// wins, the available version is still the one the installed version beat (or else the highest
+    /// priority candidate), so info shows where it comes from, but is_upgradable says it isn't an upgrade.
+    pub fn select_candidates(&mut self) {
+        let package_nums : Vec<PackageId> = self.available_candidates.keys().cloned().collect();
+        for package_num in package_nums {
+            let best = self.best_candidate(package_num)
+                .or_else(|| self.highest_non_downgrade(package_num).map(|(i, _)| i))
+                .or_else(|| self.highest_candidate(package_num, |_, _| true).map(|(i, _)| i));
+            self.available_sources.remove(&package_num);
+            self.package_origins.remove(&package_num);
+            self.md5sums.remove(&package_num);
+            self.pre_dependencies.remove(&package_num);
+            self.provides.remove(&package_num);
+            self.available_fields.remove(&package_num);
+            match best {
+                None => {
+                    self.available_debvers.remove(&package_num);
+                    self.dependencies.insert(package_num, vec![]);
+                }
+                Some(i) => {
+                    let c = &self.available_candidates.get(&package_num).unwrap()[i];
+                    let (version, md5sum, deps) = (c.version.clone(), c.md5sum.clone(), c.dependencies.clone());
+                    let (pre_deps, source, origin) = (c.pre_dependencies.clone(), c.source.clone(), c.origin.clone());
+                    let (provides, fields) = (c.provides.clone(), c.fields.clone());
+                    self.available_debvers.insert(package_num, version);
+                    self.dependencies.insert(package_num, deps);
+                    if !pre_deps.is_empty() { self.pre_dependencies.insert(package_num, pre_deps); }
+                    if !provides.is_empty() { self.provides.insert(package_num, provides); }
+                    if let Some(m) = md5sum { self.md5sums.insert(package_num, m); }
+                    if let Some(s) = source { self.available_sources.insert(package_num, s); }
+                    if let Some(o) = origin { self.package_origins.insert(package_num, o); }
+                    self.available_fields.insert(package_num, fields);
+                }
+            }
+        }
+        self.index_available_provides();
+        self.index_sources();
+        self.build_search_index();
+    }
+
+    /// Rebuilds available_provides from what the available versions provide.
+    pub(crate) fn index_available_provides(&mut self) {
+        self.available_provides.clear();
+        for (package_num, provided) in &self.provides {
+            if !self.available_debvers.contains_key(package_num) { continue; }
+            for (virtual_num, version) in provided {
+                self.available_provides.entry(*virtual_num).or_default().push((*package_num, version.clone()));
+            }
+        }
+        for providers in self.available_provides.values_mut() {
+            providers.sort_by_key(|(pn, _)| *pn);
+        }
+    }
+
+    /// Loads an apt_preferences(5) file, replacing any loaded earlier, and reselects candidates.
+    pub fn load_preferences(&mut self, filename: &str) {
+        match self.read_preferences(filename) {
+            Err(e) => self.fail(&format!("Error: {}", e)),
+            Ok(n) => self.progress(&format!("Pins loaded: {}", n)),
+        }
+    }
+
+    /// Like load_preferences, but returns errors instead of printing them, and prints nothing.
+    /// Returns the number of pins loaded.
+    pub fn read_preferences(&mut self, filename: &str) -> Result<usize, String> {
+        let contents = std::fs::read_to_string(filename).map_err(|e| format!("can't read {}: {}", filename, e))?;
+        let pins = parse_preferences(&contents).map_err(|e| format!("{}: {}", filename, e))?;
+        self.preferences = pins;
+        self.select_candidates();
+        Ok(self.preferences.len())
+    }
+
+    /// Prints the installed version, the candidate, and every available version with its priority, like apt-cache policy.
+    pub fn print_policy(&self, package_name: &str) {
+        if !self.package_exists(package_name) {
+            self.fail(&format!("no such package {}", package_name));
+            return;
+        }
+        let package_num = self.get_package_num(package_name);
+        println!("{}:", package_name);
+        let none = String::from("(none)");
+        println!("  Installed: {}", self.get_installed_debver(package_name).map(|v| v.to_string()).unwrap_or_else(|| none.clone()));
+        let candidate = match self.installed_wins(package_num) {
+            true => self.get_installed_debver(package_name),
+            false => self.get_available_debver(package_name).or_else(|| self.get_installed_debver(package_name)),
+        };
+        println!("  Candidate: {}", candidate.map(|v| v.to_string()).unwrap_or(none));
+        println!("  Version table:");
+        let best = self.best_candidate(package_num);
+        let candidates = self.available_candidates.get(&package_num).map(|c| &c[..]).unwrap_or_default();
+        // version, priority, where it's from, and whether it's the one chosen
+        let mut rows : Vec<(&DebianVersionNum, i32, String, bool)> = candidates.iter().enumerate().map(|(i, c)| {
+            let from = match &c.origin {
+                None => String::from("(flat file)"),
+                Some(o) => format!("{} {} {}/{} {}", o.site, o.origin, o.suite, o.component, o.architecture),
+            };
+            (&c.version, self.candidate_priority(package_name, c), from, Some(i) == best)
+        }).collect();
+        if let Some(installed) = self.installed_debvers.get(&package_num) {
+            if !candidates.iter().any(|c| c.version == *installed) {
+                // no candidate is chosen when the installed version is
+                rows.push((installed, INSTALLED_PRIORITY, String::from("(installed)"), best.is_none()));
+            }
+        }
+        rows.sort_by(|a, b| b.0.cmp(a.0));
+        for (version, priority, from, chosen) in rows {
+            println!(" {} {} {}", if chosen { "***" } else { "   " }, version, priority);
+            println!("        {}", from);
+        }
+    }
+
+    pub fn print_preferences(&self) {
+        for pin in &self.preferences {
+            println!("{}", pin);
+        }
+        println!("Pins loaded: {}", self.preferences.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // foo 1.0 and 3.0 available from flat files, 2.0 installed
+    fn packages(preferences: &str) -> Packages {
+        let mut packages = Packages::new();
+        for version in ["1.0", "3.0"] {
+            let stanza = format!("Package: foo\nVersion: {}\n", version);
+            let package_nums = packages.parse_packages_reader("Packages", stanza.as_bytes());
+            packages.record_candidates(&package_nums, None);
+        }
+        packages.parse_installed_reader("status", "Package: foo\nStatus: install ok installed\nVersion: 2.0\n".as_bytes());
+        packages.preferences = parse_preferences(preferences).unwrap();
+        packages.select_candidates();
+        packages
+    }
+
+    // the available version, and whether it is an upgrade
+    fn candidate(preferences: &str) -> (Option<String>, bool) {
+        let packages = packages(preferences);
+        let available = packages.get_available_debver("foo").map(|v| v.to_string());
+        (available, packages.is_upgradable(packages.get_package_num("foo")))
+    }
+
+    fn pin(version: &str, priority: i32) -> String {
+        format!("Package: foo\nPin: version {}\nPin-Priority: {}\n", version, priority)
+    }
+
+    #[test]
+    fn highest_version_wins_by_default() {
+        assert_eq!(candidate(""), (Some(String::from("3.0")), true));
+    }
+
+    #[test]
+    fn installed_version_beats_a_newer_one_below_priority_100() {
+        // 3.0 stays the available version, for info, but isn't an upgrade
+        assert_eq!(candidate(&pin("3.0", 99)), (Some(String::from("3.0")), false));
+        assert_eq!(candidate(&pin("3.0", 100)), (Some(String::from("3.0")), true));
+    }
+
+    #[test]
+    fn downgrade_needs_a_priority_above_1000() {
+        let no_newer = |priority| format!("{}\n{}", pin("1.0", priority), pin("3.0", -1));
+        let installed_wins = |priority| {
+            let packages = packages(&no_newer(priority));
+            packages.installed_wins(packages.get_package_num("foo"))
+        };
+        assert!(installed_wins(1000));
+        assert!(!installed_wins(1001));
+        assert_eq!(candidate(&no_newer(1001)), (Some(String::from("1.0")), false));
+        assert_eq!(candidate(&format!("{}\n{}", pin("1.0", 990), pin("3.0", 500))), (Some(String::from("3.0")), true));
+    }
+
+    #[test]
+    fn info_keeps_the_repository_version_when_the_installed_one_wins() {
+        let info = packages(&pin("3.0", 99)).info_report("foo").unwrap();
+        assert_eq!(info.version.as_deref(), Some("3.0"));
+        assert_eq!(info.installed_version.as_deref(), Some("2.0"));
+        assert_eq!(info.newer_available, Some(false));
+    }
+
+    #[test]
+    fn stanzas_are_split_on_whitespace_only_lines() {
+        let crlf = format!("{}\r\n{}", pin("1.0", 990).replace('\n', "\r\n"), pin("3.0", 500).replace('\n', "\r\n"));
+        assert_eq!(parse_preferences(&crlf).unwrap().len(), 2);
+        let spaces = format!("{} \t\n\n\n{}", pin("1.0", 990), pin("3.0", 500));
+        let pins = parse_preferences(&spaces).unwrap();
+        assert_eq!(pins.iter().map(|p| p.priority).collect::<Vec<i32>>(), [990, 500]);
+        assert!(parse_preferences("Package: foo\nPin: version 1.0\n  \nPin-Priority: 1\n").is_err());
+    }
+}
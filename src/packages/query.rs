@@ -0,0 +1,395 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::debversion::{self, DebianVersionNum, VersionRelation};
+use crate::Packages;
+use crate::packages::Dependency;
+use crate::packages::interner::PackageId;
+
+/// A package named exactly name, with an optional version constraint, as in "libc6 (>= 2.34)".
+pub struct PackageSpec {
+    pub name : String,
+    pub constraint : Option<(VersionRelation, DebianVersionNum)>,
+}
+
+/// What ?depends and ?reverse-depends apply to: another pattern, or a package spec.
+pub enum Target {
+    Pattern(Box<Pattern>),
+    Spec(PackageSpec),
+}
+
+/// An aptitude-style search pattern, e.g. `?depends(libc6 (>= 2.34)) & ?installed & !?upgradable`.
+pub enum Pattern {
+    Installed,
+    Upgradable,
+    /// ?name(regex), or a bare word
+    Name(Regex),
+    /// ?version(regex) on the version string
+    VersionMatches(Regex),
+    /// ?version(op version), e.g. ?version(>= 1.0)
+    VersionCompare(VersionRelation, DebianVersionNum),
+    Section(Regex),
+    Depends(Target),
+    ReverseDepends(Target),
+    Provides(String),
+    Not(Box<Pattern>),
+    And(Box<Pattern>, Box<Pattern>),
+    Or(Box<Pattern>, Box<Pattern>),
+}
+
+fn regex(s: &str) -> Result<Regex, String> {
+    RegexBuilder::new(s).case_insensitive(true).build().map_err(|e| format!("bad regex {}: {}", s, e))
+}
+
+/// Splits "op version" into its parts if s starts with a relation operator; the version must be valid.
+fn parse_constraint(s: &str) -> Option<Result<(VersionRelation, DebianVersionNum), String>> {
+    let s = s.trim();
+    let op = ["<<", "<=", ">=", ">>", "="].iter().find(|op| s.starts_with(**op))?;
+    let version = s[op.len()..].trim();
+    if version.is_empty() {
+        return Some(Err(format!("missing version after {}", op)));
+    }
+    if let Err(e) = debversion::check_version_syntax(version) {
+        return Some(Err(format!("invalid version {:?}: {}", version, e)));
+    }
+    Some(Ok((op.parse::<VersionRelation>().unwrap(), version.parse::<DebianVersionNum>().unwrap())))
+}
+
+impl FromStr for PackageSpec {
+    type Err = String;
+    fn from_str(s: &str) -> Result<PackageSpec, String> {
+        let s = s.trim();
+        let (name, constraint) = match s.find('(') {
+            None => (s, None),
+            Some(i) => {
+                let inner = s[i + 1..].strip_suffix(')').ok_or_else(|| format!("bad package spec {}", s))?;
+                match parse_constraint(inner) {
+                    Some(c) => (s[..i].trim(), Some(c?)),
+                    None => return Err(format!("bad version constraint ({})", inner)),
+                }
+            }
+        };
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(format!("bad package spec {}", s));
+        }
+        Ok(PackageSpec { name: name.to_string(), constraint })
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Target, String> {
+        match s.trim_start().chars().next() {
+            Some('?') | Some('!') | Some('(') => Ok(Target::Pattern(Box::new(s.parse()?))),
+            _ => Ok(Target::Spec(s.parse()?)),
+        }
+    }
+}
+
+/// Recursive descent over the pattern grammar:
+///   or    := and ('|' and)*
+///   and   := unary ('&'? unary)*
+///   unary := '!' unary | '(' or ')' | '?' term ['(' argument ')'] | word
+struct Parser<'a> {
+    s : &'a str,
+    pos : usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        self.pos += self.s[self.pos..].len() - self.s[self.pos..].trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.s[self.pos..].chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Result<Pattern, String> {
+        let mut p = self.and()?;
+        while self.eat('|') {
+            p = Pattern::Or(Box::new(p), Box::new(self.and()?));
+        }
+        Ok(p)
+    }
+
+    fn and(&mut self) -> Result<Pattern, String> {
+        let mut p = self.unary()?;
+        loop {
+            if !self.eat('&') && matches!(self.peek(), None | Some('|') | Some(')')) {
+                return Ok(p);
+            }
+            p = Pattern::And(Box::new(p), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Pattern, String> {
+        match self.peek() {
+            None => Err(String::from("unexpected end of pattern")),
+            Some('!') => { self.pos += 1; Ok(Pattern::Not(Box::new(self.unary()?))) }
+            Some('(') => {
+                self.pos += 1;
+                let p = self.or()?;
+                if !self.eat(')') { return Err(String::from("missing )")); }
+                Ok(p)
+            }
+            Some('?') => { self.pos += 1; self.term() }
+            Some(c) => match self.word() {
+                "" => Err(format!("unexpected {:?} at offset {}", c, self.pos)),
+                word => Ok(Pattern::Name(regex(word)?)),
+            },
+        }
+    }
+
+    /// A run of characters that can't start or end a pattern.
+    fn word(&mut self) -> &'a str {
+        let rest = &self.s[self.pos..];
+        let len = rest.find(|c: char| c.is_whitespace() || "&|!()?".contains(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    /// The text up to the parenthesis closing the one just read, nested parentheses included.
+    fn argument(&mut self) -> Result<&'a str, String> {
+        let rest = &self.s[self.pos..];
+        let mut depth = 1;
+        for (i, c) in rest.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.pos += i + 1;
+                        return Ok(&rest[..i]);
+                    }
+                }
+                _ => (),
+            }
+        }
+        Err(String::from("missing )"))
+    }
+
+    fn term(&mut self) -> Result<Pattern, String> {
+        let name = self.word();
+        let arg = if self.s[self.pos..].starts_with('(') {
+            self.pos += 1;
+            Some(self.argument()?)
+        } else {
+            None
+        };
+        match (name, arg) {
+            ("installed", None) => Ok(Pattern::Installed),
+            ("upgradable", None) => Ok(Pattern::Upgradable),
+            ("name", Some(a)) => Ok(Pattern::Name(regex(a.trim())?)),
+            ("version", Some(a)) => match parse_constraint(a) {
+                Some(c) => { let (op, v) = c?; Ok(Pattern::VersionCompare(op, v)) }
+                None => Ok(Pattern::VersionMatches(regex(a.trim())?)),
+            },
+            ("section", Some(a)) => Ok(Pattern::Section(regex(a.trim())?)),
+            ("depends", Some(a)) => Ok(Pattern::Depends(a.parse()?)),
+            ("reverse-depends", Some(a)) => Ok(Pattern::ReverseDepends(a.parse()?)),
+            ("provides", Some(a)) => Ok(Pattern::Provides(a.trim().to_string())),
+            ("installed", Some(_)) | ("upgradable", Some(_)) => Err(format!("?{} takes no argument", name)),
+            ("name", None) | ("version", None) | ("section", None) | ("depends", None) | ("reverse-depends", None)
+                | ("provides", None) => Err(format!("?{} needs an argument", name)),
+            _ => Err(format!("unknown term ?{}", name)),
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Pattern, String> {
+        let mut parser = Parser { s, pos: 0 };
+        let p = parser.or()?;
+        match parser.peek() {
+            None => Ok(p),
+            Some(c) => Err(format!("unexpected {:?} at offset {}", c, parser.pos)),
+        }
+    }
+}
+
+impl Packages {
+    /// The dependencies a query looks at: those of the available version, or else of the installed one.
+    fn query_dependencies(&self, package_num: PackageId) -> Box<dyn Iterator<Item=&Dependency> + '_> {
+        if self.available_debvers.contains_key(&package_num) {
+            Box::new(self.install_dependencies(package_num))
+        } else {
+            Box::new(self.installed_dependencies.get(&package_num).into_iter().flatten())
+        }
+    }
+
+    /// Names package_num's available stanza, or else its installed one, says it provides.
+    pub(crate) fn provides_field(&self, package_num: PackageId) -> impl Iterator<Item=&str> {
+        self.get_field(package_num, "Provides").into_iter()
+            .flat_map(|p| p.split(','))
+            .filter_map(|p| p.split(|c: char| c.is_whitespace() || c == '(').find(|n| !n.is_empty()))
+    }
+
+    fn matches_spec(&self, spec: &PackageSpec, alt_num: PackageId, alt_constraint: &Option<(VersionRelation, String)>) -> bool {
+        if self.get_package_name(alt_num) != spec.name {
+            return false;
+        }
+        match (&spec.constraint, alt_constraint) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            // the dependency is at least as strict, in the same direction, as the spec
+            (Some((op, v)), Some((alt_op, alt_v))) => *op == *alt_op &&
+                debversion::cmp_debversion_with_op(op, &alt_v.parse::<DebianVersionNum>().unwrap(), v),
+        }
+    }
+
+    /// Evaluates pattern over every package with an available or installed version.
+    pub fn select(&self, pattern: &Pattern) -> HashSet<PackageId> {
+        let universe : HashSet<PackageId> = self.available_debvers.keys().chain(self.installed_debvers.keys()).cloned().collect();
+        self.evaluate(pattern, &universe)
+    }
+
+    fn evaluate(&self, pattern: &Pattern, universe: &HashSet<PackageId>) -> HashSet<PackageId> {
+        let version = |pn: &PackageId| self.available_debvers.get(pn).or_else(|| self.installed_debvers.get(pn));
+        let filter = |f: &dyn Fn(&PackageId) -> bool| universe.iter().filter(|pn| f(pn)).cloned().collect();
+        match pattern {
+            Pattern::Installed => filter(&|pn| self.installed_debvers.contains_key(pn)),
+            Pattern::Upgradable => filter(&|pn| self.is_upgradable(*pn)),
+            Pattern::Name(r) => filter(&|pn| r.is_match(self.get_package_name(*pn))),
+            Pattern::VersionMatches(r) => filter(&|pn| version(pn).is_some_and(|v| r.is_match(&v.to_string()))),
+            Pattern::VersionCompare(op, v) => filter(&|pn| version(pn).is_some_and(|pv| debversion::cmp_debversion_with_op(op, pv, v))),
+            Pattern::Section(r) => filter(&|pn| self.get_field(*pn, "Section").is_some_and(|s| r.is_match(s))),
+            Pattern::Depends(Target::Spec(spec)) => filter(&|pn| self.query_dependencies(*pn).flatten()
+                                                           .any(|alt| self.matches_spec(spec, alt.package_num, &alt.rel_version))),
+            Pattern::Depends(Target::Pattern(p)) => {
+                let targets = self.evaluate(p, universe);
+                filter(&|pn| self.query_dependencies(*pn).flatten().any(|alt| targets.contains(&alt.package_num)))
+            }
+            Pattern::ReverseDepends(target) => {
+                let sources : HashSet<PackageId> = match target {
+                    Target::Pattern(p) => self.evaluate(p, universe),
+                    // the constraint applies to the depending package's own version
+                    Target::Spec(spec) => filter(&|pn| self.get_package_name(*pn) == spec.name &&
+                        spec.constraint.as_ref().is_none_or(|(op, v)| version(pn).is_some_and(|pv| debversion::cmp_debversion_with_op(op, pv, v)))),
+                };
+                let depended_on : HashSet<PackageId> = sources.iter()
+                    .flat_map(|pn| self.query_dependencies(*pn).flatten().map(|alt| alt.package_num)).collect();
+                universe.intersection(&depended_on).cloned().collect()
+            }
+            Pattern::Provides(name) => {
+                let installed : HashSet<PackageId> = self.package_ids.get(name)
+                    .and_then(|v| self.installed_provides.get(&v)).into_iter().flatten().map(|(p, _)| *p).collect();
+                filter(&|pn| installed.contains(pn) || self.provides_field(*pn).any(|p| p == name))
+            }
+            Pattern::Not(p) => universe.difference(&self.evaluate(p, universe)).cloned().collect(),
+            Pattern::And(a, b) => self.evaluate(a, universe).intersection(&self.evaluate(b, universe)).cloned().collect(),
+            Pattern::Or(a, b) => self.evaluate(a, universe).union(&self.evaluate(b, universe)).cloned().collect(),
+        }
+    }
+
+    /// Parses and evaluates a pattern, returning the matching packages sorted by name.
+    pub fn query(&self, pattern: &str) -> Result<Vec<PackageId>, String> {
+        let pattern = pattern.parse::<Pattern>()?;
+        let mut results : Vec<PackageId> = self.select(&pattern).into_iter().collect();
+        results.sort_by_key(|pn| self.get_package_name(*pn));
+        Ok(results)
+    }
+
+    pub fn print_query(&self, pattern: &str) {
+        match self.query(pattern) {
+            Err(e) => self.fail(&format!("Error: {}", e)),
+            Ok(results) => self.print_package_list(results),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Packages;
+
+    const STATUS : &str = "\
+Package: app\nStatus: install ok installed\nVersion: 1.0\nDepends: libc6 (>= 2.31), awk\n\n\
+Package: libc6\nStatus: install ok installed\nVersion: 2.33\n\n\
+Package: mawk\nStatus: install ok installed\nVersion: 1.3\nProvides: awk\n";
+
+    const AVAILABLE : &str = "\
+Package: app\nVersion: 1.1\nDepends: libc6 (>= 2.34), mawk\n\n\
+Package: libc6\nVersion: 2.34\n\n\
+Package: gawk\nVersion: 5.0\nProvides: awk\n\n\
+Package: tool\nVersion: 0.5\nDepends: libc6\n";
+
+    fn loaded() -> Packages {
+        let mut p = Packages::new();
+        p.parse_installed_reader("status", STATUS.as_bytes());
+        let nums = p.parse_packages_reader("Packages", AVAILABLE.as_bytes());
+        p.record_candidates(&nums, None);
+        p.select_candidates();
+        p
+    }
+
+    fn query(p: &Packages, pattern: &str) -> Vec<String> {
+        p.query(pattern).unwrap().into_iter().map(|pn| p.get_package_name(pn).to_string()).collect()
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_than_or() {
+        let p = loaded();
+        assert_eq!(query(&p, "!?installed | ?name(^lib) & ?upgradable"), ["gawk", "libc6", "tool"]);
+        assert_eq!(query(&p, "(!?installed | ?name(^lib)) & ?upgradable"), ["libc6"]);
+        assert_eq!(query(&p, "!(?installed | ?name(gawk))"), ["tool"]);
+        // juxtaposition is &
+        assert_eq!(query(&p, "?installed ?upgradable"), ["app", "libc6"]);
+        assert_eq!(query(&p, "!!?upgradable"), ["app", "libc6"]);
+    }
+
+    #[test]
+    fn version_comparison() {
+        let p = loaded();
+        // the available version, or else the installed one
+        assert_eq!(query(&p, "?version(>= 1.1)"), ["app", "gawk", "libc6", "mawk"]);
+        assert_eq!(query(&p, "?version(<< 1.0)"), ["tool"]);
+        assert_eq!(query(&p, "?version(^2\\.)"), ["libc6"]);
+    }
+
+    #[test]
+    fn depends_with_a_version_spec() {
+        let p = loaded();
+        assert_eq!(query(&p, "?depends(libc6)"), ["app", "tool"]);
+        // app's available version needs >= 2.34, which is at least as strict as >= 2.30
+        assert_eq!(query(&p, "?depends(libc6 (>= 2.30))"), ["app"]);
+        assert!(query(&p, "?depends(libc6 (>= 2.35))").is_empty());
+        assert!(query(&p, "?depends(libc6 (<< 3))").is_empty());
+        assert_eq!(query(&p, "?depends(?provides(awk))"), ["app"]);
+        assert_eq!(query(&p, "?reverse-depends(tool)"), ["libc6"]);
+    }
+
+    #[test]
+    fn provides_looks_at_installed_and_available_packages() {
+        let p = loaded();
+        assert_eq!(query(&p, "?provides(awk)"), ["gawk", "mawk"]);
+        assert!(query(&p, "?provides(mawk)").is_empty());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let p = loaded();
+        let error = |pattern| p.query(pattern).err().unwrap();
+        assert_eq!(error("?installed &"), "unexpected end of pattern");
+        assert_eq!(error("(?installed"), "missing )");
+        assert_eq!(error("?name(app"), "missing )");
+        assert_eq!(error("?installed )"), "unexpected ')' at offset 11");
+        assert_eq!(error("?bogus"), "unknown term ?bogus");
+        assert_eq!(error("?depends"), "?depends needs an argument");
+        assert_eq!(error("?installed(x)"), "?installed takes no argument");
+        assert_eq!(error("?version(>=)"), "missing version after >=");
+        assert_eq!(error("?version(>= x)"), "invalid version \"x\": upstream version \"x\" doesn't start with a digit");
+        assert!(error("?depends(libc6 (>= 2.3 4))").starts_with("invalid version \"2.3 4\""));
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::openpgp::{self, Keyring, SignatureStatus, Verification};
+use crate::Packages;
+use crate::packages::output::{SignatureReport, VerifyIndexReport, VerifyReleaseReport};
+
+/// One line of a Release file table: "<hash> <size> <path>".
+pub struct ReleaseFileEntry {
+    pub size : u64,
+    pub md5sum : Option<String>,
+    pub sha256 : Option<String>,
+}
+
+/// A parsed repository Release (or the signed text of an InRelease) file.
+pub struct Release {
+    pub fields : HashMap<String, String>,
+    pub files : HashMap<String, ReleaseFileEntry>,
+}
+
+pub enum ReleaseError {
+    Io(String, std::io::Error),
+    Malformed(String),
+    NotListed(String),
+    Truncated { path: String, size: u64, expected: u64 },
+    SizeMismatch { path: String, size: u64, expected: u64 },
+    HashMismatch { path: String, algorithm: &'static str, hash: String, expected: String },
+}
+
+impl fmt::Display for ReleaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReleaseError::Io(file, e) => write!(f, "can't read {}: {}", file, e),
+            ReleaseError::Malformed(msg) => write!(f, "malformed Release file: {}", msg),
+            ReleaseError::NotListed(file) => write!(f, "{} is not listed in the loaded Release file", file),
+            ReleaseError::Truncated { path, size, expected } =>
+                write!(f, "{} is truncated: {} bytes, Release lists {}", path, size, expected),
+            ReleaseError::SizeMismatch { path, size, expected } =>
+                write!(f, "{} is stale: {} bytes, Release lists {}", path, size, expected),
+            ReleaseError::HashMismatch { path, algorithm, hash, expected } =>
+                write!(f, "{} is stale: {} {}, Release lists {}", path, algorithm, hash, expected),
+        }
+    }
+}
+
+impl Release {
+    pub fn parse(contents: &str) -> Result<Release, ReleaseError> {
+        // an InRelease file is the Release file, clear-signed
+        let text = match openpgp::split_clearsigned(contents) {
+            Ok((text, _)) => text,
+            Err(openpgp::PgpError::NotClearsigned) => contents.to_string(),
+            Err(e) => return Err(ReleaseError::Malformed(e.to_string())),
+        };
+        let mut fields = HashMap::new();
+        let mut files : HashMap<String, ReleaseFileEntry> = HashMap::new();
+        let mut current_key = String::new();
+
+        for line in text.lines() {
+            if line.trim().is_empty() { continue; }
+            if line.starts_with(' ') || line.starts_with('\t') {
+                let is_md5 = current_key == "MD5Sum";
+                let is_sha256 = current_key == "SHA256";
+                if !is_md5 && !is_sha256 { continue; }
+                let parts : Vec<&str> = line.split_whitespace().collect();
+                if parts.len() != 3 {
+                    return Err(ReleaseError::Malformed(format!("bad {} line {:?}", current_key, line)));
+                }
+                let size = parts[1].parse::<u64>()
+                    .map_err(|_| ReleaseError::Malformed(format!("bad size in {:?}", line)))?;
+                let entry = files.entry(parts[2].to_string())
+                    .or_insert(ReleaseFileEntry { size, md5sum: None, sha256: None });
+                if entry.size != size {
+                    return Err(ReleaseError::Malformed(format!("{} listed with sizes {} and {}", parts[2], entry.size, size)));
+                }
+                if is_md5 {
+                    entry.md5sum = Some(parts[0].to_lowercase());
+                } else {
+                    entry.sha256 = Some(parts[0].to_lowercase());
+                }
+                continue;
+            }
+            match line.split_once(':') {
+                None => return Err(ReleaseError::Malformed(format!("bad line {:?}", line))),
+                Some((key, value)) => {
+                    current_key = key.to_string();
+                    fields.insert(key.to_string(), value.trim().to_string());
+                }
+            }
+        }
+        if files.is_empty() {
+            return Err(ReleaseError::Malformed(String::from("no MD5Sum or SHA256 file table")));
+        }
+        Ok(Release { fields, files })
+    }
+
+    pub fn from_file(filename: &str) -> Result<Release, ReleaseError> {
+        let contents = std::fs::read_to_string(filename)
+            .map_err(|e| ReleaseError::Io(filename.to_string(), e))?;
+        Release::parse(&contents)
+    }
+
+    /// Finds the table entry describing filename. Matches either a path ending in the listed path
+    /// (e.g. dists/sid/main/binary-amd64/Packages) or apt's flattened lists/ naming, where '/' becomes '_'.
+    pub fn find_entry(&self, filename: &str) -> Option<(&str, &ReleaseFileEntry)> {
+        let normalized = filename.replace('\\', "/");
+        self.files.iter()
+            .filter(|(path, _)| {
+                let matches_suffix = |candidate: &str| {
+                    normalized == candidate || normalized.ends_with(&format!("/{}", candidate))
+                        || normalized.ends_with(&format!("_{}", candidate))
+                };
+                matches_suffix(path) || matches_suffix(&path.replace('/', "_"))
+            })
+            // prefer the most specific (longest) listed path
+            .max_by_key(|(path, _)| path.len())
+            .map(|(path, entry)| (&path[..], entry))
+    }
+
+    /// Checks that filename has the size and hash the Release file lists for it; SHA256 is
+    /// preferred over MD5Sum when both are present.
+    pub fn verify_file(&self, filename: &str) -> Result<(), ReleaseError> {
+        let (path, entry) = self.find_entry(filename)
+            .ok_or_else(|| ReleaseError::NotListed(filename.to_string()))?;
+        let contents = std::fs::read(Path::new(filename))
+            .map_err(|e| ReleaseError::Io(filename.to_string(), e))?;
+        let size = contents.len() as u64;
+        if size < entry.size {
+            return Err(ReleaseError::Truncated { path: path.to_string(), size, expected: entry.size });
+        }
+        if size != entry.size {
+            return Err(ReleaseError::SizeMismatch { path: path.to_string(), size, expected: entry.size });
+        }
+        let (algorithm, hash, expected) = match (&entry.sha256, &entry.md5sum) {
+            (Some(expected), _) => ("SHA256", format!("{:x}", Sha256::digest(&contents)), expected),
+            (None, Some(expected)) => ("MD5Sum", format!("{:x}", Md5::digest(&contents)), expected),
+            (None, None) => unreachable!("Release entries always carry a hash"),
+        };
+        if &hash != expected {
+            return Err(ReleaseError::HashMismatch { path: path.to_string(), algorithm, hash, expected: expected.clone() });
+        }
+        Ok(())
+    }
+}
+
+impl Packages {
+    /// Loads a Release or InRelease file; subsequent load-packages calls are verified against its file tables.
+    pub fn load_release(&mut self, filename: &str) {
+        match self.read_release(filename) {
+            Err(e) => self.fail(&format!("Error: {}", e)),
+            Ok(release) => {
+                let describe = |key: &str| release.fields.get(key).map(|v| &v[..]).unwrap_or("?").to_string();
+                let msg = format!("Release {} {} ({}): {} files listed",
+                                  describe("Origin"), describe("Suite"), describe("Codename"), release.files.len());
+                self.progress(&msg);
+            }
+        }
+    }
+
+    /// Forgets the loaded Release file, so later load-packages calls are no longer checked against it.
+    pub fn unload_release(&mut self) {
+        match self.release.take() {
+            None => self.fail("no Release file loaded"),
+            Some(_) => self.progress("Release file unloaded"),
+        }
+    }
+
+    /// Like load_release, but returns errors instead of printing them, and prints nothing.
+    pub fn read_release(&mut self, filename: &str) -> Result<&Release, ReleaseError> {
+        let release = Release::from_file(filename)?;
+        Ok(self.release.insert(release))
+    }
+
+    /// Verifies filename against the loaded Release file, if any.
+    pub fn verify_index(&self, filename: &str) -> Result<(), ReleaseError> {
+        match &self.release {
+            None => Ok(()),
+            Some(release) => release.verify_file(filename)
+        }
+    }
+
+    pub fn print_verify_index(&self, filename: &str) {
+        if self.release.is_none() {
+            self.fail("no Release file loaded");
+            return;
+        }
+        let error = self.verify_index(filename).err().map(|e| e.to_string());
+        let report = VerifyIndexReport { file: filename.to_string(), verified: error.is_none(), error };
+        if !report.verified {
+            self.mark_failed();
+        }
+        if self.emit_json(&report) { return; }
+        match &report.error {
+            None => println!("{} matches the loaded Release file", filename),
+            Some(e) => println!("Error: {}", e)
+        }
+    }
+
+    /// Checks the OpenPGP signature(s) of an InRelease file, or of a Release file with its detached
+    /// Release.gpg signature, against the keys in keyring.
+    pub fn verify_release_report(&self, release_file: &str, keyring_file: &str, detached_sig: Option<&str>) -> Result<VerifyReleaseReport, String> {
+        let keyring = Keyring::from_file(keyring_file).map_err(|e| e.to_string())?;
+        let verification : Verification = match detached_sig {
+            None => std::fs::read_to_string(release_file)
+                .map_err(|e| format!("can't read {}: {}", release_file, e))
+                .and_then(|contents| openpgp::verify_clearsigned(&contents, &keyring).map_err(|e| e.to_string())),
+            Some(sig_file) => match (std::fs::read(release_file), std::fs::read(sig_file)) {
+                (Ok(data), Ok(sig)) => openpgp::verify_detached(&data, &sig, &keyring).map_err(|e| e.to_string()),
+                (Err(e), _) => Err(format!("can't read {}: {}", release_file, e)),
+                (_, Err(e)) => Err(format!("can't read {}: {}", sig_file, e)),
+            }
+        }?;
+        let signatures = verification.signatures.iter().map(|sig| {
+            let (status, unsupported) = match &sig.status {
+                SignatureStatus::Good => ("good", None),
+                SignatureStatus::Bad => ("bad", None),
+                SignatureStatus::UnknownKey => ("unknown-key", None),
+                SignatureStatus::Unsupported(what) => ("unsupported", Some(what.to_string())),
+            };
+            SignatureReport {
+                issuer: sig.issuer.clone(),
+                status: status.to_string(),
+                fingerprint: sig.key.as_ref().map(|(fpr, _)| fpr.clone()),
+                primary_fingerprint: sig.key.as_ref().map(|(_, primary)| primary.clone()),
+                unsupported,
+            }
+        }).collect();
+        Ok(VerifyReleaseReport { file: release_file.to_string(), verified: verification.is_valid(), signatures })
+    }
+
+    /// Prints the result of verify_release_report: the signing key fingerprints, and whether the file is verified.
+    pub fn verify_release(&self, release_file: &str, keyring_file: &str, detached_sig: Option<&str>) {
+        let report = match self.verify_release_report(release_file, keyring_file, detached_sig) {
+            Err(e) => { self.fail(&format!("Error: {}", e)); return }
+            Ok(r) => r
+        };
+        if !report.verified {
+            self.mark_failed();
+        }
+        if self.emit_json(&report) { return; }
+        for sig in &report.signatures {
+            match (sig.status.as_str(), &sig.fingerprint, &sig.primary_fingerprint) {
+                ("good", Some(fpr), Some(primary)) if fpr != primary =>
+                    println!("Good signature from key {} (subkey {})", primary, fpr),
+                ("good", Some(fpr), _) => println!("Good signature from key {}", fpr),
+                ("bad", Some(fpr), _) => println!("BAD signature from key {}", fpr),
+                ("unknown-key", _, _) => println!("No public key for issuer {}", sig.issuer),
+                ("unsupported", _, _) => println!("Can't check signature from {}: unsupported {}",
+                                                  sig.issuer, sig.unsupported.as_deref().unwrap_or("")),
+                _ => println!("Can't check signature from {}", sig.issuer),
+            }
+        }
+        println!("{}: {}", release_file, if report.verified { "verified" } else { "NOT verified" });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const INDEX : &str = "Package: a\nVersion: 1.0\n";
+
+    /// A Release file listing main/binary-amd64/Packages with contents' size and hashes.
+    fn release_for(contents: &str) -> String {
+        format!("Origin: Debian\nSuite: unstable\nMD5Sum:\n {:x} {} main/binary-amd64/Packages\nSHA256:\n {:x} {} main/binary-amd64/Packages\n",
+                Md5::digest(contents.as_bytes()), contents.len(), Sha256::digest(contents.as_bytes()), contents.len())
+    }
+
+    /// Writes contents as <dir>/main/binary-amd64/Packages.
+    fn write_index(dir: &Path, contents: &str) -> String {
+        let index_dir = dir.join("main/binary-amd64");
+        fs::create_dir_all(&index_dir).unwrap();
+        let path = index_dir.join("Packages");
+        fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn parses_the_hash_tables_into_one_entry_per_file() {
+        let release = Release::parse(&release_for(INDEX)).ok().unwrap();
+        assert_eq!(release.fields["Suite"], "unstable");
+        assert_eq!(release.files.len(), 1);
+        let entry = &release.files["main/binary-amd64/Packages"];
+        assert_eq!(entry.size, INDEX.len() as u64);
+        assert_eq!(entry.md5sum.as_deref(), Some(&format!("{:x}", Md5::digest(INDEX.as_bytes()))[..]));
+        assert_eq!(entry.sha256.as_deref(), Some(&format!("{:x}", Sha256::digest(INDEX.as_bytes()))[..]));
+    }
+
+    #[test]
+    fn rejects_a_release_without_file_tables() {
+        let err = Release::parse("Origin: Debian\nSuite: unstable\n").err().unwrap();
+        assert_eq!(err.to_string(), "malformed Release file: no MD5Sum or SHA256 file table");
+    }
+
+    #[test]
+    fn verifies_a_matching_index_and_finds_it_by_flattened_name() {
+        let release = Release::parse(&release_for(INDEX)).ok().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let index = write_index(dir.path(), INDEX);
+        assert!(release.verify_file(&index).is_ok());
+        let flattened = dir.path().join("deb.debian.org_debian_dists_sid_main_binary-amd64_Packages");
+        fs::write(&flattened, INDEX).unwrap();
+        assert!(release.verify_file(flattened.to_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn catches_truncated_and_stale_indices() {
+        let release = Release::parse(&release_for(INDEX)).ok().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let index = write_index(dir.path(), &INDEX[..10]);
+        assert!(matches!(release.verify_file(&index), Err(ReleaseError::Truncated { size: 10, .. })));
+        write_index(dir.path(), &format!("{}Depends: b\n", INDEX));
+        assert!(matches!(release.verify_file(&index), Err(ReleaseError::SizeMismatch { .. })));
+        // same size, different contents
+        write_index(dir.path(), &INDEX.replace("1.0", "2.0"));
+        assert!(matches!(release.verify_file(&index), Err(ReleaseError::HashMismatch { algorithm: "SHA256", .. })));
+    }
+
+    #[test]
+    fn unloading_the_release_lets_unlisted_files_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let release_file = dir.path().join("Release");
+        fs::write(&release_file, release_for(INDEX)).unwrap();
+        let other = dir.path().join("Packages");
+        fs::write(&other, INDEX).unwrap();
+        let other = other.to_str().unwrap();
+
+        let mut p = Packages::new();
+        assert!(p.read_release(release_file.to_str().unwrap()).is_ok());
+        assert!(matches!(p.verify_index(other), Err(ReleaseError::NotListed(_))));
+        p.unload_release();
+        assert!(p.verify_index(other).is_ok());
+    }
+}
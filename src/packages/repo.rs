@@ -0,0 +1,209 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use flate2::read::MultiGzDecoder;
+use xz2::read::XzDecoder;
+
+use crate::Packages;
+use crate::packages::release::Release;
+
+/// Where an available package was loaded from by load-repo.
+#[derive(Clone)]
+pub struct PackageOrigin {
+    /// the mirror root passed to load-repo; matched by "Pin: origin" preferences
+    pub site : String,
+    pub origin : String,
+    pub label : String,
+    pub suite : String,
+    pub codename : String,
+    pub component : String,
+    pub architecture : String,
+    /// NotAutomatic: yes in the Release file (e.g. experimental, backports)
+    pub not_automatic : bool,
+    pub but_automatic_upgrades : bool,
+}
+
+// in order of preference; the uncompressed index is cheapest to read
+const INDEX_NAMES : [&str; 3] = ["Packages", "Packages.xz", "Packages.gz"];
+
+fn open_index(path: &Path) -> io::Result<BufReader<Box<dyn Read>>> {
+    let file = File::open(path)?;
+    let reader : Box<dyn Read> = match path.extension().and_then(|e| e.to_str()) {
+        Some("gz") => Box::new(MultiGzDecoder::new(file)),
+        Some("xz") => Box::new(XzDecoder::new(file)),
+        _ => Box::new(file),
+    };
+    Ok(BufReader::new(reader))
+}
+
+impl Packages {
+    /// Loads every binary Packages index of a suite from a Debian mirror tree on disk:
+    /// root/dists/suite/{InRelease,Release} and root/dists/suite/<component>/binary-<arch>/Packages[.xz|.gz].
+    /// Components default to those the Release file lists; each index is verified against the Release file.
+    pub fn load_repo(&mut self, root: &str, suite: &str, components: &[&str]) {
+        let dists : PathBuf = [root, "dists", suite].iter().collect();
+        let release_path = ["InRelease", "Release"].iter().map(|f| dists.join(f)).find(|p| p.exists());
+        let release = match release_path {
+            None => { self.fail(&format!("Error: no InRelease or Release file in {}", dists.display())); return }
+            Some(p) => match Release::from_file(&p.to_string_lossy()) {
+                Err(e) => { self.fail(&format!("Error: {}", e)); return }
+                Ok(r) => r
+            }
+        };
+
+        let field = |key: &str| release.fields.get(key).cloned().unwrap_or_default();
+        let origin = PackageOrigin {
+            site: root.to_string(),
+            origin: field("Origin"),
+            label: field("Label"),
+            suite: if release.fields.contains_key("Suite") { field("Suite") } else { suite.to_string() },
+            codename: field("Codename"),
+            component: String::new(),
+            architecture: String::new(),
+            not_automatic: field("NotAutomatic") == "yes",
+            but_automatic_upgrades: field("ButAutomaticUpgrades") == "yes",
+        };
+        let components : Vec<String> = if components.is_empty() {
+            field("Components").split_whitespace().map(String::from).collect()
+        } else {
+            components.iter().map(|c| c.to_string()).collect()
+        };
+        let mut architectures : Vec<String> = field("Architectures").split_whitespace().map(String::from).collect();
+        if !architectures.iter().any(|a| a == "all") {
+            architectures.push(String::from("all"));
+        }
+
+        let mut indexes = vec![];
+        for component in &components {
+            for arch in &architectures {
+                let dir = dists.join(component).join(format!("binary-{}", arch));
+                if let Some(index) = INDEX_NAMES.iter().map(|f| dir.join(f)).find(|p| p.exists()) {
+                    indexes.push((component, arch, index));
+                }
+            }
+        }
+
+        if self.strict {
+            // all or nothing: check every index before storing any of them
+            for (_, _, index) in &indexes {
+                let index_name = index.to_string_lossy().to_string();
+                let checked = release.verify_file(&index_name).map_err(|e| e.to_string())
+                    .and_then(|_| open_index(index).map_err(|e| format!("can't read {}: {}", index_name, e)))
+                    .and_then(|reader| self.check_packages_reader(&index_name, reader));
+                if let Err(e) = checked {
+                    self.fail(&format!("Error: {}", e));
+                    return;
+                }
+            }
+        }
+
+        for (component, arch, index) in indexes {
+            let index_name = index.to_string_lossy().to_string();
+            if let Err(e) = release.verify_file(&index_name) {
+                self.fail(&format!("Error: {}", e));
+                continue;
+            }
+            let reader = match open_index(&index) {
+                Err(e) => { self.fail(&format!("Error: can't read {}: {}", index_name, e)); continue }
+                Ok(l) => l
+            };
+            let since = self.begin_load(&index_name);
+            let stanzas = self.parse_packages_reader(&index_name, reader);
+            if let Err(e) = self.check_strict(since) {
+                self.fail(&format!("Error: {}", e));
+                continue;
+            }
+            let index_origin = PackageOrigin { component: component.clone(), architecture: arch.clone(), ..origin.clone() };
+            self.record_candidates(&stanzas, Some(index_origin));
+            self.progress(&format!("{}/binary-{}: {} packages", component, arch, stanzas.len()));
+            self.print_load_summary(&index_name);
+        }
+        self.select_candidates();
+        self.progress(&format!("Packages available: {}", self.available_debvers.keys().len()));
+    }
+
+    /// Returns where package_name's candidate version was loaded from, if it came from load-repo.
+    pub fn get_package_origin(&self, package_name: &str) -> Option<&PackageOrigin> {
+        let package_num = self.package_ids.get(package_name)?;
+        self.package_origins.get(&package_num)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Packages;
+
+    // made by tests/repo/generate.sh
+    const ROOT : &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/repo");
+
+    fn version(p: &Packages, package_name: &str) -> Option<String> {
+        p.get_available_debver(package_name).map(|v| v.to_string())
+    }
+
+    #[test]
+    fn walks_every_component_and_architecture() {
+        let mut p = Packages::new();
+        p.load_repo(ROOT, "sid", &[]);
+        assert!(!p.take_failure());
+        let names = ["app", "doc", "tool"];
+        assert_eq!(names.map(|n| version(&p, n)), ["1.0", "1.0", "2.0"].map(|v| Some(String::from(v))));
+    }
+
+    #[test]
+    fn records_the_component_and_architecture_of_each_index() {
+        let mut p = Packages::new();
+        p.load_repo(ROOT, "sid", &[]);
+        let origin = |name| {
+            let o = p.get_package_origin(name).unwrap();
+            (o.suite.as_str(), o.codename.as_str(), o.component.as_str(), o.architecture.as_str())
+        };
+        assert_eq!(origin("app"), ("unstable", "sid", "main", "amd64"));
+        assert_eq!(origin("doc"), ("unstable", "sid", "main", "all"));
+        assert_eq!(origin("tool"), ("unstable", "sid", "contrib", "amd64"));
+
+        let repository = p.info_report("tool").unwrap().repository.unwrap();
+        assert_eq!((repository.origin.as_str(), repository.component.as_str()), ("Debian", "contrib"));
+    }
+
+    #[test]
+    fn prefers_uncompressed_then_xz_then_gz() {
+        let mut p = Packages::new();
+        p.load_repo(ROOT, "sid", &["main"]);
+        // binary-amd64 has Packages and Packages.gz (0.9); binary-all has Packages.xz and Packages.gz (0.5)
+        assert_eq!(version(&p, "app").as_deref(), Some("1.0"));
+        assert_eq!(version(&p, "doc").as_deref(), Some("1.0"));
+        assert!(!p.package_exists("tool"));
+    }
+
+    #[test]
+    fn reads_inrelease_before_release() {
+        let mut p = Packages::new();
+        p.load_repo(ROOT, "experimental", &[]);
+        let o = p.get_package_origin("app").unwrap();
+        assert_eq!((o.suite.as_str(), o.not_automatic), ("experimental", true));
+
+        // NotAutomatic: experimental's newer app loses to sid's
+        p.load_repo(ROOT, "sid", &[]);
+        assert_eq!(version(&p, "app").as_deref(), Some("1.0"));
+        assert_eq!(p.get_package_origin("app").unwrap().suite, "unstable");
+    }
+
+    #[test]
+    fn rejects_an_index_whose_hash_does_not_match() {
+        let mut p = Packages::new();
+        p.load_repo(ROOT, "stale", &[]);
+        assert!(p.take_failure());
+        assert!(version(&p, "app").is_none());
+        assert_eq!(version(&p, "lib").as_deref(), Some("1.0"));
+    }
+
+    #[test]
+    fn strict_mode_loads_nothing_from_a_suite_with_a_bad_index() {
+        let mut p = Packages::new();
+        p.set_strict(true);
+        p.load_repo(ROOT, "stale", &[]);
+        assert!(p.take_failure());
+        assert!(version(&p, "lib").is_none());
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::Packages;
+use crate::packages::output::{SearchReport, SearchResultReport};
+use crate::packages::interner::PackageId;
+
+/// What search looks for: packages whose name (or description, unless names_only) matches pattern,
+/// and whose fields have the given values. Matching is case-insensitive throughout.
+pub struct SearchQuery {
+    pub pattern : Option<String>,
+    pub names_only : bool,
+    /// (field, value) pairs, e.g. ("Section", "games"); all must match
+    pub fields : Vec<(String, String)>,
+}
+
+pub(crate) struct SearchEntry {
+    pub(crate) package_num : PackageId,
+    pub(crate) name : String,
+    pub(crate) description : String,
+}
+
+/// Built whenever the loaded packages change, so that a search doesn't rescan every stanza. Snapshots
+/// keep it too, since building it takes longer than the rest of loading one.
+#[derive(Default)]
+pub struct SearchIndex {
+    /// packages with an available or installed version, sorted by name
+    pub(crate) entries : Vec<SearchEntry>,
+    /// every three-byte substring of the lowercased names and descriptions, ascending; the entries
+    /// containing trigrams[i] are trigram_entries[trigram_starts[i]..trigram_starts[i + 1]], ascending
+    pub(crate) trigrams : Vec<u32>,
+    pub(crate) trigram_starts : Vec<usize>,
+    pub(crate) trigram_entries : Vec<u32>,
+    /// lowercased (field, value) -> entries having it, ascending
+    pub(crate) fields : HashMap<(String, String), Vec<u32>>,
+}
+
+// how well a package matches, best first
+const RANK_EXACT : u8 = 0;
+const RANK_NAME_PREFIX : u8 = 1;
+const RANK_NAME : u8 = 2;
+const RANK_DESCRIPTION : u8 = 3;
+
+fn trigrams_of(text: &str) -> impl Iterator<Item=u32> + '_ {
+    text.as_bytes().windows(3).map(|w| (w[0] as u32) << 16 | (w[1] as u32) << 8 | w[2] as u32)
+}
+
+/// Entries in both ascending lists.
+fn intersect(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] < b[j] { i += 1 } else if a[i] > b[j] { j += 1 } else { out.push(a[i]); i += 1; j += 1 }
+    }
+    out
+}
+
+impl SearchIndex {
+    fn trigram_postings(&self, trigram: u32) -> Option<&[u32]> {
+        let i = self.trigrams.binary_search(&trigram).ok()?;
+        Some(&self.trigram_entries[self.trigram_starts[i]..self.trigram_starts[i + 1]])
+    }
+
+    /// Entries that may match the query: those having all the fields and, when the pattern is a plain
+    /// string, all of its trigrams. Returns None when nothing narrows the search down.
+    fn candidates(&self, query: &SearchQuery) -> Option<Vec<u32>> {
+        let mut lists : Vec<&[u32]> = vec![];
+        for (key, value) in &query.fields {
+            match self.fields.get(&(key.to_lowercase(), value.to_lowercase())) {
+                None => return Some(vec![]),
+                Some(entries) => lists.push(entries),
+            }
+        }
+        let literal = query.pattern.as_ref().filter(|p| !p.contains(|c| "\\.+*?()|[]{}^$".contains(c))).map(|p| p.to_lowercase());
+        let trigrams : Vec<u32> = literal.as_deref().map(|l| trigrams_of(l).collect()).unwrap_or_default();
+        for t in trigrams {
+            match self.trigram_postings(t) {
+                None => return Some(vec![]),
+                Some(entries) => lists.push(entries),
+            }
+        }
+        lists.sort_by_key(|l| l.len());
+        let (first, rest) = lists.split_first()?;
+        Some(rest.iter().fold(first.to_vec(), |acc, l| intersect(&acc, l)))
+    }
+
+    fn rank(entry: &SearchEntry, regex: Option<&Regex>, names_only: bool) -> Option<u8> {
+        let regex = match regex {
+            None => return Some(RANK_EXACT),
+            Some(r) => r,
+        };
+        match regex.find(&entry.name) {
+            Some(m) if m.start() == 0 && m.end() == entry.name.len() => Some(RANK_EXACT),
+            Some(m) if m.start() == 0 => Some(RANK_NAME_PREFIX),
+            Some(_) => Some(RANK_NAME),
+            None if !names_only && regex.is_match(&entry.description) => Some(RANK_DESCRIPTION),
+            None => None,
+        }
+    }
+}
+
+impl Packages {
+    /// Rebuilds the search index from the loaded packages; the loaders call this once they're done.
+    pub fn build_search_index(&mut self) {
+        let mut package_nums : Vec<PackageId> = self.available_debvers.keys().chain(self.installed_debvers.keys()).cloned().collect();
+        package_nums.sort_by_key(|pn| self.get_package_name(*pn));
+        package_nums.dedup();
+
+        let mut index = SearchIndex::default();
+        // (trigram, entry) pairs, sorted and grouped by trigram afterwards
+        let mut trigrams : Vec<u64> = vec![];
+        for (i, pn) in package_nums.into_iter().enumerate() {
+            let entry = SearchEntry {
+                package_num: pn,
+                name: self.get_package_name(pn).to_string(),
+                description: self.get_field(pn, "Description").unwrap_or("").to_string(),
+            };
+            trigrams.extend(trigrams_of(&entry.name.to_lowercase()).chain(trigrams_of(&entry.description.to_lowercase()))
+                            .map(|t| (t as u64) << 32 | i as u64));
+            let fields = self.available_fields.get(&pn).into_iter().chain(self.installed_fields.get(&pn)).flatten();
+            // multi-line fields like Conffiles aren't worth looking up by value
+            for (key, value) in fields.filter(|(_, v)| !v.contains('\n')) {
+                let postings = index.fields.entry((key.to_lowercase(), value.trim().to_lowercase())).or_default();
+                // available and installed stanzas often agree
+                if postings.last() != Some(&(i as u32)) {
+                    postings.push(i as u32);
+                }
+            }
+            index.entries.push(entry);
+        }
+        trigrams.sort_unstable();
+        trigrams.dedup();
+        for pair in trigrams {
+            let (t, i) = ((pair >> 32) as u32, pair as u32);
+            if index.trigrams.last() != Some(&t) {
+                index.trigrams.push(t);
+                index.trigram_starts.push(index.trigram_entries.len());
+            }
+            index.trigram_entries.push(i);
+        }
+        index.trigram_starts.push(index.trigram_entries.len());
+        self.search_index = index;
+    }
+
+    /// Returns the packages matching query, best matches first, then by name.
+    pub fn search(&self, query: &SearchQuery) -> Result<Vec<PackageId>, String> {
+        let regex = match &query.pattern {
+            None => None,
+            Some(p) => Some(RegexBuilder::new(p).case_insensitive(true).build().map_err(|e| format!("bad regex {}: {}", p, e))?),
+        };
+        let index = &self.search_index;
+        let candidates : Box<dyn Iterator<Item=&SearchEntry>> = match index.candidates(query) {
+            None => Box::new(index.entries.iter()),
+            Some(c) => Box::new(c.into_iter().map(move |i| &index.entries[i as usize])),
+        };
+        let mut hits : Vec<(u8, &SearchEntry)> = candidates
+            .filter_map(|e| SearchIndex::rank(e, regex.as_ref(), query.names_only).map(|r| (r, e)))
+            .collect();
+        // entries are in name order already, and the sort is stable
+        hits.sort_by_key(|(rank, _)| *rank);
+        Ok(hits.into_iter().map(|(_, e)| e.package_num).collect())
+    }
+
+    /// What search and query print about each package they find.
+    fn package_summary(&self, package_num: PackageId) -> SearchResultReport {
+        let available = self.available_debvers.get(&package_num);
+        let installed = self.installed_debvers.get(&package_num);
+        SearchResultReport {
+            package: self.get_package_name(package_num).to_string(),
+            version: available.or(installed).map(|v| v.to_string()),
+            installed_version: installed.map(|v| v.to_string()),
+            upgradable: self.is_upgradable(package_num),
+            description: self.get_field(package_num, "Description").and_then(|d| d.lines().next()).map(String::from),
+        }
+    }
+
+    pub fn print_search(&self, query: &SearchQuery) {
+        match self.search(query) {
+            Err(e) => self.fail(&e),
+            Ok(results) => self.print_package_list(results),
+        }
+    }
+
+    /// Prints one line per package: name, version, installed state and short description.
+    pub fn print_package_list(&self, package_nums: Vec<PackageId>) {
+        let report = SearchReport { results: package_nums.into_iter().map(|pn| self.package_summary(pn)).collect() };
+        if self.emit_json(&report) { return; }
+        for r in &report.results {
+            let state = match &r.installed_version {
+                None => String::new(),
+                Some(_) if !r.upgradable => String::from(" [installed]"),
+                Some(i) => format!(" [installed {}, upgradable]", i),
+            };
+            let description = r.description.as_ref().map_or(String::new(), |d| format!(" - {}", d));
+            println!("{} {}{}{}", r.package, r.version.as_deref().unwrap_or(""), state, description);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SearchQuery;
+    use crate::Packages;
+
+    const AVAILABLE : &str = "\
+Package: 0ad\nVersion: 1.0\nSection: games\nDescription: real-time strategy game\n\n\
+Package: 0ad-data\nVersion: 1.0\nSection: games\nDescription: data files for 0ad\n\n\
+Package: launcher\nVersion: 1.0\nSection: Utils\nDescription: starts 0AD and other games\n\n\
+Package: pyzor\nVersion: 1.0\nSection: mail\nDescription: spam-catcher\n";
+
+    fn loaded() -> Packages {
+        let mut p = Packages::new();
+        let nums = p.parse_packages_reader("Packages", AVAILABLE.as_bytes());
+        p.record_candidates(&nums, None);
+        p.select_candidates();
+        p
+    }
+
+    fn search(p: &Packages, pattern: Option<&str>, names_only: bool, fields: &[(&str, &str)]) -> Result<Vec<String>, String> {
+        let query = SearchQuery {
+            pattern: pattern.map(String::from),
+            names_only,
+            fields: fields.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        };
+        Ok(p.search(&query)?.into_iter().map(|pn| p.get_package_name(pn).to_string()).collect())
+    }
+
+    #[test]
+    fn exact_names_then_prefixes_then_descriptions() {
+        let p = loaded();
+        assert_eq!(search(&p, Some("0ad"), false, &[]).unwrap(), ["0ad", "0ad-data", "launcher"]);
+        assert_eq!(search(&p, Some("0AD"), true, &[]).unwrap(), ["0ad", "0ad-data"]);
+        assert_eq!(search(&p, Some("ad-d"), false, &[]).unwrap(), ["0ad-data"]);
+        // a regex isn't narrowed down by trigrams
+        assert_eq!(search(&p, Some("^(pyzor|0ad)$"), false, &[]).unwrap(), ["0ad", "pyzor"]);
+        assert!(search(&p, Some("nothing"), false, &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn fields_must_all_match_ignoring_case() {
+        let p = loaded();
+        assert_eq!(search(&p, None, false, &[("section", "GAMES")]).unwrap(), ["0ad", "0ad-data"]);
+        assert_eq!(search(&p, None, false, &[("Section", "utils")]).unwrap(), ["launcher"]);
+        assert_eq!(search(&p, Some("data"), false, &[("Section", "games")]).unwrap(), ["0ad-data"]);
+        assert!(search(&p, None, false, &[("Section", "games"), ("Version", "2.0")]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_bad_regex_is_an_error() {
+        let p = loaded();
+        assert!(search(&p, Some("0ad("), false, &[]).err().unwrap().starts_with("bad regex 0ad("));
+    }
+}
@@ -0,0 +1,493 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::hash::Hash;
+
+use crate::debversion::{DebianVersionNum, VersionRelation};
+use crate::Packages;
+use crate::packages::{Candidate, Dependency, Fields, RelVersionedPackageNum, SourceRef};
+use crate::packages::preferences::parse_preferences;
+use crate::packages::release::{Release, ReleaseFileEntry};
+use crate::packages::repo::PackageOrigin;
+use crate::packages::interner::PackageId;
+use crate::packages::search::{SearchEntry, SearchIndex};
+
+// A snapshot is a header followed by the payload:
+//   magic (8 bytes), format version (u32), payload length (u64), CRC-32 of the payload (u32).
+// Integers are little-endian; strings are a u32 byte length and UTF-8 bytes; packages are indices
+// into the name table at the start of the payload, so numbering doesn't have to survive a reload.
+const SNAPSHOT_MAGIC : &[u8; 8] = b"RPKGSNAP";
+// bump whenever the payload layout changes
+const SNAPSHOT_VERSION : u32 = 3;
+const HEADER_LEN : usize = 8 + 4 + 8 + 4;
+
+struct Encoder<'a> {
+    buf : Vec<u8>,
+    packages : &'a Packages,
+}
+
+impl<'a> Encoder<'a> {
+    fn u8(&mut self, v: u8) { self.buf.push(v); }
+    fn u32(&mut self, v: u32) { self.buf.extend_from_slice(&v.to_le_bytes()); }
+    fn u64(&mut self, v: u64) { self.buf.extend_from_slice(&v.to_le_bytes()); }
+    fn bool(&mut self, v: bool) { self.u8(v as u8); }
+
+    fn len(&mut self, n: usize) { self.u32(n as u32); }
+
+    fn str(&mut self, s: &str) {
+        self.len(s.len());
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+
+    // the name table is in id order, so a package's index in it is its id
+    fn package(&mut self, package_num: PackageId) {
+        self.u32(package_num.index() as u32);
+    }
+
+    fn version(&mut self, v: &DebianVersionNum) { self.str(&v.to_string()); }
+
+    fn option<T>(&mut self, v: &Option<T>, f: impl FnOnce(&mut Self, &T)) {
+        match v {
+            None => self.bool(false),
+            Some(x) => { self.bool(true); f(self, x) }
+        }
+    }
+
+    fn vec<T>(&mut self, v: &[T], mut f: impl FnMut(&mut Self, &T)) {
+        self.len(v.len());
+        for x in v {
+            f(self, x);
+        }
+    }
+
+    /// Writes a map keyed by package id, in id order.
+    fn package_map<T>(&mut self, map: &HashMap<PackageId, T>, mut f: impl FnMut(&mut Self, &T)) {
+        let mut keys : Vec<&PackageId> = map.keys().collect();
+        keys.sort();
+        self.len(keys.len());
+        for k in keys {
+            self.package(*k);
+            f(self, &map[k]);
+        }
+    }
+
+    fn dependencies(&mut self, deps: &[Dependency]) {
+        self.vec(deps, |e, dep| e.vec(dep, |e, alt| {
+            e.package(alt.package_num);
+            e.option(&alt.rel_version, |e, (rel, ver)| { e.str(&rel.to_string()); e.str(ver) });
+        }));
+    }
+
+    fn fields(&mut self, fields: &Fields) {
+        self.vec(fields, |e, (k, v)| { e.str(k); e.str(v) });
+    }
+
+    fn source(&mut self, source: &SourceRef) {
+        self.str(&source.name);
+        self.option(&source.version, |e, v| e.version(v));
+    }
+
+    fn origin(&mut self, o: &PackageOrigin) {
+        for s in [&o.site, &o.origin, &o.label, &o.suite, &o.codename, &o.component, &o.architecture].iter() {
+            self.str(s);
+        }
+        self.bool(o.not_automatic);
+        self.bool(o.but_automatic_upgrades);
+    }
+
+    fn provides(&mut self, provides: &[(PackageId, Option<DebianVersionNum>)]) {
+        self.vec(provides, |e, (pn, v)| {
+            e.package(*pn);
+            e.option(v, |e, v| e.version(v));
+        });
+    }
+
+    fn candidate(&mut self, c: &Candidate) {
+        self.version(&c.version);
+        self.option(&c.md5sum, |e, m| e.str(m));
+        self.dependencies(&c.dependencies);
+        self.dependencies(&c.pre_dependencies);
+        self.provides(&c.provides);
+        self.option(&c.source, |e, s| e.source(s));
+        self.option(&c.origin, |e, o| e.origin(o));
+        self.fields(&c.fields);
+    }
+
+    fn release(&mut self, r: &Release) {
+        let mut fields : Vec<(&String, &String)> = r.fields.iter().collect();
+        fields.sort();
+        self.vec(&fields, |e, (k, v)| { e.str(k); e.str(v) });
+        let mut files : Vec<(&String, &ReleaseFileEntry)> = r.files.iter().collect();
+        files.sort_by_key(|(path, _)| *path);
+        self.vec(&files, |e, (path, entry)| {
+            e.str(path);
+            e.u64(entry.size);
+            e.option(&entry.md5sum, |e, m| e.str(m));
+            e.option(&entry.sha256, |e, s| e.str(s));
+        });
+    }
+
+    fn search_index(&mut self, index: &SearchIndex) {
+        // names are in the name table already
+        self.vec(&index.entries, |e, entry| { e.package(entry.package_num); e.str(&entry.description) });
+        self.vec(&index.trigrams, |e, t| e.u32(*t));
+        self.vec(&index.trigram_starts, |e, start| e.len(*start));
+        self.vec(&index.trigram_entries, |e, i| e.u32(*i));
+        let mut fields : Vec<(&(String, String), &Vec<u32>)> = index.fields.iter().collect();
+        fields.sort();
+        self.vec(&fields, |e, ((key, value), entries)| {
+            e.str(key);
+            e.str(value);
+            e.vec(entries, |e, i| e.u32(*i));
+        });
+    }
+
+    fn payload(&mut self) {
+        let p = self.packages;
+        let names : Vec<&str> = p.package_ids.names().collect();
+        self.vec(&names, |e, name| e.str(name));
+
+        self.package_map(&p.available_debvers, |e, v| e.version(v));
+        self.package_map(&p.installed_debvers, |e, v| e.version(v));
+        self.package_map(&p.md5sums, |e, m| e.str(m));
+        self.package_map(&p.dependencies, |e, d| e.dependencies(d));
+        self.package_map(&p.pre_dependencies, |e, d| e.dependencies(d));
+        self.package_map(&p.installed_dependencies, |e, d| e.dependencies(d));
+        self.package_map(&p.installed_provides, |e, providers| e.provides(providers));
+        self.package_map(&p.provides, |e, provided| e.provides(provided));
+        self.package_map(&p.available_fields, |e, f| e.fields(f));
+        self.package_map(&p.installed_fields, |e, f| e.fields(f));
+        self.package_map(&p.installed_sources, |e, s| e.source(s));
+        self.package_map(&p.available_sources, |e, s| e.source(s));
+        self.package_map(&p.package_origins, |e, o| e.origin(o));
+        self.package_map(&p.available_candidates, |e, cs| e.vec(cs, |e, c| e.candidate(c)));
+        self.option(&p.release, |e, r| e.release(r));
+        self.vec(&p.preferences, |e, pin| e.str(&pin.to_stanza()));
+        self.search_index(&p.search_index);
+    }
+}
+
+struct Decoder<'a> {
+    buf : &'a [u8],
+    pos : usize,
+    // index in the name table -> package id in the Packages being filled
+    package_nums : Vec<PackageId>,
+}
+
+type DecodeResult<T> = Result<T, String>;
+
+impl<'a> Decoder<'a> {
+    fn bytes(&mut self, n: usize) -> DecodeResult<&'a [u8]> {
+        if self.buf.len() - self.pos < n {
+            return Err(String::from("snapshot is truncated"));
+        }
+        let b = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(b)
+    }
+
+    fn u8(&mut self) -> DecodeResult<u8> { Ok(self.bytes(1)?[0]) }
+    fn u32(&mut self) -> DecodeResult<u32> { Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap())) }
+    fn u64(&mut self) -> DecodeResult<u64> { Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap())) }
+    fn bool(&mut self) -> DecodeResult<bool> { Ok(self.u8()? != 0) }
+
+    fn len(&mut self) -> DecodeResult<usize> { Ok(self.u32()? as usize) }
+
+    fn str(&mut self) -> DecodeResult<String> {
+        let n = self.len()?;
+        String::from_utf8(self.bytes(n)?.to_vec()).map_err(|_| String::from("snapshot has a malformed string"))
+    }
+
+    fn package(&mut self) -> DecodeResult<PackageId> {
+        let i = self.u32()? as usize;
+        self.package_nums.get(i).cloned().ok_or_else(|| format!("snapshot refers to unknown package {}", i))
+    }
+
+    fn version(&mut self) -> DecodeResult<DebianVersionNum> {
+        Ok(self.str()?.parse::<DebianVersionNum>().unwrap())
+    }
+
+    fn option<T>(&mut self, f: impl FnOnce(&mut Self) -> DecodeResult<T>) -> DecodeResult<Option<T>> {
+        if self.bool()? { Ok(Some(f(self)?)) } else { Ok(None) }
+    }
+
+    fn vec<T>(&mut self, mut f: impl FnMut(&mut Self) -> DecodeResult<T>) -> DecodeResult<Vec<T>> {
+        let n = self.len()?;
+        // don't trust n for the allocation; a corrupt length fails on truncation instead
+        let mut v = Vec::with_capacity(n.min(1024));
+        for _ in 0..n {
+            v.push(f(self)?);
+        }
+        Ok(v)
+    }
+
+    fn package_map<T>(&mut self, mut f: impl FnMut(&mut Self) -> DecodeResult<T>) -> DecodeResult<HashMap<PackageId, T>> {
+        let n = self.len()?;
+        let mut map = HashMap::with_capacity(n.min(1 << 16));
+        for _ in 0..n {
+            let k = self.package()?;
+            map.insert(k, f(self)?);
+        }
+        Ok(map)
+    }
+
+    fn string_map<K: Eq + Hash, V>(&mut self, mut f: impl FnMut(&mut Self) -> DecodeResult<(K, V)>) -> DecodeResult<HashMap<K, V>> {
+        Ok(self.vec(|d| f(d))?.into_iter().collect())
+    }
+
+    fn dependencies(&mut self) -> DecodeResult<Vec<Dependency>> {
+        self.vec(|d| d.vec(|d| {
+            let package_num = d.package()?;
+            let rel_version = d.option(|d| {
+                let rel = d.str()?;
+                if !["<<", "<=", "=", ">=", ">>"].contains(&rel.as_str()) {
+                    return Err(format!("snapshot has a bad version relation {:?}", rel));
+                }
+                Ok((rel.parse::<VersionRelation>().unwrap(), d.str()?))
+            })?;
+            Ok(RelVersionedPackageNum { package_num, rel_version })
+        }))
+    }
+
+    fn fields(&mut self) -> DecodeResult<Fields> {
+        self.vec(|d| Ok((d.str()?, d.str()?)))
+    }
+
+    fn source(&mut self) -> DecodeResult<SourceRef> {
+        Ok(SourceRef { name: self.str()?, version: self.option(|d| d.version())? })
+    }
+
+    fn origin(&mut self) -> DecodeResult<PackageOrigin> {
+        Ok(PackageOrigin {
+            site: self.str()?,
+            origin: self.str()?,
+            label: self.str()?,
+            suite: self.str()?,
+            codename: self.str()?,
+            component: self.str()?,
+            architecture: self.str()?,
+            not_automatic: self.bool()?,
+            but_automatic_upgrades: self.bool()?,
+        })
+    }
+
+    fn provides(&mut self) -> DecodeResult<Vec<(PackageId, Option<DebianVersionNum>)>> {
+        self.vec(|d| Ok((d.package()?, d.option(|d| d.version())?)))
+    }
+
+    fn candidate(&mut self) -> DecodeResult<Candidate> {
+        Ok(Candidate {
+            version: self.version()?,
+            md5sum: self.option(|d| d.str())?,
+            dependencies: self.dependencies()?,
+            pre_dependencies: self.dependencies()?,
+            provides: self.provides()?,
+            source: self.option(|d| d.source())?,
+            origin: self.option(|d| d.origin())?,
+            fields: self.fields()?,
+        })
+    }
+
+    fn release(&mut self) -> DecodeResult<Release> {
+        let fields = self.string_map(|d| Ok((d.str()?, d.str()?)))?;
+        let files = self.string_map(|d| Ok((d.str()?, ReleaseFileEntry {
+            size: d.u64()?,
+            md5sum: d.option(|d| d.str())?,
+            sha256: d.option(|d| d.str())?,
+        })))?;
+        Ok(Release { fields, files })
+    }
+
+    fn search_index(&mut self, p: &Packages) -> DecodeResult<SearchIndex> {
+        let entries = self.vec(|d| {
+            let package_num = d.package()?;
+            Ok(SearchEntry { package_num, name: p.get_package_name(package_num).to_string(), description: d.str()? })
+        })?;
+        let trigrams = self.vec(|d| d.u32())?;
+        let trigram_starts = self.vec(|d| d.len())?;
+        let trigram_entries = self.vec(|d| d.u32())?;
+        let fields = self.string_map(|d| Ok(((d.str()?, d.str()?), d.vec(|d| d.u32())?)))?;
+        // searches index by these without checking
+        let in_range = |i: &u32| (*i as usize) < entries.len();
+        // an index that was never built has no starts at all
+        let starts_ok = match trigram_starts.last() {
+            None => trigrams.is_empty() && trigram_entries.is_empty(),
+            Some(last) => trigram_starts.len() == trigrams.len() + 1 && *last == trigram_entries.len()
+                && trigram_starts.windows(2).all(|w| w[0] <= w[1]),
+        };
+        if !starts_ok || !trigram_entries.iter().all(in_range) || !fields.values().flatten().all(in_range) {
+            return Err(String::from("snapshot has a malformed search index"));
+        }
+        Ok(SearchIndex { entries, trigrams, trigram_starts, trigram_entries, fields })
+    }
+
+    /// Fills p, which must be empty, from the payload.
+    fn payload(&mut self, p: &mut Packages) -> DecodeResult<()> {
+        let names = self.vec(|d| d.str())?;
+        self.package_nums = names.iter().map(|name| p.get_package_num_inserting(name)).collect();
+
+        p.available_debvers = self.package_map(|d| d.version())?;
+        p.installed_debvers = self.package_map(|d| d.version())?;
+        p.md5sums = self.package_map(|d| d.str())?;
+        p.dependencies.extend(self.package_map(|d| d.dependencies())?);
+        p.pre_dependencies = self.package_map(|d| d.dependencies())?;
+        p.installed_dependencies = self.package_map(|d| d.dependencies())?;
+        p.installed_provides = self.package_map(|d| d.provides())?;
+        p.provides = self.package_map(|d| d.provides())?;
+        p.available_fields = self.package_map(|d| d.fields())?;
+        p.installed_fields = self.package_map(|d| d.fields())?;
+        p.installed_sources = self.package_map(|d| d.source())?;
+        p.available_sources = self.package_map(|d| d.source())?;
+        p.package_origins = self.package_map(|d| d.origin())?;
+        p.available_candidates = self.package_map(|d| d.vec(|d| d.candidate()))?;
+        p.release = self.option(|d| d.release())?;
+        let stanzas = self.vec(|d| d.str())?;
+        p.preferences = parse_preferences(&stanzas.join("\n"))
+            .map_err(|e| format!("snapshot has bad preferences: {}", e))?;
+        p.index_available_provides();
+        p.index_sources();
+        p.search_index = self.search_index(p)?;
+        if self.pos != self.buf.len() {
+            return Err(String::from("snapshot has trailing data"));
+        }
+        Ok(())
+    }
+}
+
+/// Whether data starts like a snapshot; it may still be corrupt.
+pub fn is_snapshot(data: &[u8]) -> bool {
+    data.starts_with(SNAPSHOT_MAGIC)
+}
+
+impl Packages {
+    /// Encodes everything loaded (packages, versions, dependencies, hashes, fields, sources, origins,
+    /// candidates, the Release file, pins and the search index) as a snapshot.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut e = Encoder { buf: vec![], packages: self };
+        e.payload();
+        let payload = e.buf;
+        let mut out = Vec::with_capacity(HEADER_LEN + payload.len());
+        out.extend_from_slice(SNAPSHOT_MAGIC);
+        out.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        out.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        out.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+        out.extend_from_slice(&payload);
+        out
+    }
+
+    /// Replaces everything loaded with the contents of a snapshot. The output format, strict mode and server are kept.
+    /// On error, nothing changes.
+    pub fn restore_snapshot(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() < HEADER_LEN || !is_snapshot(data) {
+            return Err(String::from("not an rpkg snapshot"));
+        }
+        let mut header = Decoder { buf: &data[8..HEADER_LEN], pos: 0, package_nums: vec![] };
+        let (version, len, crc) = (header.u32()?, header.u64()?, header.u32()?);
+        if version != SNAPSHOT_VERSION {
+            return Err(format!("snapshot format version {} is not supported (expected {})", version, SNAPSHOT_VERSION));
+        }
+        let payload = &data[HEADER_LEN..];
+        if payload.len() as u64 != len {
+            return Err(format!("snapshot is {} bytes, header says {}", data.len(), HEADER_LEN as u64 + len));
+        }
+        if crc32fast::hash(payload) != crc {
+            return Err(String::from("snapshot checksum mismatch; the file is corrupt"));
+        }
+
+        let mut restored = Packages::new();
+        Decoder { buf: payload, pos: 0, package_nums: vec![] }.payload(&mut restored)?;
+        restored.format = self.format;
+        restored.strict = self.strict;
+        std::mem::swap(&mut restored.async_state, &mut self.async_state);
+        *self = restored;
+        Ok(())
+    }
+
+    /// Like load_snapshot, but returns errors instead of printing them, and prints nothing.
+    pub fn read_snapshot(&mut self, filename: &str) -> Result<(), String> {
+        let data = std::fs::read(filename).map_err(|e| format!("can't read {}: {}", filename, e))?;
+        self.restore_snapshot(&data).map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    pub fn save_snapshot(&self, filename: &str) {
+        let data = self.snapshot();
+        match std::fs::write(filename, &data) {
+            Err(e) => self.fail(&format!("Error: can't write {}: {}", filename, e)),
+            Ok(()) => self.progress(&format!("Snapshot saved: {} packages, {} bytes", self.package_ids.len(), data.len())),
+        }
+    }
+
+    pub fn load_snapshot(&mut self, filename: &str) {
+        if let Err(e) = self.read_snapshot(filename) {
+            self.fail(&format!("Error: {}", e));
+            return;
+        }
+        self.progress(&format!("Packages available: {}", self.available_debvers.keys().len()));
+        self.progress(&format!("Packages installed: {}", self.installed_debvers.keys().len()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Packages;
+    use crate::packages::search::SearchQuery;
+
+    const INSTALLED : &str = concat!(env!("CARGO_MANIFEST_DIR"), "/data/installed-packages");
+
+    fn loaded() -> Packages {
+        let mut packages = Packages::new();
+        packages.read_installed(INSTALLED).unwrap();
+        packages
+    }
+
+    fn search(packages: &Packages, pattern: &str, field: Option<(&str, &str)>) -> Vec<String> {
+        let query = SearchQuery {
+            pattern: Some(pattern.to_string()),
+            names_only: false,
+            fields: field.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        };
+        packages.search(&query).unwrap().into_iter().map(|pn| packages.get_package_name(pn).to_string()).collect()
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let packages = loaded();
+        let data = packages.snapshot();
+        let mut restored = Packages::new();
+        restored.restore_snapshot(&data).unwrap();
+        assert_eq!(restored.snapshot(), data);
+        assert_eq!(restored.installed_debvers.len(), packages.installed_debvers.len());
+        for (pattern, field) in [("libc6", None), ("python", Some(("Section", "python")))] {
+            let found = search(&packages, pattern, field);
+            assert!(!found.is_empty());
+            assert_eq!(search(&restored, pattern, field), found);
+        }
+    }
+
+    #[test]
+    fn empty_round_trip() {
+        let data = Packages::new().snapshot();
+        let mut restored = loaded();
+        restored.restore_snapshot(&data).unwrap();
+        assert!(restored.installed_debvers.is_empty());
+        assert!(search(&restored, "libc6", None).is_empty());
+    }
+
+    #[test]
+    fn bad_checksum_is_rejected() {
+        let mut data = loaded().snapshot();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        let mut packages = loaded();
+        let before = packages.snapshot();
+        assert_eq!(packages.restore_snapshot(&data).unwrap_err(), "snapshot checksum mismatch; the file is corrupt");
+        assert_eq!(packages.snapshot(), before);
+    }
+
+    #[test]
+    fn other_version_is_rejected() {
+        let mut data = Packages::new().snapshot();
+        data[8..12].copy_from_slice(&1u32.to_le_bytes());
+        let err = Packages::new().restore_snapshot(&data).unwrap_err();
+        assert_eq!(err, format!("snapshot format version 1 is not supported (expected {})", super::SNAPSHOT_VERSION));
+    }
+}
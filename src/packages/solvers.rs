@@ -1,154 +1,150 @@
 use crate::Packages;
-use crate::packages::Dependency;
-use std::collections::VecDeque;
-use rpkg::debversion;
+use crate::packages::{Dependency, RelVersionedPackageNum};
+use crate::packages::interner::PackageId;
+use std::collections::{HashMap, VecDeque};
+use crate::debversion::{DebianVersionNum, VersionRange, VersionRelation};
 
 impl Packages {
     /// Computes a solution for the transitive dependencies of package_name; when there is a choice A | B | C, 
-    /// chooses the first option A. Returns a Vec<i32> of package numbers.
+    /// chooses the first option A. Returns a Vec<PackageId> of package ids.
     ///
     /// Note: does not consider which packages are installed.
-    pub fn transitive_dep_solution(&self, package_name: &str) -> Vec<i32> {
+    pub(crate) fn transitive_dep_solution(&self, package_name: &str) -> Vec<PackageId> {
         if !self.package_exists(package_name) {
             return vec![];
         }
 
-        let deps : &Vec<Dependency> = &*self.dependencies.get(self.get_package_num(package_name)).unwrap();
-        let mut dependency_set:Vec<i32> = vec![];
+        let deps : &Vec<Dependency> = self.dependencies.get(&self.get_package_num(package_name)).unwrap();
+        let mut dependency_set:Vec<PackageId> = vec![];
 
         // implement worklist
 
         for dep in deps {
-            dependency_set.push(dep.get(0).unwrap().package_num)
+            dependency_set.push(dep.first().unwrap().package_num)
         }
 
         let mut i = 0;
         while let Some(pkg_num)= dependency_set.get(i) {
             if let Some(deps) = self.dependencies.get(pkg_num){
                 for dep in deps {
-                    let dep_num = dep.get(0).unwrap().package_num;
+                    let dep_num = dep.first().unwrap().package_num;
                     if !dependency_set.contains(&dep_num) {
                         dependency_set.push(dep_num);
                         // println!("{:?}", dependency_set);
                     }
                 }
             };
-            i = i + 1;
+            i += 1;
         }
-        return dependency_set;
+        dependency_set
     }
 
-    /// Computes a set of packages that need to be installed to satisfy package_name's deps given
-    /// the current installed packages.
+    /// Pre-Depends and Depends of package_num; both must be satisfied to install it.
+    pub fn install_dependencies(&self, package_num: PackageId) -> impl Iterator<Item=&Dependency> {
+        self.pre_dependencies.get(&package_num).into_iter().flatten()
+            .chain(self.dependencies.get(&package_num).into_iter().flatten())
+    }
+
+    /// Computes a set of packages that need to be installed to satisfy package_name's deps
+    /// (Pre-Depends included) given the current installed packages.
     /// When a dependency A | B | C is unsatisfied, there are two possible cases:
     ///   (1) there are no versions of A, B, or C installed; pick the alternative with the
     /// highest version number (yes, compare apples and oranges).
     ///   (2) at least one of A, B, or C is installed (say A, B), but with the wrong version;
     /// of the installed packages (A, B), pick the one with the highest version number.
-    pub fn compute_how_to_install(&self, package_name: &str) -> Vec<i32> {
+    /// Only alternatives whose available version satisfies every constraint put on that package so far are
+    /// considered; if a dependency has none, returns an error naming it and saying why each alternative failed.
+    pub(crate) fn compute_how_to_install(&self, package_name: &str) -> Result<Vec<PackageId>, String> {
         if !self.package_exists(package_name) {
-            return vec![];
+            return Ok(vec![]);
         }
-        let mut dependencies_to_add : Vec<i32> = vec![];
+        let mut dependencies_to_add : Vec<PackageId> = vec![];
+        // the constraints on each package chosen so far
+        let mut constraints : HashMap<PackageId, Vec<(VersionRelation, DebianVersionNum)>> = HashMap::new();
+        let constraints_with = |constraints: &HashMap<PackageId, Vec<(VersionRelation, DebianVersionNum)>>, alt: &RelVersionedPackageNum| {
+            let mut cs = constraints.get(&alt.package_num).cloned().unwrap_or_default();
+            if let Some((op, ver)) = &alt.rel_version {
+                cs.push((op.clone(), ver.parse::<DebianVersionNum>().unwrap()));
+            }
+            cs
+        };
 
         // implement more sophisticated worklist
 
-        let deps : &Vec<Dependency> = &*self.dependencies.get(self.get_package_num(package_name)).unwrap();
         let mut dependency_queue = VecDeque::new();
-        for dep in deps {
+        for dep in self.install_dependencies(self.get_package_num(package_name)) {
             dependency_queue.push_back(dep);
         }
 
         while let Some(pkgs) = dependency_queue.pop_front() {
             // dep satisfied
             if self.dep_is_satisfied(pkgs).is_some() {
-                // let mut dep_to_queue: Dependency = vec![];
-                // for i in 0..pkgs.len() {
-                //     let pkg = pkgs.get(i).unwrap();
-                //     if pkg.package_num == *self.get_package_num(self.dep_is_satisfied(pkgs).unwrap()) {
-                //         dep_to_queue.push(*pkg);
-                //     }
-                // }
-                // dependency_queue.push_back(&dep_to_queue);
                 continue;
             }
-            // dep satisfied, wrong ver
-            let wrong_ver_deps = self.dep_satisfied_by_wrong_version(pkgs);
-            if wrong_ver_deps.len()!=0 {
-                let first = wrong_ver_deps.first().unwrap();
-                let mut dep_to_add = self.get_package_num(first);
-                let mut max = self.get_available_debver(first).unwrap();
-                for wrong_ver_dep in wrong_ver_deps {
-                    if let ver_of_wrong_ver_dep = self.get_available_debver(wrong_ver_dep).unwrap()  {
-                        if debversion::cmp_debversion_with_op(&debversion::VersionRelation::StrictlyGreater, ver_of_wrong_ver_dep, max) {
-                            max = ver_of_wrong_ver_dep;
-                            dep_to_add = self.get_package_num(wrong_ver_dep);
-                        }
-                    }
-                }
-
-                for dep in self.dependencies.get(dep_to_add).unwrap() {
-                    dependency_queue.push_back(dep);
-                }
-
-                if !dependencies_to_add.contains(dep_to_add) {
-                    dependencies_to_add.push(*dep_to_add);
-                }
-                continue;
+            // dep satisfied, wrong ver: choose among the installed alternatives if any can be upgraded,
+            // otherwise among all of them
+            let wrong_ver_deps : Vec<&str> = self.dep_satisfied_by_wrong_version(pkgs);
+            let available : Vec<&RelVersionedPackageNum> = pkgs.iter()
+                .filter(|alt| self.available_debvers.contains_key(&alt.package_num))
+                .collect();
+            let wrong_ver_available : Vec<&RelVersionedPackageNum> = available.iter().cloned()
+                .filter(|alt| wrong_ver_deps.contains(&self.get_package_name(alt.package_num)))
+                .collect();
+            let candidates = if !wrong_ver_available.is_empty() { wrong_ver_available } else { available };
+            if candidates.is_empty() {
+                return Err(format!("unsatisfiable dependency {}", self.dep2str(pkgs)));
+            }
+            let allowed : Vec<&RelVersionedPackageNum> = candidates.iter().cloned()
+                .filter(|alt| constraints_range(&constraints_with(&constraints, alt)).contains(&self.available_debvers[&alt.package_num]))
+                .collect();
+            if allowed.is_empty() {
+                let reasons : Vec<String> = candidates.iter()
+                    .map(|alt| self.describe_unsatisfied(alt.package_num, &constraints_with(&constraints, alt)))
+                    .collect();
+                return Err(format!("unsatisfiable dependency {}: {}", self.dep2str(pkgs), reasons.join("; ")));
             }
-            // none installed
-            else {
-                // let mut dep_to_add = pkgs.front().unwrap();
-                // let mut highest_ver = self.get_available_debver(self.get_package_name(dep_to_add.package_num)).unwrap();
-                // for i in 0..pkgs.len() {
-                //     let option = pkgs.get(i).unwrap();
-                //     match self.get_available_debver(self.get_package_name(option.package_num)) {
-                //         Some(x) => {
-                //             if debversion::cmp_debversion_with_op(&debversion::VersionRelation::StrictlyGreater, x, highest_ver) {
-                //                 dep_to_add = option;
-                //                 highest_ver = x;
-                //             }
-                //         }
-                //         None => ()
-                //     }
-                // }
-                // for i in 0..pkgs.len() {
-                //     let pkg = pkgs.get(i).unwrap();
-                //     if pkg.package_num == dep_to_add.package_num {
-                //         dep_to_queue.push(*pkg);
-                //     }
-                // }
-                // dependency_queue.push_back(&dep_to_queue);
-                // dependencies_to_add.push(dep_to_add.package_num);
 
-                // continue;
-                let mut not_inst_deps = Vec::new();
-                for dep in pkgs {
-                    not_inst_deps.push(dep.package_num);
-                }
-                let first = not_inst_deps.first().unwrap();
-                let mut dep_to_add = first;
-                let mut max = self.get_available_debver(self.get_package_name(*first)).unwrap();
-                for not_inst_dep in not_inst_deps.iter() {
-                    if let ver_of_not_inst_dep = self.get_available_debver(self.get_package_name(*not_inst_dep)).unwrap()  {
-                        if debversion::cmp_debversion_with_op(&debversion::VersionRelation::StrictlyGreater, ver_of_not_inst_dep, max) {
-                            max = ver_of_not_inst_dep;
-                            dep_to_add = &not_inst_dep;
-                        }
-                    }
+            let mut chosen = allowed[0];
+            for alt in &allowed {
+                if self.available_debvers[&alt.package_num] > self.available_debvers[&chosen.package_num] {
+                    chosen = alt;
                 }
+            }
+            let dep_to_add = chosen.package_num;
+            let cs = constraints_with(&constraints, chosen);
+            constraints.insert(dep_to_add, cs);
 
-                for dep in self.dependencies.get(dep_to_add).unwrap() {
+            // only expand a package the first time it's chosen, or dependency cycles never terminate
+            if !dependencies_to_add.contains(&dep_to_add) {
+                for dep in self.install_dependencies(dep_to_add) {
                     dependency_queue.push_back(dep);
                 }
-
-                if !dependencies_to_add.contains(dep_to_add) {
-                    dependencies_to_add.push(*dep_to_add);
-                }
-                continue;
+                dependencies_to_add.push(dep_to_add);
             }
         }
 
-        return dependencies_to_add;
+        Ok(dependencies_to_add)
+    }
+
+    /// Says why package_num can't be installed under constraints: none of its available versions is
+    /// allowed by them, or only a version other than the candidate is.
+    fn describe_unsatisfied(&self, package_num: PackageId, constraints: &[(VersionRelation, DebianVersionNum)]) -> String {
+        let name = self.get_package_name(package_num);
+        let described = constraints.iter().map(|(op, v)| format!("{} {}", op, v)).collect::<Vec<_>>().join(", ");
+        let range = constraints_range(constraints);
+        let other_versions : Vec<&DebianVersionNum> = self.available_candidates.get(&package_num).into_iter().flatten()
+            .map(|c| &c.version)
+            .filter(|v| range.contains(v))
+            .collect();
+        match other_versions.first() {
+            Some(v) =>
+                format!("candidate {} {} doesn't satisfy {}, but {} would", name, self.available_debvers[&package_num], described, v),
+            _ => format!("no available version of {} satisfies {}", name, described),
+        }
     }
 }
+
+/// The versions allowed by every one of constraints.
+fn constraints_range(constraints: &[(VersionRelation, DebianVersionNum)]) -> VersionRange {
+    constraints.iter().fold(VersionRange::any(), |range, (op, v)| range.intersection(&VersionRange::from_relation(op, v)))
+}
@@ -0,0 +1,185 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::debversion::DebianVersionNum;
+use crate::Packages;
+use crate::packages::SourceRef;
+use crate::packages::interner::PackageId;
+
+impl Packages {
+    /// Resolves the source package of an installed or available binary. A stanza without a Source: field
+    /// is its own source, and a Source: field without a version means the source version is the binary version.
+    fn resolve_source<'a>(&'a self, package_num: PackageId, sources: &'a HashMap<PackageId, SourceRef>,
+                          debvers: &'a HashMap<PackageId, DebianVersionNum>) -> Option<(&'a str, &'a DebianVersionNum)> {
+        let binary_version = debvers.get(&package_num)?;
+        match sources.get(&package_num) {
+            None => Some((self.get_package_name(package_num), binary_version)),
+            Some(source) => match &source.version {
+                None => Some((&source.name, binary_version)),
+                Some(v) => Some((&source.name, v))
+            }
+        }
+    }
+
+    /// Returns the source package name and version of the installed version of package_name, or None if not installed.
+    pub fn get_installed_source(&self, package_name: &str) -> Option<(&str, &DebianVersionNum)> {
+        let package_num = self.package_ids.get(package_name)?;
+        self.resolve_source(package_num, &self.installed_sources, &self.installed_debvers)
+    }
+
+    /// Returns the source package name and version of the available version of package_name, or None if not available.
+    pub fn get_available_source(&self, package_name: &str) -> Option<(&str, &DebianVersionNum)> {
+        let package_num = self.package_ids.get(package_name)?;
+        self.resolve_source(package_num, &self.available_sources, &self.available_debvers)
+    }
+
+    /// Rebuilds source_binaries from the installed and available versions' sources.
+    pub(crate) fn index_sources(&mut self) {
+        let mut source_binaries : HashMap<String, Vec<PackageId>> = HashMap::new();
+        for package_num in self.installed_debvers.keys().chain(self.available_debvers.keys()) {
+            let installed = self.resolve_source(*package_num, &self.installed_sources, &self.installed_debvers);
+            let available = self.resolve_source(*package_num, &self.available_sources, &self.available_debvers);
+            for (source, _) in installed.into_iter().chain(available) {
+                source_binaries.entry(source.to_string()).or_default().push(*package_num);
+            }
+        }
+        for binaries in source_binaries.values_mut() {
+            binaries.sort_by_key(|pn| self.get_package_name(*pn));
+            binaries.dedup();
+        }
+        self.source_binaries = source_binaries;
+    }
+
+    /// Returns the package numbers of all installed or available binaries built from source_name, sorted by name.
+    pub fn binaries_of(&self, source_name: &str) -> Vec<PackageId> {
+        self.source_binaries.get(source_name).cloned().unwrap_or_default()
+    }
+
+    /// Finds source packages whose installed binaries were not all built from the same source version,
+    /// as happens after a partial upgrade. Returns (source name, binaries) pairs sorted by source name.
+    pub fn source_version_skew(&self) -> Vec<(String, Vec<PackageId>)> {
+        let mut by_source : BTreeMap<&str, Vec<PackageId>> = BTreeMap::new();
+        for package_num in self.installed_debvers.keys() {
+            let pkg = self.get_package_name(*package_num);
+            let (source, _) = self.get_installed_source(pkg).unwrap();
+            by_source.entry(source).or_default().push(*package_num);
+        }
+
+        let mut skewed = vec![];
+        for (source, mut binaries) in by_source {
+            let first_version = self.get_installed_source(self.get_package_name(binaries[0])).unwrap().1;
+            if binaries.iter().all(|pn| self.get_installed_source(self.get_package_name(*pn)).unwrap().1 == first_version) {
+                continue;
+            }
+            binaries.sort_by_key(|pn| self.get_package_name(*pn));
+            skewed.push((source.to_string(), binaries));
+        }
+        skewed
+    }
+
+    pub fn print_source_of(&self, package_name: &str) {
+        if !self.package_exists(package_name) {
+            self.fail(&format!("no such package {}", package_name));
+            return;
+        }
+        match self.get_installed_source(package_name) {
+            None => (),
+            Some((src, ver)) => println!("Installed-Source: {} ({})", src, ver)
+        }
+        match self.get_available_source(package_name) {
+            None => (),
+            Some((src, ver)) => println!("Source: {} ({})", src, ver)
+        }
+    }
+
+    pub fn print_binaries_of(&self, source_name: &str) {
+        let binaries = self.binaries_of(source_name);
+        if binaries.is_empty() {
+            self.fail(&format!("no binaries built from source {}", source_name));
+            return;
+        }
+        println!("Source {}:", source_name);
+        for pn in binaries {
+            let pkg = self.get_package_name(pn);
+            let mut line = format!("- {}", pkg);
+            if let Some(v) = self.get_available_debver(pkg) {
+                line.push_str(&format!(" available {}", v));
+            }
+            if let Some(v) = self.get_installed_debver(pkg) {
+                line.push_str(&format!(" installed {}", v));
+            }
+            println!("{}", line);
+        }
+    }
+
+    pub fn print_source_skew(&self) {
+        let skewed = self.source_version_skew();
+        for (source, binaries) in &skewed {
+            println!("Source {}:", source);
+            for pn in binaries {
+                let pkg = self.get_package_name(*pn);
+                let (_, source_ver) = self.get_installed_source(pkg).unwrap();
+                println!("- {} {} (source {})", pkg, self.get_installed_debver(pkg).unwrap(), source_ver);
+            }
+        }
+        println!("Sources with version skew: {}", skewed.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Packages;
+
+    // bar's binaries were built from two source versions; baz is its own source
+    const STATUS : &str = "\
+Package: libbar1\nStatus: install ok installed\nVersion: 1.0-1\nSource: bar\n\n\
+Package: bar-utils\nStatus: install ok installed\nVersion: 1.0-1+b1\nSource: bar (1.0-1)\n\n\
+Package: bar-doc\nStatus: install ok installed\nVersion: 0.9-1\nSource: bar\n\n\
+Package: baz\nStatus: install ok installed\nVersion: 2.0\n";
+
+    const AVAILABLE : &str = "\
+Package: libbar2\nVersion: 2.0-1\nSource: bar\n\n\
+Package: baz\nVersion: 2.1\n";
+
+    fn loaded() -> Packages {
+        let mut p = Packages::new();
+        p.parse_installed_reader("status", STATUS.as_bytes());
+        let nums = p.parse_packages_reader("Packages", AVAILABLE.as_bytes());
+        p.record_candidates(&nums, None);
+        p.select_candidates();
+        p
+    }
+
+    fn names(p: &Packages, pkgs: &[crate::packages::interner::PackageId]) -> Vec<String> {
+        pkgs.iter().map(|pn| p.get_package_name(*pn).to_string()).collect()
+    }
+
+    #[test]
+    fn a_source_field_without_a_version_takes_the_binary_version() {
+        let p = loaded();
+        let source = |s: Option<(&str, &crate::debversion::DebianVersionNum)>| s.map(|(n, v)| format!("{} {}", n, v));
+        assert_eq!(source(p.get_installed_source("libbar1")).as_deref(), Some("bar 1.0-1"));
+        assert_eq!(source(p.get_installed_source("bar-utils")).as_deref(), Some("bar 1.0-1"));
+        assert_eq!(source(p.get_available_source("libbar2")).as_deref(), Some("bar 2.0-1"));
+        // no Source: field at all
+        assert_eq!(source(p.get_available_source("baz")).as_deref(), Some("baz 2.1"));
+        assert!(p.get_available_source("libbar1").is_none());
+    }
+
+    #[test]
+    fn binaries_of_lists_installed_and_available_binaries() {
+        let p = loaded();
+        assert_eq!(names(&p, &p.binaries_of("bar")), ["bar-doc", "bar-utils", "libbar1", "libbar2"]);
+        assert_eq!(names(&p, &p.binaries_of("baz")), ["baz"]);
+        assert!(p.binaries_of("libbar1").is_empty());
+    }
+
+    #[test]
+    fn skew_is_binaries_of_one_source_from_different_versions() {
+        let p = loaded();
+        let skewed : Vec<(String, Vec<String>)> = p.source_version_skew().into_iter()
+            .map(|(source, binaries)| (source, names(&p, &binaries)))
+            .collect();
+        // libbar2 isn't installed, and bar-utils is a binNMU of the same source version as libbar1
+        assert_eq!(skewed, [(String::from("bar"), vec![String::from("bar-doc"), String::from("bar-utils"), String::from("libbar1")])]);
+    }
+}
@@ -0,0 +1,274 @@
+use std::borrow::Cow;
+use std::io::BufRead;
+
+use crate::debversion::{self, VersionRelation};
+use crate::packages::parsers::relation_syntax_warning;
+
+/// A field of a Stanza, borrowed from the text it was parsed from.
+pub struct Field<'a> {
+    pub name : &'a str,
+    // as written, continuation lines and their indentation included
+    pub value : &'a str,
+    // the line the field starts on, counting from 1
+    pub line : usize,
+}
+
+impl<'a> Field<'a> {
+    /// The value with its continuation lines trimmed and joined by newlines; only allocates if there are any.
+    pub fn joined(&self) -> Cow<'a, str> {
+        if !self.value.contains('\n') {
+            return Cow::Borrowed(self.value);
+        }
+        Cow::Owned(self.value.lines().map(str::trim).collect::<Vec<_>>().join("\n"))
+    }
+}
+
+/// A stanza of a control file, borrowed from the text it was parsed from.
+pub struct Stanza<'a> {
+    pub line : usize,
+    pub fields : Vec<Field<'a>>,
+    // lines that are neither a field nor a continuation, with their line numbers and what's wrong
+    pub problems : Vec<(usize, String)>,
+}
+
+impl<'a> Stanza<'a> {
+    pub fn get(&self, name: &str) -> Option<&Field<'a>> {
+        self.fields.iter().find(|f| f.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Parses text, the lines of one stanza, the first of which is line number first_line of its file.
+    fn parse(text: &'a [u8], first_line: usize) -> Stanza<'a> {
+        let mut stanza = Stanza { line: first_line, fields: Vec::with_capacity(24), problems: vec![] };
+        let text = match std::str::from_utf8(text) {
+            Err(e) => {
+                let line = first_line + text[..e.valid_up_to()].iter().filter(|b| **b == b'\n').count();
+                stanza.problems.push((line, String::from("line isn't valid UTF-8")));
+                return stanza;
+            }
+            Ok(t) => t
+        };
+        let base = text.as_ptr() as usize;
+        for (i, line) in text.lines().enumerate() {
+            let n = first_line + i;
+            let line = line.trim_end();
+            if line.starts_with(' ') || line.starts_with('\t') {
+                // the value runs on to the end of this line, which follows it in text
+                match stanza.fields.last_mut() {
+                    None => stanza.problems.push((n, String::from("continuation line outside a field"))),
+                    Some(f) => {
+                        let start = f.value.as_ptr() as usize - base;
+                        f.value = &text[start..line.as_ptr() as usize - base + line.len()];
+                    }
+                }
+                continue;
+            }
+            match line.split_once(':') {
+                Some((name, value)) if !name.is_empty() && !name.contains(char::is_whitespace) =>
+                    stanza.fields.push(Field { name, value: value.trim_start(), line: n }),
+                _ => stanza.problems.push((n, format!("not a field: {:?}", line))),
+            }
+        }
+        stanza
+    }
+}
+
+fn is_blank(line: &[u8]) -> bool {
+    line.iter().all(u8::is_ascii_whitespace)
+}
+
+/// The stanzas of a control file that is all in memory, borrowing from it.
+pub struct Stanzas<'a> {
+    data : &'a [u8],
+    pos : usize,
+    // the number of the line at pos
+    line : usize,
+}
+
+impl<'a> Stanzas<'a> {
+    pub fn new(data: &'a [u8]) -> Stanzas<'a> {
+        Stanzas { data, pos: 0, line: 1 }
+    }
+
+    // the line at pos, with its newline
+    fn next_line(&self) -> &'a [u8] {
+        let rest = &self.data[self.pos..];
+        match rest.iter().position(|b| *b == b'\n') {
+            None => rest,
+            Some(i) => &rest[..=i],
+        }
+    }
+}
+
+impl<'a> Iterator for Stanzas<'a> {
+    type Item = Stanza<'a>;
+
+    fn next(&mut self) -> Option<Stanza<'a>> {
+        while self.pos < self.data.len() && is_blank(self.next_line()) {
+            self.pos += self.next_line().len();
+            self.line += 1;
+        }
+        if self.pos == self.data.len() {
+            return None;
+        }
+        let (start, first_line) = (self.pos, self.line);
+        while self.pos < self.data.len() && !is_blank(self.next_line()) {
+            self.pos += self.next_line().len();
+            self.line += 1;
+        }
+        Some(Stanza::parse(&self.data[start..self.pos], first_line))
+    }
+}
+
+/// Reads the stanzas of a control file one at a time, each into the same buffer. A read error ends the
+/// stanzas, with a last one that has it as a problem.
+pub struct StanzaReader<R> {
+    reader : R,
+    buf : Vec<u8>,
+    // the number of the next line to read
+    line : usize,
+    done : bool,
+}
+
+impl<R: BufRead> StanzaReader<R> {
+    pub fn new(reader: R) -> StanzaReader<R> {
+        StanzaReader { reader, buf: Vec::with_capacity(1 << 16), line: 1, done: false }
+    }
+
+    /// The next stanza, borrowed from the reader until the one after is read.
+    pub fn next_stanza(&mut self) -> Option<Stanza<'_>> {
+        if self.done {
+            return None;
+        }
+        self.buf.clear();
+        let mut first_line = self.line;
+        loop {
+            let start = self.buf.len();
+            match self.reader.read_until(b'\n', &mut self.buf) {
+                Err(e) => {
+                    self.done = true;
+                    let mut stanza = Stanza::parse(&self.buf[..start], first_line);
+                    stanza.problems.push((self.line, format!("can't read: {}", e)));
+                    return Some(stanza);
+                }
+                Ok(0) => {
+                    self.done = true;
+                    break;
+                }
+                Ok(_) => ()
+            }
+            self.line += 1;
+            if is_blank(&self.buf[start..]) {
+                self.buf.truncate(start);
+                if start > 0 {
+                    break;
+                }
+                first_line = self.line;
+            }
+        }
+        if self.buf.is_empty() {
+            return None;
+        }
+        Some(Stanza::parse(&self.buf, first_line))
+    }
+}
+
+// a relation as written: the package named, and the version constraint if any
+pub type Relation<'a> = (&'a str, Option<(VersionRelation, &'a str)>);
+
+/// Parses a comma-separated list of (possibly versioned) alternatives, as in a Depends: field.
+/// Relations that parse only leniently, with a deprecated operator or unusual spacing, add a warning to warnings.
+/// If strict, they are errors instead, as are package names that policy doesn't allow and anything after a
+/// relation but architecture and build profile restrictions.
+pub fn parse_relations<'a>(value: &'a str, strict: bool, warnings: &mut Vec<String>) -> Result<Vec<Vec<Relation<'a>>>, String> {
+    if value.trim().is_empty() {
+        return Ok(vec![]);
+    }
+    let mut relations = Vec::with_capacity(value.len() / 16 + 1);
+    for relation in value.split(',') {
+        let mut alternatives = Vec::with_capacity(1);
+        for alt in relation.split('|') {
+            alternatives.push(parse_relation(alt.trim(), strict, warnings)?);
+        }
+        relations.push(alternatives);
+    }
+    Ok(relations)
+}
+
+/// Whether name is a package name as policy 5.6.1 allows: at least two characters, lowercase letters, digits,
+/// "+", "-" and ".", starting with a letter or digit.
+pub fn is_package_name(name: &str) -> bool {
+    let allowed = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || "+-.".contains(c);
+    name.len() >= 2 && name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit()) && name.chars().all(allowed)
+}
+
+/// Parses "name[:arch] [(op version)]"; architecture and build profile restrictions after it are ignored,
+/// and if strict, anything else there is an error.
+fn parse_relation<'a>(alt: &'a str, strict: bool, warnings: &mut Vec<String>) -> Result<Relation<'a>, String> {
+    if alt.is_empty() {
+        return Err(String::from("empty relation"));
+    }
+    let is_name_char = |c: char| c.is_alphanumeric() || "_.+-".contains(c);
+    let name_end = alt.find(|c| !is_name_char(c)).unwrap_or(alt.len());
+    if name_end == 0 {
+        return Err(format!("malformed relation {:?}", alt));
+    }
+    let name = &alt[..name_end];
+    if strict && !is_package_name(name) {
+        return Err(format!("invalid package name {:?}", name));
+    }
+    let mut qualified_end = name_end;
+    if alt[name_end..].starts_with(':') {
+        qualified_end = alt[name_end + 1..].find(|c: char| !is_name_char(c)).map_or(alt.len(), |i| name_end + 1 + i);
+        let arch = &alt[name_end + 1..qualified_end];
+        if strict && (arch.is_empty() || !arch.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')) {
+            return Err(format!("malformed relation {:?}", alt));
+        }
+    }
+    let rest = alt[qualified_end..].trim_start();
+    if !rest.starts_with('(') {
+        if strict {
+            check_restrictions(rest, alt)?;
+        }
+        return Ok((name, None));
+    }
+    let close = rest.find(')').ok_or_else(|| format!("unclosed parenthesis in {:?}", alt))?;
+    let inside = rest[1..close].trim();
+    let op_len = inside.find(|c| !"<=>".contains(c)).unwrap_or(inside.len());
+    let (op, ver) = (&inside[..op_len], inside[op_len..].trim_start());
+    if op.is_empty() {
+        return Err(format!("no relation operator in {:?}", alt));
+    }
+    let relation = op.parse::<VersionRelation>().map_err(|e| format!("{} in {:?}", e, alt))?;
+    if ver.is_empty() || ver.contains(char::is_whitespace) {
+        return Err(format!("malformed relation {:?}", alt));
+    }
+    debversion::check_version_syntax(ver).map_err(|e| format!("invalid version in {:?}: {}", alt, e))?;
+    let written = &alt[..alt.len() - rest.len() + close + 1];
+    if let Some(warning) = relation_syntax_warning(written, &alt[..qualified_end], op, ver) {
+        if strict {
+            return Err(warning);
+        }
+        warnings.push(warning);
+    }
+    if strict {
+        check_restrictions(rest[close + 1..].trim_start(), alt)?;
+    }
+    Ok((name, Some((relation, ver))))
+}
+
+/// Checks what follows a relation: an optional architecture restriction list "[...]", then any number of
+/// build profile restriction lists "<...>".
+fn check_restrictions(mut rest: &str, alt: &str) -> Result<(), String> {
+    let malformed = || format!("malformed relation {:?}", alt);
+    for (open, close, repeated) in [('[', ']', false), ('<', '>', true)] {
+        while let Some(list) = rest.strip_prefix(open) {
+            let end = list.find(close).ok_or_else(malformed)?;
+            if list[..end].contains(open) {
+                return Err(malformed());
+            }
+            rest = list[end + 1..].trim_start();
+            if !repeated { break; }
+        }
+    }
+    if rest.is_empty() { Ok(()) } else { Err(malformed()) }
+}
@@ -0,0 +1,21 @@
+#!/bin/sh
+# Regenerates the OpenPGP fixtures used by the tests in src/openpgp.rs. The keys have no passphrase
+# and are thrown away; only the public keys and signatures are kept.
+set -e
+cd "$(dirname "$0")"
+rm -f *.asc *.fpr *.clearsigned *.sig
+export GNUPGHOME="$(mktemp -d)"
+trap 'rm -rf "$GNUPGHOME"' EXIT
+gpg="gpg --batch --quiet --pinentry-mode loopback --passphrase ''"
+
+$gpg --quick-gen-key 'rpkg test RSA <rsa@example.org>' rsa2048 sign never
+$gpg --quick-gen-key 'rpkg test Ed25519 <ed25519@example.org>' ed25519 sign never
+$gpg --armor --export rsa@example.org > rsa.asc
+$gpg --armor --export ed25519@example.org > ed25519.asc
+$gpg --with-colons --list-keys rsa@example.org | awk -F: '$1 == "fpr" { print $10; exit }' > rsa.fpr
+$gpg --with-colons --list-keys ed25519@example.org | awk -F: '$1 == "fpr" { print $10; exit }' > ed25519.fpr
+
+$gpg --local-user rsa@example.org --digest-algo SHA256 --clearsign --output rsa.clearsigned message
+$gpg --local-user ed25519@example.org --digest-algo SHA512 --clearsign --output ed25519.clearsigned message
+$gpg --local-user rsa@example.org --detach-sign --output rsa.sig message
+$gpg --local-user ed25519@example.org --textmode --armor --detach-sign --output ed25519.sig.asc message
@@ -0,0 +1,52 @@
+#!/bin/sh
+# Regenerates the mirror tree used by the tests in src/packages/repo.rs:
+#   sid: main (binary-amd64 and binary-all) and contrib, with indexes in every compression format
+#   experimental: an InRelease file, which takes precedence over the Release file next to it
+#   stale: a binary-amd64 index that no longer matches its Release file
+set -e
+cd "$(dirname "$0")"
+rm -rf dists
+
+# index <dir> <name> <text>: writes <dir>/<name>, compressing it if name says so
+index() {
+    mkdir -p "$1"
+    case "$2" in
+        *.gz) printf "$3" | gzip -9n > "$1/$2" ;;
+        *.xz) printf "$3" | xz > "$1/$2" ;;
+        *) printf "$3" > "$1/$2" ;;
+    esac
+}
+
+# release <suite dir> <fields>: writes the fields and the hash tables of every index under the suite
+release() {
+    (
+        cd "$1"
+        printf "$2"
+        echo "MD5Sum:"
+        find . -path './*/binary-*/Packages*' | sort | while read -r f; do
+            echo " $(md5sum < "$f" | cut -d' ' -f1) $(wc -c < "$f") ${f#./}"
+        done
+        echo "SHA256:"
+        find . -path './*/binary-*/Packages*' | sort | while read -r f; do
+            echo " $(sha256sum < "$f" | cut -d' ' -f1) $(wc -c < "$f") ${f#./}"
+        done
+    )
+}
+
+# the uncompressed index beats Packages.gz, and Packages.xz beats Packages.gz
+index dists/sid/main/binary-amd64 Packages 'Package: app\nVersion: 1.0\nDepends: doc\n'
+index dists/sid/main/binary-amd64 Packages.gz 'Package: app\nVersion: 0.9\n'
+index dists/sid/main/binary-all Packages.xz 'Package: doc\nVersion: 1.0\n'
+index dists/sid/main/binary-all Packages.gz 'Package: doc\nVersion: 0.5\n'
+index dists/sid/contrib/binary-amd64 Packages.gz 'Package: tool\nVersion: 2.0\n'
+release dists/sid 'Origin: Debian\nLabel: Debian\nSuite: unstable\nCodename: sid\nArchitectures: amd64\nComponents: main contrib\n' > dists/sid/Release
+
+index dists/experimental/main/binary-amd64 Packages 'Package: app\nVersion: 2.0\n'
+release dists/experimental 'Origin: Debian\nLabel: Debian\nSuite: experimental\nCodename: rc-buggy\nNotAutomatic: yes\nArchitectures: amd64\nComponents: main\n' > dists/experimental/InRelease
+release dists/experimental 'Origin: Debian\nSuite: not-the-inrelease\nArchitectures: amd64\nComponents: main\n' > dists/experimental/Release
+
+index dists/stale/main/binary-amd64 Packages 'Package: app\nVersion: 1.0\n'
+index dists/stale/main/binary-all Packages 'Package: lib\nVersion: 1.0\n'
+release dists/stale 'Origin: Debian\nSuite: stale\nArchitectures: amd64\nComponents: main\n' > dists/stale/Release
+# same size, different contents
+index dists/stale/main/binary-amd64 Packages 'Package: app\nVersion: 1.1\n'